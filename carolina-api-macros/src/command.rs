@@ -0,0 +1,306 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    spanned::Spanned, Attribute, Data, DeriveInput, Expr, ExprLit, Fields, GenericArgument, Ident,
+    Lit, LitStr, Meta, PathArguments, Type,
+};
+
+use crate::plugin::api::camel_to_snake_case;
+
+#[derive(Default)]
+struct CommandAttrs {
+    name: Option<LitStr>,
+    aliases: Vec<LitStr>,
+    description: Option<String>,
+}
+
+#[derive(Default)]
+struct ArgAttrs {
+    name: Option<LitStr>,
+    rest: bool,
+    flag: bool,
+    description: Option<String>,
+}
+
+/// Joins doc comment lines, used as description if not given.
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+fn parse_command_attrs(attrs: &[Attribute]) -> syn::Result<CommandAttrs> {
+    let mut parsed = CommandAttrs {
+        description: doc_comment(attrs),
+        ..Default::default()
+    };
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("command")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                parsed.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("alias") {
+                parsed.aliases.push(meta.value()?.parse()?);
+            } else if meta.path.is_ident("description") {
+                parsed.description = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `name`, `alias` or `description`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+fn parse_arg_attrs(attrs: &[Attribute]) -> syn::Result<ArgAttrs> {
+    let mut parsed = ArgAttrs {
+        description: doc_comment(attrs),
+        ..Default::default()
+    };
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("arg")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                parsed.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("rest") {
+                parsed.rest = true;
+            } else if meta.path.is_ident("flag") {
+                parsed.flag = true;
+            } else if meta.path.is_ident("description") {
+                parsed.description = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `name`, `rest`, `flag` or `description`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+/// Inner type if the type is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let seg = path.path.segments.last()?;
+    if seg.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &seg.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+/// Whether the type is written as `bool`, e.g. `std::primitive::bool`. Aliases of `bool` are
+/// not recognized, such fields need `#[arg(flag)]`.
+fn is_bool(ty: &Type) -> bool {
+    is_named(ty, "bool")
+}
+
+/// Whether the type is written as `Rest`, e.g. `plugin::Rest`, such fields are rest arguments
+/// even without `#[arg(rest)]`.
+fn is_rest(ty: &Type) -> bool {
+    is_named(ty, "Rest")
+}
+
+fn is_named(ty: &Type, name: &str) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+    path.qself.is_none()
+        && path
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == name && seg.arguments.is_none())
+}
+
+/// Generates the spec expression and the constructor of `ctor` from `args`.
+fn command_tokens(
+    krate: &TokenStream,
+    default_name: &Ident,
+    attrs: &[Attribute],
+    fields: &Fields,
+    ctor: TokenStream,
+) -> syn::Result<(LitStr, TokenStream, TokenStream)> {
+    let command = parse_command_attrs(attrs)?;
+    let name = command.name.unwrap_or_else(|| {
+        LitStr::new(
+            &camel_to_snake_case(&default_name.to_string()),
+            default_name.span(),
+        )
+    });
+
+    let named = match fields {
+        Fields::Named(named) => named.named.iter().collect(),
+        Fields::Unit => vec![],
+        Fields::Unnamed(_) => {
+            return Err(syn::Error::new(
+                fields.span(),
+                "`Command` can only be derived for named fields or unit",
+            ))
+        }
+    };
+
+    let field_attrs = named
+        .iter()
+        .map(|field| parse_arg_attrs(&field.attrs))
+        .collect::<syn::Result<Vec<_>>>()?;
+    let is_flag = |i: usize| field_attrs[i].flag || is_bool(&named[i].ty);
+
+    let mut arg_specs = vec![];
+    let mut inits = vec![];
+    let mut has_rest = false;
+    for (i, (field, attrs)) in named.iter().zip(&field_attrs).enumerate() {
+        let ident = field.ident.as_ref().unwrap();
+        let arg_name = attrs.name.clone().unwrap_or_else(|| {
+            let name = ident.to_string();
+            LitStr::new(name.trim_start_matches("r#"), ident.span())
+        });
+        let ty = &field.ty;
+        let optional = option_inner(ty);
+        let rest = attrs.rest || is_rest(optional.unwrap_or(ty));
+
+        if rest {
+            if has_rest || (i + 1..named.len()).any(|i| !is_flag(i)) {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "`rest` argument must be the last positional argument",
+                ));
+            }
+            has_rest = true;
+        }
+
+        let (kind, init) = if is_flag(i) {
+            (
+                quote! { #krate::ArgKind::Flag },
+                quote! { args.flag(#arg_name) },
+            )
+        } else {
+            let value_ty = optional.unwrap_or(ty);
+            if !matches!(value_ty, Type::Path(_)) {
+                return Err(syn::Error::new_spanned(
+                    value_ty,
+                    "unsupported argument type, expected a type implementing `FromArg`",
+                ));
+            }
+            let kind = if rest {
+                quote! { #krate::ArgKind::Rest }
+            } else {
+                quote! { <#value_ty as #krate::FromArg>::KIND }
+            };
+            let init = match optional {
+                Some(_) => quote! { args.value::<#value_ty>(#arg_name)? },
+                None => quote! {
+                    args.value::<#value_ty>(#arg_name)?
+                        .ok_or_else(|| #krate::CommandError::MissingArg(#arg_name.to_owned()))?
+                },
+            };
+            (kind, init)
+        };
+
+        let optional_call = optional.map(|_| quote! { .optional() });
+        let description = attrs
+            .description
+            .as_ref()
+            .map(|d| quote! { .description(#d) });
+        arg_specs.push(quote! {
+            .arg(#krate::ArgSpec::new(#arg_name, #kind) #optional_call #description)
+        });
+        inits.push(quote! { #ident: #init });
+    }
+
+    let aliases = &command.aliases;
+    let description = command.description.map(|d| quote! { .description(#d) });
+    let spec = quote! {
+        #krate::CommandSpec::new(#name)
+            #(.alias(#aliases))*
+            #description
+            #(#arg_specs)*
+    };
+    let construct = match fields {
+        Fields::Unit => ctor,
+        _ => quote! { #ctor { #(#inits),* } },
+    };
+    Ok((name, spec, construct))
+}
+
+pub fn derive_command(input: DeriveInput) -> syn::Result<TokenStream> {
+    let krate = quote! { ::carolina_api::plugin };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (specs, from_args) = match &input.data {
+        Data::Struct(data) => {
+            let (_, spec, construct) =
+                command_tokens(&krate, ident, &input.attrs, &data.fields, quote! { Self })?;
+            (
+                quote! { ::std::vec![#spec] },
+                quote! { ::std::result::Result::Ok(#construct) },
+            )
+        }
+        Data::Enum(data) => {
+            let mut specs = vec![];
+            let mut arms = vec![];
+            for variant in &data.variants {
+                let var_ident = &variant.ident;
+                let (name, spec, construct) = command_tokens(
+                    &krate,
+                    var_ident,
+                    &variant.attrs,
+                    &variant.fields,
+                    quote! { Self::#var_ident },
+                )?;
+                specs.push(spec);
+                arms.push(quote! { #name => ::std::result::Result::Ok(#construct), });
+            }
+            (
+                quote! { ::std::vec![#(#specs),*] },
+                quote! {
+                    match name {
+                        #(#arms)*
+                        _ => ::std::result::Result::Err(
+                            #krate::CommandError::UnknownCommand(name.to_owned()),
+                        ),
+                    }
+                },
+            )
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span,
+                "`Command` can not be derived for union",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::Command for #ident #ty_generics #where_clause {
+            fn specs() -> ::std::vec::Vec<#krate::CommandSpec> {
+                #specs
+            }
+
+            #[allow(unused_variables)]
+            fn from_args(
+                name: &str,
+                args: &#krate::ParsedArgs,
+            ) -> ::std::result::Result<Self, #krate::CommandError> {
+                #from_args
+            }
+        }
+    })
+}
@@ -0,0 +1,272 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{
+    visit_mut::{self, VisitMut},
+    FnArg, GenericParam, Ident, ItemTrait, PatType, ReturnType, Signature, TraitItem, Type,
+    TypeParamBound,
+};
+
+use crate::plugin::api::future_output;
+
+/// Replaces generic parameters with their dyn types.
+struct ReplaceParams<'a> {
+    params: &'a [(Ident, Type)],
+    replaced: bool,
+}
+
+impl VisitMut for ReplaceParams<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty {
+            if path.qself.is_none() {
+                let found = self
+                    .params
+                    .iter()
+                    .find(|(param, _)| path.path.is_ident(param));
+                if let Some((_, dyn_ty)) = found {
+                    *ty = dyn_ty.clone();
+                    self.replaced = true;
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
+}
+
+/// Receiver of a method, how it is passed to the dyn trait.
+enum Receiver {
+    Ref,
+    Mut,
+    Owned,
+}
+
+struct DynMethod {
+    sig: Signature,
+    receiver: Receiver,
+    /// Dyn signature of the method.
+    dyn_sig: Signature,
+    /// Argument names, and whether they are converted by `IntoDyn`.
+    args: Vec<(TokenStream, bool)>,
+    returns_future: bool,
+    attrs: Vec<syn::Attribute>,
+}
+
+fn dyn_method(func: &syn::TraitItemFn, params: &[(Ident, Type)]) -> syn::Result<Option<DynMethod>> {
+    let sig = &func.sig;
+    let Some(receiver) = sig.receiver() else {
+        if func.default.is_none() {
+            return Err(syn::Error::new_spanned(
+                &sig.ident,
+                "method without `self` receiver must have a default implementation to generate dyn trait",
+            ));
+        }
+        return Ok(None);
+    };
+    if receiver.colon_token.is_some() {
+        return Err(syn::Error::new_spanned(
+            receiver,
+            "typed `self` receiver is not supported to generate dyn trait",
+        ));
+    }
+    if sig.asyncness.is_some() {
+        return Err(syn::Error::new_spanned(
+            sig.asyncness,
+            "use `fn .. -> impl Future<Output = ..> + Send` instead of `async fn` to generate dyn trait",
+        ));
+    }
+    let receiver_kind = match (&receiver.reference, &receiver.mutability) {
+        (Some(_), Some(_)) => Receiver::Mut,
+        (Some(_), None) => Receiver::Ref,
+        (None, _) => Receiver::Owned,
+    };
+
+    let mut dyn_sig = sig.clone();
+    dyn_sig.generics.params = Default::default();
+    dyn_sig.generics.where_clause = None;
+    for param in &sig.generics.params {
+        match param {
+            GenericParam::Lifetime(_) => dyn_sig.generics.params.push(param.clone()),
+            GenericParam::Type(ty) if params.iter().any(|(p, _)| *p == ty.ident) => {}
+            GenericParam::Type(ty) => {
+                return Err(syn::Error::new_spanned(
+                    &ty.ident,
+                    format!(
+                        "generic parameter `{}` needs a `dyn_param` mapping to generate dyn trait",
+                        ty.ident
+                    ),
+                ))
+            }
+            GenericParam::Const(param) => {
+                return Err(syn::Error::new_spanned(
+                    param,
+                    "const generic parameter is not supported to generate dyn trait",
+                ))
+            }
+        }
+    }
+
+    let mut args = vec![];
+    for input in dyn_sig.inputs.iter_mut() {
+        match input {
+            FnArg::Receiver(receiver) => {
+                if let Receiver::Owned = receiver_kind {
+                    *receiver = syn::parse_quote! { self: ::std::boxed::Box<Self> };
+                }
+            }
+            FnArg::Typed(PatType { pat, ty, .. }) => {
+                let syn::Pat::Ident(pat_ident) = &**pat else {
+                    return Err(syn::Error::new_spanned(
+                        pat,
+                        "argument must be a plain identifier to generate dyn trait",
+                    ));
+                };
+                let mut replace = ReplaceParams {
+                    params,
+                    replaced: false,
+                };
+                replace.visit_type_mut(ty);
+                let name = &pat_ident.ident;
+                args.push((quote! { #name }, replace.replaced));
+            }
+        }
+    }
+
+    let mut returns_future = false;
+    if let ReturnType::Type(_, ty) = &mut dyn_sig.output {
+        if let Type::ImplTrait(impl_trait) = &**ty {
+            if future_output(ty).is_none() {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "only `impl Future` can be returned to generate dyn trait",
+                ));
+            }
+            let bounds = &impl_trait.bounds;
+            let has_lifetime = bounds
+                .iter()
+                .any(|bound| matches!(bound, TypeParamBound::Lifetime(_)));
+            let static_bound = (!has_lifetime).then(|| quote! { + 'static });
+            **ty = syn::parse_quote! {
+                ::std::pin::Pin<::std::boxed::Box<dyn #bounds #static_bound>>
+            };
+            returns_future = true;
+        }
+        ReplaceParams {
+            params,
+            replaced: false,
+        }
+        .visit_type_mut(ty);
+    }
+
+    let attrs = func
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .cloned()
+        .collect();
+
+    Ok(Some(DynMethod {
+        sig: sig.clone(),
+        receiver: receiver_kind,
+        dyn_sig,
+        args,
+        returns_future,
+        attrs,
+    }))
+}
+
+/// Generates the dyn-compatible trait of `trait_`, with boxed futures, and the bridging impls:
+/// the dyn trait for every `T: Trait`, and the trait for `Box<dyn DynTrait>`.
+///
+/// Generic parameters of methods are replaced with types in `params`, arguments are converted
+/// by `IntoDyn` when calling the dyn trait.
+pub(crate) fn generate_dyn(
+    trait_: &ItemTrait,
+    dyn_name: &Ident,
+    params: &[(Ident, Type)],
+) -> syn::Result<TokenStream> {
+    if !trait_.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &trait_.generics,
+            "generic trait is not supported to generate dyn trait",
+        ));
+    }
+
+    let trait_name = &trait_.ident;
+    let vis = &trait_.vis;
+    let supertraits = &trait_.supertraits;
+    let plus = (!supertraits.is_empty()).then(|| quote! { + });
+
+    let mut methods = vec![];
+    for item in &trait_.items {
+        if let TraitItem::Fn(func) = item {
+            methods.extend(dyn_method(func, params)?);
+        }
+    }
+
+    let dyn_fns = methods.iter().map(|m| {
+        let attrs = &m.attrs;
+        let dyn_sig = &m.dyn_sig;
+        quote! {
+            #(#attrs)*
+            #dyn_sig;
+        }
+    });
+
+    let blanket_fns = methods.iter().map(|m| {
+        let dyn_sig = &m.dyn_sig;
+        let ident = &m.sig.ident;
+        let receiver = match m.receiver {
+            Receiver::Owned => quote! { *self },
+            _ => quote! { self },
+        };
+        let args = m.args.iter().map(|(arg, _)| arg);
+        let call = quote! { <T as #trait_name>::#ident(#receiver, #(#args),*) };
+        let body = if m.returns_future {
+            quote! { ::std::boxed::Box::pin(#call) }
+        } else {
+            call
+        };
+        quote! {
+            #dyn_sig {
+                #body
+            }
+        }
+    });
+
+    let reverse_fns = methods.iter().map(|m| {
+        let sig = &m.sig;
+        let ident = &sig.ident;
+        let receiver = match m.receiver {
+            Receiver::Ref => quote! { &**self },
+            Receiver::Mut => quote! { &mut **self },
+            Receiver::Owned => quote! { self },
+        };
+        let args = m.args.iter().map(|(arg, convert)| {
+            if *convert {
+                quote! { ::carolina_api::IntoDyn::into_dyn(#arg) }
+            } else {
+                arg.to_token_stream()
+            }
+        });
+        quote! {
+            #sig {
+                #dyn_name::#ident(#receiver, #(#args),*)
+            }
+        }
+    });
+
+    Ok(quote! {
+        #vis trait #dyn_name: #supertraits #plus 'static {
+            #(#dyn_fns)*
+        }
+
+        impl<T: #trait_name + 'static> #dyn_name for T {
+            #(#blanket_fns)*
+        }
+
+        // For dynamic dispatching
+        impl #trait_name for ::std::boxed::Box<dyn #dyn_name> {
+            #(#reverse_fns)*
+        }
+    })
+}
@@ -1,13 +1,35 @@
 use proc_macro::TokenStream;
-use syn::{parse_macro_input, punctuated::Punctuated, ItemMod, Meta};
+use syn::{parse_macro_input, punctuated::Punctuated, DeriveInput, ItemMod, Meta};
 
+mod command;
+mod dyn_gen;
 mod plugin;
 
-/// Generate plugin api macros for the trait in the module.
+/// Generate plugin api macros for the traits in the module.
 ///
 /// Imported types in the module will be provided when using macros to generate static dispatching
 /// enum, and the trait will be exported.
-/// Use **absolute module path** when importing.
+/// Imports are rewritten for the host crate, `crate::` paths become `$crate::`; `super::` paths
+/// can not be resolved there and are rejected.
+///
+/// Methods listed in `ignore(..)` or marked `#[dispatch(skip)]` are not forwarded by the
+/// dispatching enum, which uses the trait default instead. `#[dispatch(boxed)]` boxes the future
+/// of a forwarded method, keeping the enum's futures small.
+///
+/// With `gen_dyn`, the dyn-compatible trait named by `dyn_t` is generated with boxed futures,
+/// along with impls of it for every plugin and of the trait for its boxed trait object.
+/// Generic parameters of methods are replaced by types given in `dyn_param(G = Type, ..)`, and
+/// arguments are converted by `carolina_api::IntoDyn`.
+///
+/// A module may declare several traits, each taking its arguments by `#[plugin_api(..)]` on the
+/// trait instead. Every trait gets its own `export_<trait>!`, `define_dispatcher_<trait>!`,
+/// `<TRAIT>_DYN_LOADER_FN_NAME` and `<Trait>DynLoader`, so a plugin can export more than one.
+/// A module with a single trait also gets the unsuffixed `export_plugin!`, `DYN_LOADER_FN_NAME`
+/// and `DynPluginLoader`.
+///
+/// With feature `registry` of `carolina_api`, exported plugins of all linked crates are also
+/// registered at link time, and can be iterated or loaded by `<Trait>Entry` without listing
+/// their crates.
 #[proc_macro_attribute]
 pub fn plugin_api(attr: TokenStream, input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as ItemMod);
@@ -18,6 +40,25 @@ pub fn plugin_api(attr: TokenStream, input: TokenStream) -> TokenStream {
         .into()
 }
 
+/// Derive `carolina_api::plugin::Command` for typed command arguments.
+///
+/// A struct is one command, and each variant of an enum is a command. Commands are named after
+/// the type or variant in snake case, fields are arguments in declaration order.
+///
+/// - `#[command(name = "..", alias = "..", description = "..")]` on the type or variants,
+///   doc comments are used as description if not given.
+/// - `#[arg(name = "..", description = "..")]` on fields, `Option<T>` fields are optional.
+/// - `#[arg(rest)]` takes all remaining text, `bool` fields or `#[arg(flag)]` are `--name` flags.
+///   Fields are recognized as `bool` by the last path segment, aliases need `#[arg(flag)]`.
+#[proc_macro_derive(Command, attributes(command, arg))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    command::derive_command(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
 #[doc(hidden)]
 #[proc_macro]
 pub fn __generate_enum(input: TokenStream) -> TokenStream {
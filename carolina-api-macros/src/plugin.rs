@@ -6,7 +6,8 @@ pub(crate) mod api {
     use quote::{quote, ToTokens};
     use syn::{
         parenthesized, punctuated::Punctuated, token::RArrow, Expr, Ident, ItemMod, ItemTrait,
-        LitByteStr, Meta, PatType, Path, Signature, Token, TraitItem, UsePath,
+        ItemUse, LitByteStr, Meta, PatType, Path, Signature, Token, TraitItem, TraitItemFn,
+        UseTree,
     };
 
     pub static EXPORT_FN_HASH: &str =
@@ -23,10 +24,142 @@ pub(crate) mod api {
         result
     }
 
+    /// How a trait method is forwarded by the dispatcher enum.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum DispatchMode {
+        Forward,
+        /// Boxes the returned future, keeping the dispatcher's future small.
+        Boxed,
+        /// Not forwarded, falling back to the trait default.
+        Skip,
+    }
+
+    fn dispatch_mode(func: &TraitItemFn, ignored: &HashSet<Ident>) -> syn::Result<DispatchMode> {
+        let mut mode = if ignored.contains(&func.sig.ident) {
+            DispatchMode::Skip
+        } else {
+            DispatchMode::Forward
+        };
+        for attr in func
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("dispatch"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    mode = DispatchMode::Skip;
+                } else if meta.path.is_ident("boxed") {
+                    mode = DispatchMode::Boxed;
+                } else {
+                    return Err(meta.error("expected `skip` or `boxed`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(mode)
+    }
+
+    /// Output type if the type is `impl Future<Output = T>`.
+    pub(crate) fn future_output(ty: &syn::Type) -> Option<syn::Type> {
+        let syn::Type::ImplTrait(impl_trait) = ty else {
+            return None;
+        };
+        for bound in &impl_trait.bounds {
+            let syn::TypeParamBound::Trait(ty) = bound else {
+                continue;
+            };
+            let path_seg = ty.path.segments.last()?;
+            if path_seg.ident != "Future" {
+                continue;
+            }
+            let syn::PathArguments::AngleBracketed(arg) = &path_seg.arguments else {
+                continue;
+            };
+            for ele in &arg.args {
+                let syn::GenericArgument::AssocType(ty) = ele else {
+                    continue;
+                };
+                if ty.ident == "Output" {
+                    return Some(ty.ty.clone());
+                }
+            }
+        }
+        None
+    }
+
+    fn is_future_fn(sig: &Signature) -> bool {
+        sig.asyncness.is_some()
+            || matches!(&sig.output, syn::ReturnType::Type(_, ty) if future_output(ty).is_some())
+    }
+
+    /// Checks that the method can be forwarded, errors point at the offending part.
+    fn check_forwardable(func: &TraitItemFn, mode: DispatchMode) -> syn::Result<()> {
+        let sig = &func.sig;
+        if mode == DispatchMode::Skip {
+            if func.default.is_none() {
+                return Err(syn::Error::new_spanned(
+                    &sig.ident,
+                    "skipped method must have a default implementation",
+                ));
+            }
+            return Ok(());
+        }
+
+        let hint = "consider `#[dispatch(skip)]`";
+        match sig.receiver() {
+            None => {
+                return Err(syn::Error::new_spanned(
+                    &sig.ident,
+                    format!("method without `self` receiver can not be dispatched, {hint}"),
+                ))
+            }
+            Some(receiver) if receiver.colon_token.is_some() => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    format!("typed `self` receiver can not be dispatched, {hint}"),
+                ))
+            }
+            Some(_) => {}
+        }
+        if let Some(variadic) = &sig.variadic {
+            return Err(syn::Error::new_spanned(
+                variadic,
+                "variadic method can not be dispatched",
+            ));
+        }
+        for arg in &sig.inputs {
+            if let syn::FnArg::Typed(PatType { pat, .. }) = arg {
+                if !matches!(&**pat, syn::Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none())
+                {
+                    return Err(syn::Error::new_spanned(
+                        pat,
+                        format!("argument must be a plain identifier to be forwarded, {hint}"),
+                    ));
+                }
+            }
+        }
+        if let syn::ReturnType::Type(_, ty) = &sig.output {
+            if matches!(&**ty, syn::Type::ImplTrait(_)) && future_output(ty).is_none() {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    format!("only `impl Future` can be returned from dispatched method, {hint}"),
+                ));
+            }
+        }
+        if mode == DispatchMode::Boxed && !is_future_fn(sig) {
+            return Err(syn::Error::new_spanned(
+                &sig.ident,
+                "`boxed` requires the method to be async or return `impl Future`",
+            ));
+        }
+        Ok(())
+    }
+
     fn generate_dis_fn(
         trait_: &Path,
         enum_name: &Ident,
         sig: &Signature,
+        boxed: bool,
         vars: &[Ident],
     ) -> syn::Result<proc_macro2::TokenStream> {
         let ident = &sig.ident;
@@ -40,33 +173,16 @@ pub(crate) mod api {
             })
             .collect();
 
-        let mut future_output = None;
-        if let syn::ReturnType::Type(_, ty) = &sig.output {
-            if let syn::Type::ImplTrait(impl_trait) = &**ty {
-                for bound in &impl_trait.bounds {
-                    let syn::TypeParamBound::Trait(ty) = bound else {
-                        continue;
-                    };
-                    let path_seg = ty.path.segments.first().unwrap().clone();
-                    if path_seg.ident != "Future" {
-                        continue;
-                    }
-                    let syn::PathArguments::AngleBracketed(arg) = path_seg.arguments else {
-                        continue;
-                    };
-                    for ele in arg.args {
-                        let syn::GenericArgument::AssocType(ty) = ele else {
-                            continue;
-                        };
-                        if ty.ident == "Output" {
-                            future_output = Some(ty.ty);
-                        }
-                    }
-                }
-            }
-        }
+        let future_output = match &sig.output {
+            syn::ReturnType::Type(_, ty) => future_output(ty),
+            syn::ReturnType::Default => None,
+        };
 
-        let handle_tokens = if sig.asyncness.is_some() || future_output.is_some() {
+        let handle_tokens = if boxed {
+            quote! {
+                ::std::boxed::Box::pin(#trait_::#ident(plug, #(#args),* )).await
+            }
+        } else if sig.asyncness.is_some() || future_output.is_some() {
             quote! {
                 #trait_::#ident(plug, #(#args),* ).await
             }
@@ -98,20 +214,23 @@ pub(crate) mod api {
         })
     }
 
+    /// Names generated for a trait, `legacy` also generates the unsuffixed names kept for
+    /// modules with a single trait.
     fn make_macro(
         trait_data: &ItemTrait,
+        funcs: &[(Signature, DispatchMode)],
         dyn_ty: Option<Path>,
         dyn_wrap_ty: Option<Path>,
         inner_tt: &TokenStream,
+        legacy: bool,
     ) -> syn::Result<proc_macro2::TokenStream> {
         let trait_name = &trait_data.ident;
+        let trait_vis = &trait_data.vis;
         let name_snake = camel_to_snake_case(&trait_name.to_string());
-        let funcs = trait_data.items.iter().filter_map(|r| {
-            if let TraitItem::Fn(func) = r {
-                Some(func.sig.clone())
-            } else {
-                None
-            }
+        let funcs = funcs.iter().filter_map(|(sig, mode)| match mode {
+            DispatchMode::Forward => Some(quote! { #sig }),
+            DispatchMode::Boxed => Some(quote! { #[boxed] #sig }),
+            DispatchMode::Skip => None,
         });
 
         let dyn_ty = dyn_ty.unwrap_or_else(|| trait_name.clone().into());
@@ -123,47 +242,93 @@ pub(crate) mod api {
             &format!("__{EXPORT_FN_HASH}_make_dyn_{name_snake}"),
             call_site,
         );
-        let dyn_wrap_tokens = match dyn_wrap_ty {
-            Some(ty) => quote! { #ty::new(<$plug as Default>::default()) },
-            None => quote! { <$plug as Default>::default() },
+        let exported_ident = Ident::new(&format!("__Exported{trait_name}"), call_site);
+        let entry_ident = Ident::new(&format!("{trait_name}Entry"), call_site);
+        let submit_ident = Ident::new(&format!("__submit_{name_snake}"), call_site);
+        let dyn_wrap = |plug: proc_macro2::TokenStream| match &dyn_wrap_ty {
+            Some(ty) => quote! { #ty::new(#plug) },
+            None => plug,
         };
+        let dyn_wrap_tokens = dyn_wrap(quote! { <$plug as ::std::default::Default>::default() });
+        let into_dyn_tokens = dyn_wrap(quote! {
+            *plug
+                .downcast::<$plug>()
+                .expect("registry entry converts its own plugin type")
+        });
 
-        let export_plug_macro = quote! {
-            /// Export plugin struct.
-            #[macro_export]
-            macro_rules! export_plugin {
-                ($plug:ty) => {
-                    #[doc(hidden)]
-                    pub fn #cmptime_fn_ident() -> $plug {
-                        <$plug as Default>::default()
-                    }
+        let export_macro_name = Ident::new(&format!("export_{name_snake}"), call_site);
+        // Legacy `export_plugin!` repeats the rules, since macro-expanded exported macros can not
+        // be called by `$crate` paths in the same crate.
+        let export_rules = quote! {
+            ($plug:ty) => {
+                #[doc(hidden)]
+                pub fn #cmptime_fn_ident() -> $plug {
+                    <$plug as ::std::default::Default>::default()
+                }
 
-                    #[cfg(feature = "dyplugin")]
-                    #[doc(hidden)]
-                    #[no_mangle]
-                    pub extern "Rust" fn #dyn_fn_ident() -> Box<dyn #dyn_ty_macro> {
-                        Box::new(#dyn_wrap_tokens)
-                    }
+                #[cfg(feature = "dyplugin")]
+                #[doc(hidden)]
+                #[no_mangle]
+                pub extern "Rust" fn #dyn_fn_ident() -> ::std::boxed::Box<dyn #dyn_ty_macro> {
+                    ::std::boxed::Box::new(#dyn_wrap_tokens)
+                }
 
-                    #[doc(hidden)]
-                    pub type __ExportedPlugin = $plug;
-                };
-            }
+                #[doc(hidden)]
+                pub type #exported_ident = $plug;
 
+                $crate::#submit_ident! {
+                    $crate::#entry_ident::new(
+                        module_path!(),
+                        stringify!($plug),
+                        || ::std::boxed::Box::new(<$plug as ::std::default::Default>::default()),
+                        |plug| ::std::boxed::Box::new(#into_dyn_tokens),
+                    )
+                }
+            };
+        };
+        let export_plug_macro = quote! {
+            #[doc = concat!("Export plugin struct implementing `", stringify!(#trait_name), "`.")]
+            #[macro_export]
+            macro_rules! #export_macro_name {
+                #export_rules
+            }
         };
 
         let static_name_dyn = LitByteStr::new(dyn_fn_ident.to_string().as_bytes(), call_site);
+        let static_name_ident = Ident::new(
+            &format!("{}_DYN_LOADER_FN_NAME", name_snake.to_uppercase()),
+            call_site,
+        );
+        let loader_ident = Ident::new(&format!("{trait_name}DynLoader"), call_site);
         let dispatcher_macro_name =
             Ident::new(&format!("define_dispatcher_{name_snake}"), call_site);
+        let dispatcher_mod = Ident::new(&format!("__{name_snake}_dispatcher"), call_site);
         let load_plugin_name = Ident::new(&format!("load_cmptime_{name_snake}"), call_site);
 
+        let legacy_tokens = legacy.then(|| {
+            quote! {
+                /// Static name for the dynamic plugin loader function.
+                pub static DYN_LOADER_FN_NAME: &'static [u8] = #static_name_ident;
+                /// Dynamic plugin loader entry.
+                pub type DynPluginLoader = #loader_ident;
+
+                /// Export plugin struct.
+                #[macro_export]
+                macro_rules! export_plugin {
+                    #export_rules
+                }
+            }
+        });
+
         Ok(quote! {
-            /// Static name for the dynamic plugin loader function.
-            pub static DYN_LOADER_FN_NAME: &'static [u8] = #static_name_dyn;
-            /// Dynamic plugin loader entry.
-            pub type DynPluginLoader = extern "Rust" fn() -> Box<dyn #dyn_ty>;
+            #[doc = concat!("Static name for the dynamic loader function of `", stringify!(#trait_name), "`.")]
+            pub static #static_name_ident: &'static [u8] = #static_name_dyn;
+            #[doc = concat!("Dynamic loader entry of `", stringify!(#trait_name), "`.")]
+            pub type #loader_ident = extern "Rust" fn() -> ::std::boxed::Box<dyn #dyn_ty>;
 
-            pub use carolina_api_macros::__generate_enum;
+            ::carolina_api::__plugin_registry!(#trait_vis #entry_ident, dyn #dyn_ty);
+            #[doc(hidden)]
+            pub use ::carolina_api::__submit_plugin as #submit_ident;
 
             /// Generated macro for plugin system to create static dispatching enum.
             /// **DO NOT** use this in **PLUGIN** environment!
@@ -172,20 +337,21 @@ pub(crate) mod api {
                 ($vis:vis $e_name:ident( $($plug_crate:ident),* )) => {
 
                     #[doc(hidden)]
-                    mod __plugin_dispatcher {
+                    #[allow(unused_imports)]
+                    mod #dispatcher_mod {
                         use super::*;
-                        use #dyn_ty_macro as DynTy;
-                        use $crate::#trait_name as Trait;
+                        use #dyn_ty_macro as __DynTy;
+                        use $crate::#trait_name as __Trait;
                         #inner_tt
 
                         $crate::__generate_enum!(
-                            $vis $e_name Trait DynTy (
+                            $vis $e_name __Trait __DynTy #exported_ident (
                                 $($plug_crate),*
                             ) ( #(#funcs);* )
                         );
                     }
 
-                    pub use __plugin_dispatcher::*;
+                    pub use #dispatcher_mod::*;
                 };
             }
 
@@ -199,11 +365,79 @@ pub(crate) mod api {
             }
 
             #export_plug_macro
+            #legacy_tokens
         })
     }
 
-    /// Extrat module, return trait, other module inner tokens, and tokens for macro inner.
-    fn extract_mod(module: &ItemMod) -> syn::Result<(ItemTrait, TokenStream)> {
+    /// Rewrites an import of the module for the dispatcher module, which is expanded in the host
+    /// crate.
+    ///
+    /// `crate` paths become `$crate`, `self` paths refer to items copied along with imports, and
+    /// other paths are extern crates. `super` can not be resolved there and is rejected.
+    fn rewrite_use(item: &ItemUse) -> syn::Result<TokenStream> {
+        let ItemUse {
+            attrs,
+            vis,
+            leading_colon,
+            tree,
+            ..
+        } = item;
+        let tree = match leading_colon {
+            Some(_) => tree.to_token_stream(),
+            None => rewrite_use_tree(tree)?,
+        };
+        Ok(quote! { #(#attrs)* #vis use #leading_colon #tree; })
+    }
+
+    fn rewrite_use_tree(tree: &UseTree) -> syn::Result<TokenStream> {
+        let unresolved = |ident: &Ident| {
+            syn::Error::new_spanned(
+                ident,
+                format!("`{ident}` can not be resolved in the generated dispatcher, use a `crate::` path instead"),
+            )
+        };
+        match tree {
+            UseTree::Path(path) if path.ident == "crate" => {
+                let tree = &path.tree;
+                Ok(quote! { $crate::#tree })
+            }
+            UseTree::Path(path) if path.ident == "super" => Err(unresolved(&path.ident)),
+            UseTree::Name(name)
+                if matches!(name.ident.to_string().as_str(), "crate" | "self" | "super") =>
+            {
+                Err(syn::Error::new_spanned(
+                    &name.ident,
+                    format!(
+                        "`{}` must be imported with a name, e.g. `use {0} as name;`",
+                        name.ident
+                    ),
+                ))
+            }
+            UseTree::Rename(rename) if rename.ident == "crate" => {
+                let name = &rename.rename;
+                Ok(quote! { $crate as #name })
+            }
+            UseTree::Rename(rename) if rename.ident == "super" || rename.ident == "self" => {
+                Err(unresolved(&rename.ident))
+            }
+            UseTree::Glob(glob) => Err(syn::Error::new_spanned(
+                glob,
+                "glob import needs a path, e.g. `use crate::module::*;`",
+            )),
+            UseTree::Group(group) => {
+                let items = group
+                    .items
+                    .iter()
+                    .map(rewrite_use_tree)
+                    .collect::<syn::Result<Vec<_>>>()?;
+                Ok(quote! { { #(#items),* } })
+            }
+            UseTree::Path(_) | UseTree::Name(_) | UseTree::Rename(_) => Ok(tree.to_token_stream()),
+        }
+    }
+
+    /// Extrat module, return traits, other module inner tokens, and tokens for macro inner.
+    fn extract_mod(module: &ItemMod) -> syn::Result<(Vec<ItemTrait>, TokenStream)> {
         use syn::Item;
 
         let Some((_, items)) = module.content.as_ref() else {
@@ -211,42 +445,35 @@ pub(crate) mod api {
         };
 
         let mut tt = TokenStream::new();
-        let mut target = None;
+        let mut targets = vec![];
         for ele in items {
             match ele {
-                Item::Trait(item_trait) => {
-                    if target.is_some() {
-                        return Err(syn::Error::new_spanned(ele, "more than one trait received"));
-                    }
-                    target = Some(item_trait);
-                }
-                Item::Use(item) => {
-                    let mut item = item.clone();
-                    if let syn::UseTree::Path(UsePath {
-                        ident,
-                        colon2_token: _,
-                        tree,
-                    }) = &mut item.tree
-                    {
-                        let tokens = if ident == "crate" {
-                            quote! { use $crate::#tree; }
-                        } else {
-                            quote! { use #ident::#tree; }
-                        };
-                        tokens.to_tokens(&mut tt);
-                    } else {
-                        item.to_tokens(&mut tt);
-                    };
-                }
+                Item::Trait(item_trait) => targets.push(item_trait.clone()),
+                Item::Use(item) => rewrite_use(item)?.to_tokens(&mut tt),
                 tokens => tokens.to_tokens(&mut tt),
             }
         }
 
-        let Some(target) = target else {
+        if targets.is_empty() {
             return Err(syn::Error::new_spanned(module, "missing trait"));
-        };
+        }
 
-        Ok((target.clone(), tt))
+        Ok((targets, tt))
+    }
+
+    /// Forwarded method, `#[boxed]` boxes its future.
+    struct DisFn {
+        boxed: bool,
+        sig: Signature,
+    }
+    impl syn::parse::Parse for DisFn {
+        fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+            let attrs = input.call(syn::Attribute::parse_outer)?;
+            Ok(Self {
+                boxed: attrs.iter().any(|attr| attr.path().is_ident("boxed")),
+                sig: input.parse()?,
+            })
+        }
     }
 
     struct EnumGen {
@@ -254,8 +481,9 @@ pub(crate) mod api {
         name: Ident,
         trait_: Path,
         dyn_ty: Path,
+        exported: Ident,
         items: Vec<Ident>,
-        funcs: Vec<syn::Signature>,
+        funcs: Vec<DisFn>,
     }
     impl syn::parse::Parse for EnumGen {
         fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
@@ -263,30 +491,44 @@ pub(crate) mod api {
             let name = input.parse()?;
             let trait_ = input.parse()?;
             let dyn_ty = input.parse()?;
+            let exported = input.parse()?;
 
             let var_tokens;
             parenthesized!(var_tokens in input);
             let func_tokens;
             parenthesized!(func_tokens in input);
             let paths = Punctuated::<Ident, Token![,]>::parse_terminated(&var_tokens)?;
-            let funcs = Punctuated::<syn::Signature, Token![;]>::parse_terminated(&func_tokens)?;
+            let funcs = Punctuated::<DisFn, Token![;]>::parse_terminated(&func_tokens)?;
             Ok(Self {
                 vis,
                 name,
                 trait_,
                 dyn_ty,
+                exported,
                 items: paths.into_iter().collect(),
                 funcs: funcs.into_iter().collect(),
             })
         }
     }
 
+    /// `fn info(&self) -> PluginInfo`, whose id is shown by the enum's `Debug`.
+    fn is_info_fn(sig: &Signature) -> bool {
+        sig.ident == "info"
+            && sig.asyncness.is_none()
+            && sig.inputs.len() == 1
+            && matches!(sig.receiver(), Some(r) if r.reference.is_some() && r.mutability.is_none())
+            && matches!(&sig.output, syn::ReturnType::Type(_, ty)
+                if matches!(&**ty, syn::Type::Path(path)
+                    if path.path.segments.last().is_some_and(|seg| seg.ident == "PluginInfo")))
+    }
+
     pub(crate) fn generate_enum(input: TokenStream) -> syn::Result<TokenStream> {
         let EnumGen {
             vis,
             name,
             trait_,
             dyn_ty,
+            exported,
             items,
             funcs,
         } = syn::parse2(input)?;
@@ -296,90 +538,313 @@ pub(crate) mod api {
             .map(|item| Ident::new(&item.to_string().to_uppercase(), Span::call_site()))
             .collect();
 
+        let has_info = funcs.iter().any(|func| is_info_fn(&func.sig));
         let funcs = funcs
             .into_iter()
-            .map(|sig| generate_dis_fn(&trait_, &name, &sig, &var_names))
+            .map(|func| generate_dis_fn(&trait_, &name, &func.sig, func.boxed, &var_names))
             .collect::<Result<Vec<_>, _>>()?;
 
+        let var_strs: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+        let debug_tokens = if has_info {
+            quote! {
+                f.debug_tuple(self.variant_name())
+                    .field(&#trait_::info(self).id)
+                    .finish()
+            }
+        } else {
+            quote! { f.write_str(self.variant_name()) }
+        };
+
         let expanded = quote! {
              #vis enum #name {
-                #(#var_names(::#items::__ExportedPlugin),)*
-                DynPlugin(Box<dyn #dyn_ty>),
+                #(#var_names(::#items::#exported),)*
+                DynPlugin(::std::boxed::Box<dyn #dyn_ty>),
             }
 
             #(
-                impl From<::#items::__ExportedPlugin> for #name {
-                    fn from(plug: ::#items::__ExportedPlugin) -> Self {
+                impl ::std::convert::From<::#items::#exported> for #name {
+                    fn from(plug: ::#items::#exported) -> Self {
                         Self::#var_names(plug)
                     }
                 }
             )*
 
-            impl From<Box<dyn #dyn_ty>> for #name {
-                fn from(plug: Box<dyn #dyn_ty>) -> Self {
+            impl ::std::convert::From<::std::boxed::Box<dyn #dyn_ty>> for #name {
+                fn from(plug: ::std::boxed::Box<dyn #dyn_ty>) -> Self {
                     Self::DynPlugin(plug)
                 }
             }
 
+            /// Takes a compile-time plugin of one of the variants, e.g. from the link-time
+            /// registry, other types are returned back.
+            impl ::std::convert::TryFrom<::std::boxed::Box<dyn ::std::any::Any>> for #name {
+                type Error = ::std::boxed::Box<dyn ::std::any::Any>;
+
+                fn try_from(
+                    plug: ::std::boxed::Box<dyn ::std::any::Any>,
+                ) -> ::std::result::Result<Self, Self::Error> {
+                    #(
+                        let plug = match plug.downcast::<::#items::#exported>() {
+                            ::std::result::Result::Ok(plug) => {
+                                return ::std::result::Result::Ok(Self::#var_names(*plug))
+                            }
+                            ::std::result::Result::Err(plug) => plug,
+                        };
+                    )*
+                    ::std::result::Result::Err(plug)
+                }
+            }
+
             impl #trait_ for #name {
                 #(#funcs)*
             }
+
+            #[allow(dead_code)]
+            impl #name {
+                /// Crates of compile-time plugins, in the order of variants.
+                #vis const STATIC_VARIANTS: &'static [&'static str] = &[#(#var_strs),*];
+
+                /// Crate of the compile-time plugin, or `"DynPlugin"`.
+                #vis fn variant_name(&self) -> &'static str {
+                    match self {
+                        #(Self::#var_names(_) => #var_strs,)*
+                        Self::DynPlugin(_) => "DynPlugin",
+                    }
+                }
+
+                #vis fn is_dynamic(&self) -> bool {
+                    ::std::matches!(self, Self::DynPlugin(_))
+                }
+
+                /// Creates every compile-time plugin by its `Default` impl.
+                #vis fn all_static() -> ::std::vec::Vec<Self> {
+                    ::std::vec![#(Self::#var_names(::std::default::Default::default())),*]
+                }
+
+                /// Downcasts to the compile-time plugin type, `None` for other types or dynamic plugins.
+                #vis fn downcast_ref<T: ::std::any::Any>(&self) -> ::std::option::Option<&T> {
+                    match self {
+                        #(Self::#var_names(plug) => (plug as &dyn ::std::any::Any).downcast_ref(),)*
+                        Self::DynPlugin(_) => ::std::option::Option::None,
+                    }
+                }
+
+                #vis fn downcast_mut<T: ::std::any::Any>(&mut self) -> ::std::option::Option<&mut T> {
+                    match self {
+                        #(Self::#var_names(plug) => (plug as &mut dyn ::std::any::Any).downcast_mut(),)*
+                        Self::DynPlugin(_) => ::std::option::Option::None,
+                    }
+                }
+            }
+
+            impl ::std::fmt::Debug for #name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    #debug_tokens
+                }
+            }
         };
 
         Ok(expanded)
     }
 
+    /// Removes `#[plugin_api(..)]` from traits and `#[dispatch(..)]` from trait methods, which
+    /// are only read by this macro.
+    fn strip_helper_attrs(mut module: ItemMod) -> ItemMod {
+        let Some((_, items)) = module.content.as_mut() else {
+            return module;
+        };
+        for item in items {
+            let syn::Item::Trait(item_trait) = item else {
+                continue;
+            };
+            item_trait
+                .attrs
+                .retain(|attr| !attr.path().is_ident("plugin_api"));
+            for item in &mut item_trait.items {
+                if let TraitItem::Fn(func) = item {
+                    func.attrs.retain(|attr| !attr.path().is_ident("dispatch"));
+                }
+            }
+        }
+        module
+    }
+
+    /// `Param = Type` in `dyn_param(..)`.
+    struct DynParam {
+        param: Ident,
+        ty: syn::Type,
+    }
+    impl syn::parse::Parse for DynParam {
+        fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+            let param = input.parse()?;
+            input.parse::<Token![=]>()?;
+            Ok(Self {
+                param,
+                ty: input.parse()?,
+            })
+        }
+    }
+
+    /// Arguments of a trait, from the module attribute or `#[plugin_api(..)]` on the trait.
+    #[derive(Default)]
+    struct TraitArgs {
+        ignored: HashSet<Ident>,
+        dyn_ty: Option<Path>,
+        dyn_wrap: Option<Path>,
+        gen_dyn: bool,
+        dyn_params: Vec<(Ident, syn::Type)>,
+    }
+
+    impl TraitArgs {
+        fn parse(&mut self, attrs: impl IntoIterator<Item = Meta>) -> syn::Result<()> {
+            for ele in attrs {
+                match ele {
+                    Meta::Path(path) if path.is_ident("gen_dyn") => self.gen_dyn = true,
+                    Meta::List(meta) => {
+                        if meta.path.is_ident("ignore") {
+                            let args = meta.parse_args_with(
+                                Punctuated::<Ident, syn::Token![,]>::parse_terminated,
+                            )?;
+                            self.ignored.extend(args);
+                        } else if meta.path.is_ident("dyn_param") {
+                            let args = meta.parse_args_with(
+                                Punctuated::<DynParam, syn::Token![,]>::parse_terminated,
+                            )?;
+                            self.dyn_params
+                                .extend(args.into_iter().map(|p| (p.param, p.ty)));
+                        } else {
+                            return Err(syn::Error::new_spanned(meta, "unknown attribute"));
+                        }
+                    }
+                    Meta::NameValue(meta) => {
+                        if meta.path.is_ident("dyn_t") {
+                            let Expr::Path(ty) = meta.value else {
+                                return Err(syn::Error::new_spanned(meta.value, "expected path"));
+                            };
+                            self.dyn_ty = Some(ty.path);
+                        } else if meta.path.is_ident("dyn_wrap") {
+                            let Expr::Path(ty) = meta.value else {
+                                return Err(syn::Error::new_spanned(meta.value, "expected path"));
+                            };
+                            self.dyn_wrap = Some(ty.path);
+                        } else {
+                            return Err(syn::Error::new_spanned(meta, "unknown attribute"));
+                        }
+                    }
+                    _ => return Err(syn::Error::new_spanned(ele, "unknown attribute")),
+                }
+            }
+            Ok(())
+        }
+    }
+
     pub(crate) fn parse_plugin_mod(
         attrs: Vec<Meta>,
         input: ItemMod,
+    ) -> syn::Result<proc_macro2::TokenStream> {
+        let (traits, tt) = extract_mod(&input)?;
+        if traits.len() > 1 {
+            if let Some(attr) = attrs.first() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "module with more than one trait takes arguments by `#[plugin_api(..)]` on each trait",
+                ));
+            }
+        }
+
+        let mut input = strip_helper_attrs(input.clone());
+        let mut output = TokenStream::new();
+        for trait_ in &traits {
+            let mut args = TraitArgs::default();
+            args.parse(attrs.iter().cloned())?;
+            for attr in trait_
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("plugin_api"))
+            {
+                args.parse(
+                    attr.parse_args_with(Punctuated::<Meta, syn::Token![,]>::parse_terminated)?,
+                )?;
+            }
+            let tokens = generate_trait(trait_, args, &mut input, &tt, traits.len() == 1)?;
+            output.extend(tokens);
+        }
+
+        Ok(quote! {
+            #input
+
+            pub use carolina_api_macros::__generate_enum;
+            #output
+        })
+    }
+
+    /// Generates items of a trait, pushing the generated dyn trait into the module.
+    fn generate_trait(
+        trait_: &ItemTrait,
+        args: TraitArgs,
+        input: &mut ItemMod,
+        tt: &TokenStream,
+        legacy: bool,
     ) -> syn::Result<proc_macro2::TokenStream> {
         let mod_name = &input.ident;
-        let (trait_, tt) = extract_mod(&input)?;
         let trait_name = &trait_.ident;
         let trait_vis = &trait_.vis;
-        let mut ignored = HashSet::<Ident>::new();
+        let TraitArgs {
+            ignored,
+            dyn_ty,
+            dyn_wrap,
+            gen_dyn,
+            dyn_params,
+        } = args;
 
-        let mut dyn_ty = None::<Path>;
-        let mut dyn_wrap = None::<Path>;
-        for ele in attrs {
-            match ele {
-                Meta::List(meta) => {
-                    if meta.path.is_ident("ignore") {
-                        let args = meta.parse_args_with(
-                            Punctuated::<Ident, syn::Token![,]>::parse_terminated,
-                        )?;
-                        for ele in args.into_iter() {
-                            ignored.insert(ele);
-                        }
-                    } else {
-                        return Err(syn::Error::new_spanned(meta, "unknown attribute"));
-                    }
-                }
-                Meta::NameValue(meta) => {
-                    if meta.path.is_ident("dyn_t") {
-                        let Expr::Path(ty) = meta.value else {
-                            return Err(syn::Error::new_spanned(meta.value, "expected path"));
-                        };
-                        dyn_ty = Some(ty.path);
-                    } else if meta.path.is_ident("dyn_wrap") {
-                        let Expr::Path(ty) = meta.value else {
-                            return Err(syn::Error::new_spanned(meta.value, "expected path"));
-                        };
-                        dyn_wrap = Some(ty.path);
-                    } else {
-                        return Err(syn::Error::new_spanned(meta, "unknown attribute"));
-                    }
-                }
-                _ => return Err(syn::Error::new_spanned(ele, "unknown attribute")),
+        let methods: Vec<_> = trait_
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                TraitItem::Fn(func) => Some(func),
+                _ => None,
+            })
+            .collect();
+        for ident in &ignored {
+            if !methods.iter().any(|func| func.sig.ident == *ident) {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    format!("no method `{ident}` in trait `{trait_name}`"),
+                ));
             }
         }
+        let mut funcs = vec![];
+        for func in methods {
+            let mode = dispatch_mode(func, &ignored)?;
+            check_forwardable(func, mode)?;
+            funcs.push((func.sig.clone(), mode));
+        }
 
-        let macros = make_macro(&trait_, dyn_ty, dyn_wrap, &tt)?;
+        let mut dyn_export = None;
+        if gen_dyn {
+            let Some(dyn_name) = dyn_ty.as_ref().and_then(Path::get_ident) else {
+                return Err(syn::Error::new_spanned(
+                    trait_name,
+                    "`gen_dyn` requires `dyn_t` to be the name of generated trait",
+                ));
+            };
+            let tokens = crate::dyn_gen::generate_dyn(trait_, dyn_name, &dyn_params)?;
+            if let Some((_, items)) = input.content.as_mut() {
+                items.push(syn::Item::Verbatim(tokens));
+            }
+            dyn_export = Some(quote! { #trait_vis use #mod_name::#dyn_name; });
+        } else if let Some((param, _)) = dyn_params.first() {
+            return Err(syn::Error::new_spanned(
+                param,
+                "`dyn_param` requires `gen_dyn`",
+            ));
+        }
 
-        Ok(quote! {
-            #input
+        let macros = make_macro(trait_, &funcs, dyn_ty, dyn_wrap, tt, legacy)?;
 
+        Ok(quote! {
             #trait_vis use #mod_name::#trait_name;
+            #dyn_export
             #macros
         })
     }
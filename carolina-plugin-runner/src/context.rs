@@ -0,0 +1,196 @@
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+
+use carolina_api::{
+    oc_interface::{
+        app::{AppDyn, MessageSource, OBApp, OBAppProvider},
+        value::Value,
+    },
+    process::*,
+    *,
+};
+use serde::de::DeserializeOwned;
+use tokio::runtime::Handle;
+
+/// [`GlobalContext`] of the plugin process, forwarding calls to the host.
+///
+/// Sync methods block the current worker thread until the host replies, so the context must be
+/// used on a multi-thread tokio runtime, which [`run`](crate::run) provides.
+#[derive(Clone)]
+pub struct RemoteContext {
+    peer: Arc<Peer<HostCall>>,
+    tasks: Arc<TaskRegistry>,
+}
+
+impl RemoteContext {
+    pub(crate) fn new(peer: Arc<Peer<HostCall>>, tasks: Arc<TaskRegistry>) -> Self {
+        Self { peer, tasks }
+    }
+
+    fn call_blocking<T: DeserializeOwned>(&self, call: HostCall) -> Result<T, ProcessError> {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.peer.call_as(call)))
+    }
+}
+
+impl GlobalContext for RemoteContext {
+    /// Always returns an app, whose actions fail if the host has no such app.
+    fn get_shared_app(&self, id: AppRid) -> Option<Box<dyn AppDyn>> {
+        Some(Box::new(RemoteApp::new(self.peer.clone(), id)))
+    }
+
+    fn get_plugin_rid(&self, id: &str) -> Option<PluginRid> {
+        let call = HostCall::GetPluginRid { id: id.to_owned() };
+        self.call_blocking(call).unwrap_or_else(|e| {
+            log::error!("failed to get rid of plugin `{id}`: {e}");
+            None
+        })
+    }
+
+    fn get_plugin_id(&self, rid: impl Into<PluginRid>) -> Option<String> {
+        let rid = rid.into();
+        self.call_blocking(HostCall::GetPluginId { rid })
+            .unwrap_or_else(|e| {
+                log::error!("failed to get id of plugin {rid}: {e}");
+                None
+            })
+    }
+
+    /// `src` is ignored, the host always uses the rid of this plugin.
+    #[allow(unused)]
+    fn call_plugin_api(
+        &self,
+        src: PluginRid,
+        target: PluginRid,
+        call: APICall,
+    ) -> impl Future<Output = APIResult> + Send + '_ {
+        let call = HostCall::CallPluginApi {
+            target,
+            endpoint: call.endpoint,
+            payload: call.payload,
+        };
+        async move { api_result(self.peer.call(call).await) }
+    }
+
+    /// Not supported, connections can not be passed to the host.
+    #[allow(unused)]
+    fn register_connect<F, FR, P, S>(
+        &self,
+        rid: PluginRid,
+        provider: P,
+        source: S,
+        close_callback: F,
+    ) where
+        P: OBAppProvider<Output: 'static> + 'static,
+        S: MessageSource + 'static,
+        F: FnOnce() -> FR + Send + 'static,
+        FR: Future<Output = StdResult<()>> + Send + 'static,
+    {
+        log::warn!("plugin {rid} registered a connect, which is not supported out of process");
+    }
+
+    /// Tasks are kept by the plugin process, and cancelled when it is deinitialized.
+    fn register_task(&self, rid: PluginRid, task: TaskHandle) {
+        self.tasks.register(rid, task);
+    }
+
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool {
+        self.call_blocking(HostCall::IsPluginEnabled { rid })
+            .unwrap_or_else(|e| {
+                log::error!("failed to get switch of plugin {rid}: {e}");
+                false
+            })
+    }
+
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool) {
+        let call = HostCall::SetPluginEnabled { rid, enabled };
+        if let Err(e) = self.call_blocking::<Value>(call) {
+            log::error!("failed to switch plugin {rid}: {e}");
+        }
+    }
+
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules {
+        self.call_blocking(HostCall::GetPluginScope { rid })
+            .unwrap_or_else(|e| {
+                log::error!("failed to get scope of plugin {rid}: {e}");
+                ScopeRules::default()
+            })
+    }
+
+    async fn set_plugin_scope(&self, rid: PluginRid, rules: ScopeRules) -> StdResult<()> {
+        self.peer
+            .call(HostCall::SetPluginScope { rid, rules })
+            .await?;
+        Ok(())
+    }
+
+    /// The host applies the timeout and filters of the session, and drops its waiter when this
+    /// future is dropped. Custom filters can not be sent, so a session with one fails right
+    /// away instead of taking events it does not want.
+    #[allow(unused)]
+    async fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> Option<SharedEvent> {
+        if session.subscribe.filters.iter().any(EventFilter::is_custom) {
+            log::error!("sessions with custom filters are not supported out of process");
+            return None;
+        }
+        let call = HostCall::WaitSession {
+            app: session.app,
+            chat: session.chat.clone(),
+            subscribe: SubscribeWire::from(&session.subscribe),
+            timeout_ms: timeout.as_millis().try_into().unwrap_or(u64::MAX),
+        };
+        let event = match self.peer.call_as::<Option<RawEvent>>(call).await {
+            Ok(event) => event?,
+            Err(e) => {
+                log::error!("failed to wait for session: {e}");
+                return None;
+            }
+        };
+        Some(Arc::new(event))
+    }
+
+    fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
+        Ok(self.call_blocking(HostCall::GetConfigDir { rid })?)
+    }
+
+    fn get_data_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
+        Ok(self.call_blocking(HostCall::GetDataDir { rid })?)
+    }
+}
+
+/// App of the host, sending actions through the connection.
+#[derive(Clone)]
+pub struct RemoteApp {
+    peer: Arc<Peer<HostCall>>,
+    app: AppRid,
+}
+
+impl RemoteApp {
+    pub(crate) fn new(peer: Arc<Peer<HostCall>>, app: AppRid) -> Self {
+        Self { peer, app }
+    }
+}
+
+impl OBApp for RemoteApp {
+    fn send_action_impl(
+        &self,
+        action: String,
+        params: Value,
+    ) -> impl Future<Output = Result<Option<Value>, String>> + Send + '_ {
+        let call = HostCall::AppAction {
+            app: self.app,
+            action,
+            params,
+        };
+        async move {
+            match self.peer.call(call).await {
+                Ok(Value::Null) => Ok(None),
+                Ok(data) => Ok(Some(data)),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    }
+}
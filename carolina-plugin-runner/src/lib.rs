@@ -0,0 +1,301 @@
+mod context;
+
+pub use context::*;
+
+use std::sync::{Arc, OnceLock, RwLock as StdRwLock};
+
+use carolina_api::{
+    process::*, APICall, CarolinaPlugin, CatchPanic, EventContext, EventState, PluginContext,
+    PluginRid, RawEvent, Subscribe, TaskRegistry,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::RwLock,
+};
+
+/// Runs the plugin on a new multi-thread runtime until the host deinitializes it.
+///
+/// Connects to the unix socket in [`SOCKET_ENV`] if it is set, otherwise uses stdin and stdout.
+pub fn run<P: CarolinaPlugin + Default + 'static>() -> Result<(), ProcessError> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(async {
+        #[cfg(unix)]
+        if let Some(path) = std::env::var_os(SOCKET_ENV) {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (reader, writer) = stream.into_split();
+            return serve(P::default(), reader, writer).await;
+        }
+        serve(P::default(), tokio::io::stdin(), tokio::io::stdout()).await
+    });
+    // Reading stdin blocks a thread, which would never be joined
+    runtime.shutdown_background();
+    result
+}
+
+/// Serves calls from the host over the reader and writer, until the host deinitializes the
+/// plugin.
+///
+/// If the host closes the connection first, the plugin is still deinitialized and
+/// [`ProcessError::Closed`] is returned. Must be called on a multi-thread runtime, see
+/// [`RemoteContext`].
+pub async fn serve<P, R, W>(plugin: P, reader: R, writer: W) -> Result<(), ProcessError>
+where
+    P: CarolinaPlugin + 'static,
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (peer, mut calls) = Peer::<HostCall>::connect::<PluginCall, _, _>(reader, writer);
+    let runner = Arc::new(Runner {
+        peer: peer.clone(),
+        plugin: RwLock::new(Some(CatchPanic::new(plugin))),
+        tasks: Default::default(),
+        rid: OnceLock::new(),
+        subscribes: Default::default(),
+    });
+
+    while let Some((id, call)) = calls.recv().await {
+        if let PluginCall::Deinit = call {
+            let reply = runner.deinit().await;
+            peer.reply(id, reply);
+            peer.flush().await;
+            return Ok(());
+        }
+
+        let runner = runner.clone();
+        peer.spawn_reply(id, async move { runner.handle(call).await });
+    }
+
+    if let Err(e) = runner.deinit().await {
+        log::error!("failed to deinit plugin after the host closed connection: {e}");
+    }
+    Err(ProcessError::Closed)
+}
+
+struct Runner<P: CarolinaPlugin> {
+    peer: Arc<Peer<HostCall>>,
+    /// `None` once deinitialized.
+    ///
+    /// Locked like `DynPlugin` of carolina-api, see the lock ordering note on its `ScopedTask`.
+    /// Calls the host stops waiting for are aborted by [`Peer::spawn_reply`].
+    plugin: RwLock<Option<CatchPanic<P>>>,
+    tasks: Arc<TaskRegistry>,
+    rid: OnceLock<PluginRid>,
+    /// Subscriptions with custom filters, which the host can not check.
+    subscribes: StdRwLock<Vec<Subscribe>>,
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+const DEINITIALIZED: &str = "plugin is deinitialized";
+
+impl<P: CarolinaPlugin> Runner<P> {
+    fn context(&self) -> Result<PluginContext<RemoteContext>, String> {
+        let rid = *self.rid.get().ok_or("plugin is not initialized")?;
+        let global = RemoteContext::new(self.peer.clone(), self.tasks.clone());
+        Ok(PluginContext::new(rid, global, None))
+    }
+
+    async fn handle(&self, call: PluginCall) -> Result<Value, String> {
+        match call {
+            PluginCall::Info { protocol } => {
+                if protocol != PROTOCOL_VERSION {
+                    return Err(format!(
+                        "unsupported protocol version {protocol}, expected {PROTOCOL_VERSION}"
+                    ));
+                }
+                let plugin = self.plugin.read().await;
+                to_value(plugin.as_ref().ok_or(DEINITIALIZED)?.info())
+            }
+            PluginCall::Init { rid } => {
+                let _ = self.rid.set(rid);
+                let context = self.context()?;
+                let mut plugin = self.plugin.write().await;
+                let plugin = plugin.as_mut().ok_or(DEINITIALIZED)?;
+                plugin.init(context).await.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::PostInit => {
+                let context = self.context()?;
+                let mut plugin = self.plugin.write().await;
+                let plugin = plugin.as_mut().ok_or(DEINITIALIZED)?;
+                plugin.post_init(context).await.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::OnEnable => {
+                let mut plugin = self.plugin.write().await;
+                let plugin = plugin.as_mut().ok_or(DEINITIALIZED)?;
+                plugin.on_enable().await.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::OnDisable => {
+                let mut plugin = self.plugin.write().await;
+                let plugin = plugin.as_mut().ok_or(DEINITIALIZED)?;
+                plugin.on_disable().await.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::SubscribeEvents => {
+                let mut plugin = self.plugin.write().await;
+                let subscribes = plugin
+                    .as_mut()
+                    .ok_or(DEINITIALIZED)?
+                    .subscribe_events()
+                    .await;
+                let wire: Vec<_> = subscribes.iter().map(SubscribeWire::from).collect();
+                *self.subscribes.write().unwrap() = subscribes;
+                to_value(wire)
+            }
+            PluginCall::HandleEvent { app, event } => {
+                let matched = self
+                    .subscribes
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .any(|subscribe| subscribe.matches(app, &event));
+                if !matched {
+                    return to_value(EventState::Pass);
+                }
+                let event: RawEvent = serde_json::from_value(event).map_err(|e| e.to_string())?;
+                let context = EventContext::new(app, RemoteApp::new(self.peer.clone(), app));
+
+                let plugin = self.plugin.read().await;
+                let state = plugin
+                    .as_ref()
+                    .ok_or(DEINITIALIZED)?
+                    .handle_event(Arc::new(event), context)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                to_value(state)
+            }
+            PluginCall::HandleApiCall {
+                src,
+                endpoint,
+                payload,
+            } => {
+                let plugin = self.plugin.read().await;
+                let result = plugin
+                    .as_ref()
+                    .ok_or(DEINITIALIZED)?
+                    .handle_api_call(src, APICall { endpoint, payload })
+                    .await;
+                to_value(result)
+            }
+            PluginCall::Deinit => Err("deinit must be handled by the serving loop".to_owned()),
+        }
+    }
+
+    /// Cancels the plugin's tasks and deinitializes it.
+    async fn deinit(&self) -> Result<Value, String> {
+        let plugin = self.plugin.write().await.take().ok_or(DEINITIALIZED)?;
+        self.tasks.cancel_all();
+        plugin.deinit().await.map_err(|e| e.to_string())?;
+        Ok(Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use carolina_api::{APIError, APIResult, Endpoint, PluginInfo, PluginInfoBuilder};
+    use serde_json::json;
+    use tokio::io::{duplex, split};
+
+    use super::*;
+
+    struct Echo {
+        deinit: Arc<AtomicBool>,
+    }
+
+    impl CarolinaPlugin for Echo {
+        fn info(&self) -> PluginInfo {
+            PluginInfoBuilder::new("echo").build()
+        }
+
+        async fn subscribe_events(&mut self) -> Vec<Subscribe> {
+            vec![Subscribe::new("message", None::<String>)]
+        }
+
+        async fn handle_api_call(&self, _src: PluginRid, call: APICall) -> APIResult {
+            match call.endpoint {
+                endpoint if endpoint == Endpoint::new(1) => Ok(call.payload),
+                endpoint => Err(APIError::EndpointNotFound(endpoint)),
+            }
+        }
+
+        async fn deinit(self) -> carolina_api::StdResult<()> {
+            self.deinit.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Serves a plugin over a duplex stream, and connects the host end to it.
+    async fn connect() -> (
+        ProcessPlugin,
+        tokio::task::JoinHandle<Result<(), ProcessError>>,
+        Arc<AtomicBool>,
+    ) {
+        let (host, plugin) = duplex(4096);
+        let deinit = Arc::new(AtomicBool::new(false));
+        let (reader, writer) = split(plugin);
+        let echo = Echo {
+            deinit: deinit.clone(),
+        };
+        let served = tokio::spawn(serve(echo, reader, writer));
+
+        let (reader, writer) = split(host);
+        let options = ProcessOptions::new(Transport::Stdio).connect_timeout(Duration::from_secs(1));
+        let plugin = ProcessPlugin::connect(reader, writer, options)
+            .await
+            .unwrap();
+        (plugin, served, deinit)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serve_calls_until_deinit() {
+        let (mut plugin, served, deinit) = connect().await;
+        assert_eq!(plugin.info().id, "echo");
+
+        let subscribes = plugin.subscribe_events().await;
+        assert_eq!(subscribes.len(), 1);
+        assert_eq!(subscribes[0].event_type, "message");
+
+        let src = PluginRid::new(1);
+        let call = APICall {
+            endpoint: Endpoint::new(1),
+            payload: json!({"text": "hi"}),
+        };
+        assert_eq!(
+            plugin.handle_api_call(src, call).await.unwrap(),
+            json!({"text": "hi"})
+        );
+        let call = APICall {
+            endpoint: Endpoint::new(2),
+            payload: json!(null),
+        };
+        assert!(matches!(
+            plugin.handle_api_call(src, call).await,
+            Err(APIError::EndpointNotFound(_))
+        ));
+
+        plugin.deinit().await.unwrap();
+        assert!(served.await.unwrap().is_ok());
+        assert!(deinit.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn host_closing_deinitializes_the_plugin() {
+        let (plugin, served, deinit) = connect().await;
+        drop(plugin);
+        let result = tokio::time::timeout(Duration::from_secs(1), served).await;
+        assert!(matches!(result, Ok(Ok(Err(ProcessError::Closed)))));
+        assert!(deinit.load(Ordering::SeqCst));
+    }
+}
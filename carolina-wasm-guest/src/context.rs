@@ -0,0 +1,270 @@
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+
+use carolina_api::{
+    oc_interface::{
+        app::{AppDyn, MessageSource, OBApp, OBAppProvider},
+        value::Value,
+    },
+    process::*,
+    *,
+};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::de::DeserializeOwned;
+
+#[cfg(target_family = "wasm")]
+#[link(wasm_import_module = "carolina")]
+extern "C" {
+    fn host_call(ptr: u32, len: u32) -> u64;
+}
+
+/// Calls the host through the `carolina.host_call` import, which returns once the host replies.
+pub fn call_host(call: &GuestCall) -> Result<Value, ProcessError> {
+    #[cfg(target_family = "wasm")]
+    {
+        let input = serde_json::to_vec(call)?;
+        let packed = unsafe { host_call(input.as_ptr() as u32, input.len() as u32) };
+        let output = unsafe { crate::__private::take((packed >> 32) as u32, packed as u32) };
+        match serde_json::from_slice(&output)? {
+            Reply::Ok(value) => Ok(value),
+            Reply::Err(e) => Err(ProcessError::Remote(e)),
+        }
+    }
+    #[cfg(not(target_family = "wasm"))]
+    {
+        let _ = call;
+        Err(ProcessError::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "not running in a wasm host",
+        )))
+    }
+}
+
+fn call_as<T: DeserializeOwned>(call: GuestCall) -> Result<T, ProcessError> {
+    Ok(serde_json::from_value(call_host(&call)?)?)
+}
+
+/// Reads the file at `path` relative to the plugin's dir, `None` if it does not exist.
+///
+/// Needs the `ConfigDir` or `DataDir` capability of the host.
+pub fn read_file(dir: PluginDir, path: impl Into<String>) -> Result<Option<String>, ProcessError> {
+    call_as(GuestCall::Sandbox(SandboxCall::ReadFile {
+        dir,
+        path: path.into(),
+    }))
+}
+
+/// Writes the file at `path` relative to the plugin's dir, creating its parent dirs.
+///
+/// Needs the `ConfigDir` or `DataDir` capability of the host.
+pub fn write_file(
+    dir: PluginDir,
+    path: impl Into<String>,
+    content: impl Into<String>,
+) -> Result<(), ProcessError> {
+    call_host(&GuestCall::Sandbox(SandboxCall::WriteFile {
+        dir,
+        path: path.into(),
+        content: content.into(),
+    }))?;
+    Ok(())
+}
+
+/// [`GlobalContext`] of the guest, forwarding calls to the host.
+///
+/// Calls are checked against the capabilities granted by the host, denied ones fail like any
+/// other error of the host.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GuestContext;
+
+impl GlobalContext for GuestContext {
+    /// Always returns an app, whose actions fail if the host has no such app.
+    fn get_shared_app(&self, id: AppRid) -> Option<Box<dyn AppDyn>> {
+        Some(Box::new(GuestApp::new(id)))
+    }
+
+    fn get_plugin_rid(&self, id: &str) -> Option<PluginRid> {
+        let call = HostCall::GetPluginRid { id: id.to_owned() };
+        call_as(GuestCall::Host(call)).unwrap_or_else(|e| {
+            log::error!("failed to get rid of plugin `{id}`: {e}");
+            None
+        })
+    }
+
+    fn get_plugin_id(&self, rid: impl Into<PluginRid>) -> Option<String> {
+        let rid = rid.into();
+        call_as(GuestCall::Host(HostCall::GetPluginId { rid })).unwrap_or_else(|e| {
+            log::error!("failed to get id of plugin {rid}: {e}");
+            None
+        })
+    }
+
+    /// `src` is ignored, the host always uses the rid of this plugin.
+    #[allow(unused)]
+    fn call_plugin_api(
+        &self,
+        src: PluginRid,
+        target: PluginRid,
+        call: APICall,
+    ) -> impl Future<Output = APIResult> + Send + '_ {
+        let call = HostCall::CallPluginApi {
+            target,
+            endpoint: call.endpoint,
+            payload: call.payload,
+        };
+        let result = api_result(call_host(&GuestCall::Host(call)));
+        async move { result }
+    }
+
+    /// Not supported, connections can not be passed to the host.
+    #[allow(unused)]
+    fn register_connect<F, FR, P, S>(
+        &self,
+        rid: PluginRid,
+        provider: P,
+        source: S,
+        close_callback: F,
+    ) where
+        P: OBAppProvider<Output: 'static> + 'static,
+        S: MessageSource + 'static,
+        F: FnOnce() -> FR + Send + 'static,
+        FR: Future<Output = StdResult<()>> + Send + 'static,
+    {
+        log::warn!("plugin {rid} registered a connect, which is not supported in wasm");
+    }
+
+    /// Not supported, the guest has no runtime to run tasks, so the task is cancelled.
+    fn register_task(&self, rid: PluginRid, task: TaskHandle) {
+        log::warn!("plugin {rid} registered a task, which is not supported in wasm");
+        task.cancel();
+    }
+
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool {
+        call_as(GuestCall::Host(HostCall::IsPluginEnabled { rid })).unwrap_or_else(|e| {
+            log::error!("failed to get switch of plugin {rid}: {e}");
+            false
+        })
+    }
+
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool) {
+        let call = HostCall::SetPluginEnabled { rid, enabled };
+        if let Err(e) = call_host(&GuestCall::Host(call)) {
+            log::error!("failed to switch plugin {rid}: {e}");
+        }
+    }
+
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules {
+        call_as(GuestCall::Host(HostCall::GetPluginScope { rid })).unwrap_or_else(|e| {
+            log::error!("failed to get scope of plugin {rid}: {e}");
+            ScopeRules::default()
+        })
+    }
+
+    async fn set_plugin_scope(&self, rid: PluginRid, rules: ScopeRules) -> StdResult<()> {
+        call_host(&GuestCall::Host(HostCall::SetPluginScope { rid, rules }))?;
+        Ok(())
+    }
+
+    /// Blocks the guest until the host has the event or `timeout` elapses, which the host
+    /// applies, as the guest has no timer. Calls into the plugin wait meanwhile, so the timeout
+    /// should be short.
+    ///
+    /// Custom filters can not be sent, so a session with one fails right away instead of taking
+    /// events it does not want.
+    #[allow(unused)]
+    async fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> Option<SharedEvent> {
+        if session.subscribe.filters.iter().any(EventFilter::is_custom) {
+            log::error!("sessions with custom filters are not supported in wasm");
+            return None;
+        }
+        let call = HostCall::WaitSession {
+            app: session.app,
+            chat: session.chat.clone(),
+            subscribe: SubscribeWire::from(&session.subscribe),
+            timeout_ms: timeout.as_millis().try_into().unwrap_or(u64::MAX),
+        };
+        match call_as::<Option<RawEvent>>(GuestCall::Host(call)) {
+            Ok(event) => event.map(Arc::new),
+            Err(e) => {
+                log::error!("failed to wait for session: {e}");
+                None
+            }
+        }
+    }
+
+    /// Path on the host, which the guest can not access, use [`read_file`] and [`write_file`].
+    fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
+        Ok(call_as(GuestCall::Host(HostCall::GetConfigDir { rid }))?)
+    }
+
+    /// Path on the host, which the guest can not access, use [`read_file`] and [`write_file`].
+    fn get_data_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
+        Ok(call_as(GuestCall::Host(HostCall::GetDataDir { rid }))?)
+    }
+}
+
+/// App of the host, sending actions through the host.
+#[derive(Debug, Clone, Copy)]
+pub struct GuestApp {
+    app: AppRid,
+}
+
+impl GuestApp {
+    pub(crate) fn new(app: AppRid) -> Self {
+        Self { app }
+    }
+}
+
+impl OBApp for GuestApp {
+    fn send_action_impl(
+        &self,
+        action: String,
+        params: Value,
+    ) -> impl Future<Output = Result<Option<Value>, String>> + Send + '_ {
+        let call = HostCall::AppAction {
+            app: self.app,
+            action,
+            params,
+        };
+        let result = match call_host(&GuestCall::Host(call)) {
+            Ok(Value::Null) => Ok(None),
+            Ok(data) => Ok(Some(data)),
+            Err(e) => Err(e.to_string()),
+        };
+        async move { result }
+    }
+}
+
+/// Logger sending records to the host, which logs them with the plugin's logger.
+pub struct GuestLogger;
+
+impl Log for GuestLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let call = SandboxCall::Log {
+            level: record.level().as_str().to_ascii_lowercase(),
+            message: record.args().to_string(),
+        };
+        // Nowhere else to report it
+        let _ = call_host(&GuestCall::Sandbox(call));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs [`GuestLogger`] as the global logger, does nothing if a logger is already set.
+pub fn init_logger(level: LevelFilter) {
+    if log::set_logger(&GuestLogger).is_ok() {
+        log::set_max_level(level);
+    }
+}
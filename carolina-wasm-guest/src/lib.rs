@@ -0,0 +1,203 @@
+mod context;
+
+pub use context::*;
+
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use carolina_api::{
+    process::*, APICall, CarolinaPlugin, EventContext, EventState, PluginContext, PluginRid,
+    RawEvent, Subscribe,
+};
+use serde_json::Value;
+
+/// Exports the wasm abi of the plugin, which must implement `CarolinaPlugin` and `Default`.
+///
+/// Expands to nothing when not compiling to wasm, so the plugin crate still builds natively.
+#[macro_export]
+macro_rules! export_wasm_plugin {
+    ($plugin:ty) => {
+        #[cfg(target_family = "wasm")]
+        const _: () = {
+            ::std::thread_local! {
+                static GUEST: ::std::cell::RefCell<$crate::Guest<$plugin>> =
+                    ::std::cell::RefCell::new($crate::Guest::new(
+                        <$plugin as ::std::default::Default>::default(),
+                    ));
+            }
+
+            #[no_mangle]
+            extern "C" fn carolina_alloc(len: u32) -> u32 {
+                $crate::__private::alloc(len)
+            }
+
+            #[no_mangle]
+            unsafe extern "C" fn carolina_free(ptr: u32, len: u32) {
+                drop($crate::__private::take(ptr, len))
+            }
+
+            #[no_mangle]
+            unsafe extern "C" fn carolina_call(ptr: u32, len: u32) -> u64 {
+                let input = $crate::__private::take(ptr, len);
+                let output = GUEST.with_borrow_mut(|guest| guest.handle(&input));
+                $crate::__private::leak(output)
+            }
+        };
+    };
+}
+
+/// Polls the future once, the guest has no runtime to wake it later.
+fn block_on<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => Ok(output),
+        Poll::Pending => Err("plugin awaited a future which is never ready in wasm".to_owned()),
+    }
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+const DEINITIALIZED: &str = "plugin is deinitialized";
+
+/// Serves calls from the host to the plugin, see [`export_wasm_plugin`].
+///
+/// Futures of the plugin must complete without waiting, as calls to the host return
+/// immediately. A panic aborts the guest, and the host fails all later calls.
+pub struct Guest<P: CarolinaPlugin> {
+    /// `None` once deinitialized.
+    plugin: Option<P>,
+    rid: Option<PluginRid>,
+    /// Subscriptions with custom filters, which the host can not check.
+    subscribes: Vec<Subscribe>,
+}
+
+impl<P: CarolinaPlugin> Guest<P> {
+    /// Wraps the plugin, and installs [`GuestLogger`] as the global logger at `Info` level.
+    pub fn new(plugin: P) -> Self {
+        init_logger(log::LevelFilter::Info);
+        Self {
+            plugin: Some(plugin),
+            rid: None,
+            subscribes: vec![],
+        }
+    }
+
+    /// Handles a [`PluginCall`] in json, and returns the [`Reply`] in json.
+    pub fn handle(&mut self, input: &[u8]) -> Vec<u8> {
+        let reply = match serde_json::from_slice(input) {
+            Ok(call) => Reply::from(self.dispatch(call)),
+            Err(e) => Reply::Err(format!("invalid call: {e}")),
+        };
+        serde_json::to_vec(&reply).unwrap_or_else(|e| {
+            serde_json::to_vec(&Reply::Err(e.to_string())).expect("string always serializes")
+        })
+    }
+
+    fn context(&self) -> Result<PluginContext<GuestContext>, String> {
+        let rid = self.rid.ok_or("plugin is not initialized")?;
+        Ok(PluginContext::new(rid, GuestContext, None))
+    }
+
+    fn plugin(&mut self) -> Result<&mut P, String> {
+        Ok(self.plugin.as_mut().ok_or(DEINITIALIZED)?)
+    }
+
+    fn dispatch(&mut self, call: PluginCall) -> Result<Value, String> {
+        match call {
+            PluginCall::Info { protocol } => {
+                if protocol != PROTOCOL_VERSION {
+                    return Err(format!(
+                        "unsupported protocol version {protocol}, expected {PROTOCOL_VERSION}"
+                    ));
+                }
+                to_value(self.plugin()?.info())
+            }
+            PluginCall::Init { rid } => {
+                self.rid = Some(rid);
+                let context = self.context()?;
+                block_on(self.plugin()?.init(context))?.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::PostInit => {
+                let context = self.context()?;
+                block_on(self.plugin()?.post_init(context))?.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::OnEnable => {
+                block_on(self.plugin()?.on_enable())?.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::OnDisable => {
+                block_on(self.plugin()?.on_disable())?.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            PluginCall::SubscribeEvents => {
+                let subscribes = block_on(self.plugin()?.subscribe_events())?;
+                let wire: Vec<_> = subscribes.iter().map(SubscribeWire::from).collect();
+                self.subscribes = subscribes;
+                to_value(wire)
+            }
+            PluginCall::HandleEvent { app, event } => {
+                let matched = self
+                    .subscribes
+                    .iter()
+                    .any(|subscribe| subscribe.matches(app, &event));
+                if !matched {
+                    return to_value(EventState::Pass);
+                }
+                let event: RawEvent = serde_json::from_value(event).map_err(|e| e.to_string())?;
+                let context = EventContext::new(app, GuestApp::new(app));
+                let state = block_on(self.plugin()?.handle_event(event.into(), context))?
+                    .map_err(|e| e.to_string())?;
+                to_value(state)
+            }
+            PluginCall::HandleApiCall {
+                src,
+                endpoint,
+                payload,
+            } => to_value(block_on(
+                self.plugin()?
+                    .handle_api_call(src, APICall { endpoint, payload }),
+            )?),
+            PluginCall::Deinit => {
+                let plugin = self.plugin.take().ok_or(DEINITIALIZED)?;
+                block_on(plugin.deinit())?.map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    /// Allocates a buffer of `len` bytes, which must be released by [`take`].
+    pub fn alloc(len: u32) -> u32 {
+        let buffer = vec![0u8; len as usize].into_boxed_slice();
+        Box::into_raw(buffer) as *mut u8 as usize as u32
+    }
+
+    /// Takes back the buffer from [`alloc`] or [`leak`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must come from [`alloc`] or [`leak`], and be taken only once.
+    pub unsafe fn take(ptr: u32, len: u32) -> Box<[u8]> {
+        let slice = std::ptr::slice_from_raw_parts_mut(ptr as usize as *mut u8, len as usize);
+        Box::from_raw(slice)
+    }
+
+    /// Leaks the buffer, and returns its pointer and length packed as `ptr << 32 | len`.
+    pub fn leak(buffer: Vec<u8>) -> u64 {
+        let len = buffer.len() as u64;
+        let ptr = Box::into_raw(buffer.into_boxed_slice()) as *mut u8 as usize as u64;
+        ptr << 32 | len
+    }
+}
@@ -0,0 +1,143 @@
+use std::{io, thread};
+
+use tokio::{runtime as tok_rt, sync::oneshot};
+
+#[derive(Debug, Clone)]
+pub enum RuntimeKind {
+    /// Multi-thread runtime, with tokio's default worker count if `None`.
+    MultiThread { worker_threads: Option<usize> },
+    /// Current-thread runtime driven by one dedicated thread.
+    CurrentThread,
+    /// Spawn onto an existing runtime, e.g. one shared by several plugins.
+    Shared(tok_rt::Handle),
+}
+
+impl Default for RuntimeKind {
+    fn default() -> Self {
+        Self::MultiThread {
+            worker_threads: None,
+        }
+    }
+}
+
+/// Options of the tokio runtime used by [`DynPlugin`](crate::plugin::DynPlugin).
+#[derive(Debug, Clone, Default)]
+pub struct DynRuntimeOptions {
+    kind: RuntimeKind,
+    thread_name: Option<String>,
+}
+
+impl DynRuntimeOptions {
+    pub fn multi_thread(worker_threads: Option<usize>) -> Self {
+        Self {
+            kind: RuntimeKind::MultiThread { worker_threads },
+            thread_name: None,
+        }
+    }
+
+    pub fn current_thread() -> Self {
+        Self {
+            kind: RuntimeKind::CurrentThread,
+            thread_name: None,
+        }
+    }
+
+    pub fn shared(handle: tok_rt::Handle) -> Self {
+        Self {
+            kind: RuntimeKind::Shared(handle),
+            thread_name: None,
+        }
+    }
+
+    /// Names threads of the runtime, ignored for shared runtime.
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    pub fn kind(&self) -> &RuntimeKind {
+        &self.kind
+    }
+
+    pub fn build(&self) -> io::Result<DynRuntime> {
+        let thread_name = self
+            .thread_name
+            .clone()
+            .unwrap_or_else(|| "carolina-plugin".to_owned());
+
+        match &self.kind {
+            #[cfg(target_family = "wasm")]
+            RuntimeKind::MultiThread { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "multi-thread runtime is not supported on wasm",
+            )),
+            #[cfg(not(target_family = "wasm"))]
+            RuntimeKind::MultiThread { worker_threads } => {
+                let mut builder = tok_rt::Builder::new_multi_thread();
+                builder.enable_all().thread_name(thread_name);
+                if let Some(workers) = worker_threads {
+                    builder.worker_threads(*workers);
+                }
+                let rt = builder.build()?;
+                Ok(DynRuntime {
+                    handle: rt.handle().clone(),
+                    owned: Some(OwnedRuntime::MultiThread(rt)),
+                })
+            }
+            RuntimeKind::CurrentThread => {
+                let rt = tok_rt::Builder::new_current_thread().enable_all().build()?;
+                let handle = rt.handle().clone();
+                let (shutdown, shutdown_rx) = oneshot::channel();
+                thread::Builder::new().name(thread_name).spawn(move || {
+                    let _ = rt.block_on(shutdown_rx);
+                })?;
+                Ok(DynRuntime {
+                    handle,
+                    owned: Some(OwnedRuntime::CurrentThread(shutdown)),
+                })
+            }
+            RuntimeKind::Shared(handle) => Ok(DynRuntime {
+                handle: handle.clone(),
+                owned: None,
+            }),
+        }
+    }
+}
+
+enum OwnedRuntime {
+    #[cfg(not(target_family = "wasm"))]
+    MultiThread(tok_rt::Runtime),
+    CurrentThread(oneshot::Sender<()>),
+}
+
+/// Tokio runtime built from [`DynRuntimeOptions`].
+///
+/// Dropping it shuts the owned runtime down in background, so it is safe to drop in async
+/// context. A shared runtime is left untouched.
+pub struct DynRuntime {
+    handle: tok_rt::Handle,
+    owned: Option<OwnedRuntime>,
+}
+
+impl DynRuntime {
+    pub fn handle(&self) -> &tok_rt::Handle {
+        &self.handle
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.owned.is_none()
+    }
+}
+
+impl Drop for DynRuntime {
+    fn drop(&mut self) {
+        match self.owned.take() {
+            #[cfg(not(target_family = "wasm"))]
+            Some(OwnedRuntime::MultiThread(rt)) => rt.shutdown_background(),
+            Some(OwnedRuntime::CurrentThread(shutdown)) => {
+                let _ = shutdown.send(());
+            }
+            None => {}
+        }
+    }
+}
@@ -2,12 +2,17 @@ use onebot_connect_interface::value::Value;
 
 use super::*;
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, thiserror::Error, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum APIError {
     #[error("target plugin not found: {0}")]
     PluginNotFound(PluginRid),
     #[error("endpoint not found: {0}")]
     EndpointNotFound(Endpoint),
+    #[error("target plugin is disabled: {0}")]
+    PluginDisabled(PluginRid),
+    #[error(transparent)]
+    PluginPanicked(#[from] PluginPanicked),
     #[error("api call error: {0}")]
     Error(String),
 }
@@ -18,7 +23,7 @@ impl APIError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct APICall {
     pub endpoint: Endpoint,
     pub payload: Value,
@@ -1,6 +1,13 @@
-use std::{future::Future, ops::Deref, path::PathBuf, pin::Pin, sync::Arc};
+use std::{
+    future::Future,
+    ops::Deref,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-use crate::StdResult;
+use crate::{RawEvent, StdResult};
 
 use super::*;
 use call::*;
@@ -78,6 +85,24 @@ impl EventContextTrait for DynEventContext {
     }
 }
 
+/// Converts generic context parameters into their dyn types, used by dyn plugin traits
+/// generated by [`plugin_api`](crate::plugin_api).
+pub trait IntoDyn<T> {
+    fn into_dyn(self) -> T;
+}
+
+impl<EC: EventContextTrait> IntoDyn<DynEventContext> for EC {
+    fn into_dyn(self) -> DynEventContext {
+        DynEventContext::from(self.into_inner())
+    }
+}
+
+impl<G: GlobalContext> IntoDyn<PluginContext<Box<dyn GlobalContextDyn>>> for PluginContext<G> {
+    fn into_dyn(self) -> PluginContext<Box<dyn GlobalContextDyn>> {
+        PluginContext::into_dyn(self)
+    }
+}
+
 pub trait GlobalContext: Send + Sync {
     fn get_shared_app(&self, id: AppRid) -> Option<Box<dyn AppDyn>>;
 
@@ -104,6 +129,71 @@ pub trait GlobalContext: Send + Sync {
         F: FnOnce() -> FR + Send + 'static,
         FR: Future<Output = StdResult<()>> + Send + 'static;
 
+    /// Keeps the handle of a task scheduled by the plugin, host should cancel all of the plugin's
+    /// tasks when it is deinitialized or reloaded, see [`TaskRegistry`].
+    ///
+    /// By default the handle is dropped, and the task runs until it finishes on its own.
+    #[allow(unused)]
+    fn register_task(&self, rid: PluginRid, task: TaskHandle) {
+        log::debug!("host does not track scheduled tasks, task of plugin {rid} is not registered");
+    }
+
+    /// Whether the plugin is enabled, disabled plugins receive no events and api calls.
+    ///
+    /// By default all plugins are enabled.
+    #[allow(unused)]
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool {
+        true
+    }
+
+    /// Enables or disables the plugin, host should notify it via `on_enable` or `on_disable`,
+    /// see [`PluginSwitches`].
+    ///
+    /// By default plugins can not be switched, and the call is only logged.
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool) {
+        log::warn!("host does not support switching plugins, plugin {rid} is not set to {enabled}");
+    }
+
+    /// Chat scope rules of the plugin, see [`ScopeRegistry`].
+    ///
+    /// By default plugins have no rules, and receive events from all chats.
+    #[allow(unused)]
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules {
+        ScopeRules::default()
+    }
+
+    /// Replaces chat scope rules of the plugin, which are persisted by host.
+    ///
+    /// By default rules can not be set, and an error is returned.
+    #[allow(unused)]
+    fn set_plugin_scope(
+        &self,
+        rid: PluginRid,
+        rules: ScopeRules,
+    ) -> impl Future<Output = StdResult<()>> + Send + '_ {
+        let message = format!("host does not support scope rules of plugin {rid}");
+        async move { StdResult::<()>::Err(message.into()) }
+    }
+
+    /// Waits for the next event of the session, which the host routes to the waiter ahead of
+    /// subscribers and intercepts, see [`SessionRegistry`].
+    ///
+    /// Resolves to `None` once `timeout` elapses, and the waiter must be removed by then or when
+    /// the future is dropped, so no later event is taken for it. The future may be polled by the
+    /// plugin's own runtime, so it should not rely on host runtime's io.
+    ///
+    /// By default sessions are not supported, and `None` is returned right away.
+    #[allow(unused)]
+    fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> impl Future<Output = Option<SharedEvent>> + Send + '_ {
+        log::warn!("host does not support sessions, plugin {rid} can not wait for events");
+        std::future::ready(None)
+    }
+
     fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf>;
 
     fn get_data_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf>;
@@ -131,6 +221,23 @@ pub trait GlobalContextDyn: Send + Sync {
         close_callback: BoxedCallbackFn<'static>,
     );
 
+    fn register_task(&self, rid: PluginRid, task: TaskHandle);
+
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool;
+
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool);
+
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules;
+
+    fn set_plugin_scope(&self, rid: PluginRid, rules: ScopeRules) -> PinBoxResult<()>;
+
+    fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> PinBoxFut<Option<SharedEvent>>;
+
     fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf>;
 
     fn get_data_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf>;
@@ -179,6 +286,39 @@ impl<'a> GlobalContext for Box<dyn GlobalContextDyn + 'a> {
         );
     }
 
+    fn register_task(&self, rid: PluginRid, task: TaskHandle) {
+        self.deref().register_task(rid, task)
+    }
+
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool {
+        self.deref().is_plugin_enabled(rid)
+    }
+
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool) {
+        self.deref().set_plugin_enabled(rid, enabled)
+    }
+
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules {
+        self.deref().get_plugin_scope(rid)
+    }
+
+    fn set_plugin_scope(
+        &self,
+        rid: PluginRid,
+        rules: ScopeRules,
+    ) -> impl Future<Output = StdResult<()>> + Send + '_ {
+        self.deref().set_plugin_scope(rid, rules)
+    }
+
+    fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> impl Future<Output = Option<SharedEvent>> + Send + '_ {
+        self.deref().wait_session(rid, session, timeout)
+    }
+
     fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
         self.deref().get_config_dir(rid)
     }
@@ -220,6 +360,35 @@ impl<T: GlobalContext> GlobalContextDyn for T {
         self.register_connect(rid, provider, source, close_callback);
     }
 
+    fn register_task(&self, rid: PluginRid, task: TaskHandle) {
+        self.register_task(rid, task)
+    }
+
+    fn is_plugin_enabled(&self, rid: PluginRid) -> bool {
+        self.is_plugin_enabled(rid)
+    }
+
+    fn set_plugin_enabled(&self, rid: PluginRid, enabled: bool) {
+        self.set_plugin_enabled(rid, enabled)
+    }
+
+    fn get_plugin_scope(&self, rid: PluginRid) -> ScopeRules {
+        self.get_plugin_scope(rid)
+    }
+
+    fn set_plugin_scope(&self, rid: PluginRid, rules: ScopeRules) -> PinBoxResult<()> {
+        Box::pin(self.set_plugin_scope(rid, rules))
+    }
+
+    fn wait_session(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> PinBoxFut<Option<SharedEvent>> {
+        Box::pin(self.wait_session(rid, session, timeout))
+    }
+
     fn get_config_dir(&self, rid: Option<PluginRid>) -> StdResult<PathBuf> {
         self.get_config_dir(rid)
     }
@@ -233,16 +402,25 @@ pub struct PluginContext<G: GlobalContext + 'static> {
     rid: PluginRid,
     global: G,
     runtime: Option<Runtime>,
+    /// Host logger taken from the runtime, shared by the plugin's logger.
+    host_logger: Option<(Arc<dyn log::Log>, log::LevelFilter)>,
+    logger: OnceLock<PluginLogger>,
 }
 
 pub type SharedPContext = Arc<PluginContext<Box<dyn GlobalContextDyn>>>;
 
 impl<G: GlobalContext> PluginContext<G> {
-    pub fn new(marker: PluginRid, global: G, runtime: Option<Runtime>) -> Self {
+    pub fn new(marker: PluginRid, global: G, mut runtime: Option<Runtime>) -> Self {
+        let host_logger = runtime
+            .as_mut()
+            .and_then(|rt| rt.logger.take())
+            .map(|(logger, lvl)| (Arc::<dyn log::Log>::from(logger), lvl));
         Self {
             rid: marker,
             global,
             runtime,
+            host_logger,
+            logger: OnceLock::new(),
         }
     }
 
@@ -250,6 +428,11 @@ impl<G: GlobalContext> PluginContext<G> {
         self.rid
     }
 
+    #[cfg_attr(not(any(feature = "protocol", feature = "script")), allow(unused))]
+    pub(crate) fn global(&self) -> &G {
+        &self.global
+    }
+
     pub fn get_shared_app(&self, rid: impl Into<AppRid>) -> Option<impl OBApp + 'static> {
         let rid = rid.into();
         self.global.get_shared_app(rid)
@@ -259,6 +442,39 @@ impl<G: GlobalContext> PluginContext<G> {
         self.global.get_plugin_rid(id.as_ref())
     }
 
+    pub fn is_plugin_enabled(&self, rid: impl Into<PluginRid>) -> bool {
+        self.global.is_plugin_enabled(rid.into())
+    }
+
+    pub fn set_plugin_enabled(&self, rid: impl Into<PluginRid>, enabled: bool) {
+        self.global.set_plugin_enabled(rid.into(), enabled)
+    }
+
+    pub fn get_plugin_scope(&self) -> ScopeRules {
+        self.global.get_plugin_scope(self.rid)
+    }
+
+    /// Replaces the plugin's own chat scope rules.
+    pub async fn set_plugin_scope(&self, rules: ScopeRules) -> StdResult<()> {
+        self.global.set_plugin_scope(self.rid, rules).await
+    }
+
+    /// Waits for the next event of the session, `None` on timeout.
+    pub async fn wait_event(&self, session: Session, timeout: Duration) -> Option<SharedEvent> {
+        self.global.wait_session(self.rid, session, timeout).await
+    }
+
+    /// Waits for the next message from the same user in the same chat as the event.
+    pub async fn wait_reply<EC: EventContextTrait>(
+        &self,
+        event: &RawEvent,
+        context: &EC,
+        timeout: Duration,
+    ) -> Option<SharedEvent> {
+        let session = Session::reply_to(context.app_marker(), event);
+        self.wait_event(session, timeout).await
+    }
+
     pub fn get_config_dir(&self) -> Result<PathBuf, Box<dyn StdErr>> {
         self.global.get_config_dir(Some(self.rid))
     }
@@ -267,6 +483,15 @@ impl<G: GlobalContext> PluginContext<G> {
         self.global.get_data_dir(Some(self.rid))
     }
 
+    /// Opens the plugin's key-value store in `namespace`, persisted under `<data dir>/kv`.
+    ///
+    /// Opening the same namespace again returns a store sharing the same state.
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn kv_store(&self, namespace: impl AsRef<str>) -> StdResult<KvStore> {
+        let dir = self.get_data_dir()?.join("kv");
+        Ok(open_file_store(dir, namespace.as_ref()).await?)
+    }
+
     pub fn register_connect<F, FR>(
         &self,
         provider: impl OBAppProvider + 'static,
@@ -280,35 +505,78 @@ impl<G: GlobalContext> PluginContext<G> {
             .register_connect(self.rid, provider, source, close_callback)
     }
 
+    /// Schedules a task on current tokio runtime, it is cancelled by the host when the plugin is
+    /// deinitialized or reloaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn schedule<F, FR>(&self, schedule: Schedule, task: F) -> TaskHandle
+    where
+        F: FnMut() -> FR + Send + 'static,
+        FR: Future<Output = ()> + Send + 'static,
+    {
+        let handle = spawn_scheduled(schedule, task);
+        self.global.register_task(self.rid, handle.clone());
+        handle
+    }
+
+    /// Returns host metrics, used to record api calls handled by the plugin.
+    pub fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.runtime.as_ref().and_then(|rt| rt.metrics.as_ref())
+    }
+
     pub fn at_runtime(&self) -> bool {
         self.runtime.is_some()
     }
 
+    #[cfg_attr(not(feature = "plugin"), allow(unused))]
+    pub(crate) fn runtime(&self) -> Option<&Runtime> {
+        self.runtime.as_ref()
+    }
+
     pub(crate) fn into_dyn(self) -> PluginContext<Box<dyn GlobalContextDyn>> {
         PluginContext {
             rid: self.rid,
             global: Box::new(self.global),
             runtime: self.runtime,
+            host_logger: self.host_logger,
+            logger: self.logger,
         }
     }
 
-    /// Initializes the logger for the plugin context.
+    /// Returns the plugin's logger, which tags records with the plugin id and rid.
     ///
-    /// This function attempts to set the logger from the runtime if it exists.
-    /// If the logger is successfully set, it returns `Ok(true)`. If there is no logger
-    /// available in the runtime, it returns `Ok(false)`.
+    /// Records go to the host logger from runtime if any, otherwise to the process global logger.
+    pub fn logger(&self) -> &PluginLogger {
+        self.logger.get_or_init(|| {
+            let (host, level) = match &self.host_logger {
+                Some((logger, lvl)) => (Some(logger.clone()), *lvl),
+                None => (None, log::max_level()),
+            };
+            let id = self
+                .global
+                .get_plugin_id(self.rid)
+                .unwrap_or_else(|| self.rid.to_string());
+            PluginLogger::new(host, id, self.rid, level)
+        })
+    }
+
+    /// Installs the plugin's logger as the process global logger.
+    ///
+    /// Only useful for dynamically loaded plugins, which have their own copy of `log`, as the
+    /// global logger can only be set once per process. Prefer [`Self::logger`].
+    /// Returns `Ok(false)` if there is no logger available in the runtime.
     ///
     /// # Errors
     ///
     /// Returns a `log::SetLoggerError` if setting the logger fails.
     pub fn init_logger(&mut self) -> Result<bool, log::SetLoggerError> {
-        if let Some(rt) = &mut self.runtime {
-            let Some((logger, lvl)) = rt.logger.take() else {
-                return Ok(false);
-            };
-            log::set_boxed_logger(logger)?;
-            log::set_max_level(lvl);
-        }
+        let Some((_, lvl)) = self.host_logger else {
+            return Ok(false);
+        };
+        log::set_boxed_logger(Box::new(self.logger().clone()))?;
+        log::set_max_level(lvl);
 
         Ok(true)
     }
@@ -322,12 +590,14 @@ impl<G: GlobalContext> PluginContext<G> {
         C: IntoAPICall<Error = E>,
         E: Display,
     {
-        self.global
-            .call_plugin_api(
-                self.rid,
-                target,
-                call.into_api_call().map_err(APIError::other)?,
-            )
-            .await
+        let call = call.into_api_call().map_err(APIError::other)?;
+        let endpoint = call.endpoint;
+        instrument_api_call(
+            self.global.call_plugin_api(self.rid, target, call),
+            self.rid,
+            target,
+            endpoint,
+        )
+        .await
     }
 }
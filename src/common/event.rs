@@ -0,0 +1,154 @@
+use std::{fmt::Debug, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use super::pick_strings;
+use crate::RawEvent;
+
+/// Common fields of an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct EventMeta {
+    #[serde(rename = "type", default)]
+    pub event_type: String,
+    #[serde(default)]
+    pub detail_type: String,
+    #[serde(default)]
+    pub sub_type: String,
+}
+
+impl EventMeta {
+    /// Picks the fields from the typed event, without serializing it into json.
+    pub fn from_event(event: &RawEvent) -> Self {
+        let [event_type, detail_type, sub_type] =
+            pick_strings(event, ["type", "detail_type", "sub_type"]);
+        Self {
+            event_type: event_type.unwrap_or_default(),
+            detail_type: detail_type.unwrap_or_default(),
+            sub_type: sub_type.unwrap_or_default(),
+        }
+    }
+
+    pub fn from_json(event: &serde_json::Value) -> Self {
+        Self::deserialize(event).unwrap_or_default()
+    }
+}
+
+/// Serializes the event into json value for field inspecting, `Null` if it fails.
+pub fn event_json(event: &RawEvent) -> serde_json::Value {
+    serde_json::to_value(event).unwrap_or_default()
+}
+
+/// Chat fields of an event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventChat {
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
+    pub guild_id: Option<String>,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+}
+
+impl EventChat {
+    /// Picks the fields from the typed event, without serializing it into json.
+    pub fn from_event(event: &RawEvent) -> Self {
+        let [group_id, guild_id, channel_id, user_id] =
+            pick_strings(event, ["group_id", "guild_id", "channel_id", "user_id"]);
+        Self {
+            group_id,
+            guild_id,
+            channel_id,
+            user_id,
+        }
+    }
+
+    pub fn from_json(event: &serde_json::Value) -> Self {
+        Self::deserialize(event).unwrap_or_default()
+    }
+}
+
+/// Plain text of the event's message, joined from its text segments, or `alt_message` if the
+/// message has no text segment.
+pub fn message_text(event: &serde_json::Value) -> Option<String> {
+    let text = event
+        .get("message")?
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|seg| seg.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .filter_map(|seg| seg.pointer("/data/text").and_then(|t| t.as_str()))
+        .collect::<String>();
+    if text.is_empty() {
+        event
+            .get("alt_message")
+            .and_then(|t| t.as_str())
+            .map(ToOwned::to_owned)
+    } else {
+        Some(text)
+    }
+}
+
+/// Predicate on fields of the serialized event, fields are located by json pointer, e.g.
+/// `/group_id`.
+///
+/// All but custom filters serialize, so they can be checked by the other end of a connection.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventFilter {
+    /// Field equals the value.
+    Eq(String, serde_json::Value),
+    /// Field is one of the values.
+    In(String, Vec<serde_json::Value>),
+    /// String field starts with the prefix.
+    StartsWith(String, String),
+    /// Plain text of the message starts with the prefix after leading whitespace, e.g. after a
+    /// mention, see [`message_text`].
+    MessagePrefix(String),
+    /// Custom predicate on the whole event.
+    #[serde(skip)]
+    Custom(Arc<dyn Fn(&serde_json::Value) -> bool + Send + Sync>),
+}
+
+impl EventFilter {
+    pub fn custom(f: impl Fn(&serde_json::Value) -> bool + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Self::Custom(_))
+    }
+
+    pub fn matches(&self, event: &serde_json::Value) -> bool {
+        match self {
+            Self::Eq(pointer, value) => event.pointer(pointer) == Some(value),
+            Self::In(pointer, values) => event
+                .pointer(pointer)
+                .is_some_and(|field| values.contains(field)),
+            Self::StartsWith(pointer, prefix) => event
+                .pointer(pointer)
+                .and_then(|field| field.as_str())
+                .is_some_and(|field| field.starts_with(prefix.as_str())),
+            Self::MessagePrefix(prefix) => message_text(event)
+                .is_some_and(|text| text.trim_start().starts_with(prefix.as_str())),
+            Self::Custom(f) => f(event),
+        }
+    }
+}
+
+impl Debug for EventFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eq(pointer, value) => f.debug_tuple("Eq").field(pointer).field(value).finish(),
+            Self::In(pointer, values) => f.debug_tuple("In").field(pointer).field(values).finish(),
+            Self::StartsWith(pointer, prefix) => f
+                .debug_tuple("StartsWith")
+                .field(pointer)
+                .field(prefix)
+                .finish(),
+            Self::MessagePrefix(prefix) => f.debug_tuple("MessagePrefix").field(prefix).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use log::{
+    kv::{self, Key, Source, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+
+use super::*;
+
+/// Forwards to the process global logger, used when the host provides none.
+struct GlobalLogger;
+
+impl Log for GlobalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        log::logger().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        log::logger().log(record)
+    }
+
+    fn flush(&self) {
+        log::logger().flush()
+    }
+}
+
+struct LoggerInner {
+    host: Arc<dyn Log>,
+    plugin_id: String,
+    rid: PluginRid,
+    level: AtomicUsize,
+}
+
+/// Logger handle of a single plugin.
+///
+/// Every record is forwarded to the host logger tagged with `plugin` and `plugin_rid` key-values,
+/// records above the plugin's own level filter are dropped.
+/// Use it with the `logger:` argument of `log` macros:
+///
+/// ```ignore
+/// let logger = context.logger();
+/// log::info!(logger: logger, "plugin started");
+/// ```
+#[derive(Clone)]
+pub struct PluginLogger {
+    inner: Arc<LoggerInner>,
+}
+
+fn level_from_usize(lvl: usize) -> LevelFilter {
+    LevelFilter::iter()
+        .find(|l| *l as usize == lvl)
+        .unwrap_or(LevelFilter::Off)
+}
+
+impl PluginLogger {
+    /// Creates logger forwarding to `host`, or to the process global logger if `None`.
+    pub fn new(
+        host: Option<Arc<dyn Log>>,
+        plugin_id: impl Into<String>,
+        rid: PluginRid,
+        level: LevelFilter,
+    ) -> Self {
+        Self {
+            inner: Arc::new(LoggerInner {
+                host: host.unwrap_or_else(|| Arc::new(GlobalLogger)),
+                plugin_id: plugin_id.into(),
+                rid,
+                level: AtomicUsize::new(level as usize),
+            }),
+        }
+    }
+
+    pub fn plugin_id(&self) -> &str {
+        &self.inner.plugin_id
+    }
+
+    pub fn rid(&self) -> PluginRid {
+        self.inner.rid
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        level_from_usize(self.inner.level.load(Ordering::Relaxed))
+    }
+
+    /// Sets the plugin's level filter.
+    ///
+    /// `log` macros check the global max level first, which the host has to set no lower than
+    /// the plugin levels it allows.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.inner.level.store(level as usize, Ordering::Relaxed);
+    }
+}
+
+struct Tagged<'a> {
+    plugin_id: &'a str,
+    rid: u64,
+    rest: &'a dyn Source,
+}
+
+impl Source for Tagged<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        visitor.visit_pair(Key::from_str("plugin"), self.plugin_id.into())?;
+        visitor.visit_pair(Key::from_str("plugin_rid"), self.rid.into())?;
+        self.rest.visit(visitor)
+    }
+}
+
+impl Log for PluginLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level() && self.inner.host.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let tagged = Tagged {
+            plugin_id: &self.inner.plugin_id,
+            rid: self.inner.rid.inner(),
+            rest: record.key_values(),
+        };
+        self.inner.host.log(
+            &Record::builder()
+                .args(*record.args())
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .key_values(&tagged)
+                .build(),
+        )
+    }
+
+    fn flush(&self) {
+        self.inner.host.flush()
+    }
+}
+
+impl std::fmt::Debug for PluginLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginLogger")
+            .field("plugin_id", &self.inner.plugin_id)
+            .field("rid", &self.inner.rid)
+            .field("level", &self.level())
+            .finish()
+    }
+}
@@ -0,0 +1,524 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use fxhash::FxHashMap;
+
+use super::*;
+
+/// Upper bounds of latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+struct Histogram {
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (*bound, cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64(),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    /// Cumulative count of each bucket with its upper bound.
+    pub buckets: Vec<(f64, u64)>,
+    /// Sum of all observed latencies, in seconds.
+    pub sum: f64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventOutcome {
+    Pass,
+    Intercept,
+    Error,
+}
+
+impl EventOutcome {
+    pub fn of<E>(result: &Result<EventState, E>) -> Self {
+        match result {
+            Ok(EventState::Pass) => Self::Pass,
+            Ok(EventState::Intercept) => Self::Intercept,
+            Err(_) => Self::Error,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Intercept => "intercept",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    plugin: PluginRid,
+    event_type: String,
+    outcome: EventOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct CallKey {
+    plugin: PluginRid,
+    endpoint: Endpoint,
+    ok: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventMetric {
+    pub plugin: PluginRid,
+    pub plugin_id: Option<String>,
+    pub event_type: String,
+    pub outcome: EventOutcome,
+    pub latency: HistogramSnapshot,
+}
+
+#[derive(Debug, Clone)]
+pub struct APICallMetric {
+    /// Plugin handling the call.
+    pub plugin: PluginRid,
+    pub plugin_id: Option<String>,
+    pub endpoint: Endpoint,
+    pub ok: bool,
+    pub latency: HistogramSnapshot,
+}
+
+#[derive(Debug, Clone)]
+pub struct PanicMetric {
+    pub plugin: PluginRid,
+    pub plugin_id: Option<String>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub events: Vec<EventMetric>,
+    pub api_calls: Vec<APICallMetric>,
+    pub panics: Vec<PanicMetric>,
+}
+
+impl MetricsSnapshot {
+    /// Ratio of intercepted events among all events handled by the plugin.
+    pub fn intercept_rate(&self, plugin: PluginRid) -> Option<f64> {
+        let (mut intercepted, mut total) = (0, 0);
+        for metric in self.events.iter().filter(|m| m.plugin == plugin) {
+            total += metric.latency.count;
+            if metric.outcome == EventOutcome::Intercept {
+                intercepted += metric.latency.count;
+            }
+        }
+        (total > 0).then(|| intercepted as f64 / total as f64)
+    }
+}
+
+/// Metrics of plugin event handling and api calls, shared by host and plugins.
+#[derive(Default)]
+pub struct Metrics {
+    plugin_ids: RwLock<FxHashMap<PluginRid, String>>,
+    events: RwLock<FxHashMap<EventKey, Histogram>>,
+    api_calls: RwLock<FxHashMap<CallKey, Histogram>>,
+    panics: RwLock<FxHashMap<PluginRid, AtomicU64>>,
+}
+
+fn observe<K: Eq + std::hash::Hash>(
+    map: &RwLock<FxHashMap<K, Histogram>>,
+    key: K,
+    elapsed: Duration,
+) {
+    if let Some(histogram) = map.read().unwrap().get(&key) {
+        histogram.observe(elapsed);
+        return;
+    }
+    map.write()
+        .unwrap()
+        .entry(key)
+        .or_default()
+        .observe(elapsed);
+}
+
+impl Metrics {
+    /// Sets the plugin id used as label of the plugin's metrics.
+    pub fn set_plugin_id(&self, rid: PluginRid, id: impl Into<String>) {
+        self.plugin_ids.write().unwrap().insert(rid, id.into());
+    }
+
+    pub fn record_event(
+        &self,
+        plugin: PluginRid,
+        event_type: impl Into<String>,
+        outcome: EventOutcome,
+        elapsed: Duration,
+    ) {
+        let key = EventKey {
+            plugin,
+            event_type: event_type.into(),
+            outcome,
+        };
+        observe(&self.events, key, elapsed);
+    }
+
+    pub fn record_api_call(
+        &self,
+        plugin: PluginRid,
+        endpoint: Endpoint,
+        ok: bool,
+        elapsed: Duration,
+    ) {
+        observe(
+            &self.api_calls,
+            CallKey {
+                plugin,
+                endpoint,
+                ok,
+            },
+            elapsed,
+        );
+    }
+
+    pub fn record_panic(&self, plugin: PluginRid) {
+        if let Some(count) = self.panics.read().unwrap().get(&plugin) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.panics
+            .write()
+            .unwrap()
+            .entry(plugin)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Runs `handle_event` future of the plugin and records its outcome and latency.
+    pub async fn observe_event<F, E>(
+        &self,
+        plugin: PluginRid,
+        event_type: impl Into<String>,
+        fut: F,
+    ) -> Result<EventState, E>
+    where
+        F: Future<Output = Result<EventState, E>>,
+    {
+        let start = Instant::now();
+        let result = fut.await;
+        self.record_event(
+            plugin,
+            event_type,
+            EventOutcome::of(&result),
+            start.elapsed(),
+        );
+        result
+    }
+
+    /// Runs api call future handled by the plugin and records its result and latency.
+    pub async fn observe_api_call<F, T, E>(
+        &self,
+        plugin: PluginRid,
+        endpoint: Endpoint,
+        fut: F,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let start = Instant::now();
+        let result = fut.await;
+        self.record_api_call(plugin, endpoint, result.is_ok(), start.elapsed());
+        result
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let ids = self.plugin_ids.read().unwrap();
+        let events = self
+            .events
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, histogram)| EventMetric {
+                plugin: key.plugin,
+                plugin_id: ids.get(&key.plugin).cloned(),
+                event_type: key.event_type.clone(),
+                outcome: key.outcome,
+                latency: histogram.snapshot(),
+            })
+            .collect();
+        let api_calls = self
+            .api_calls
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, histogram)| APICallMetric {
+                plugin: key.plugin,
+                plugin_id: ids.get(&key.plugin).cloned(),
+                endpoint: key.endpoint,
+                ok: key.ok,
+                latency: histogram.snapshot(),
+            })
+            .collect();
+
+        let panics = self
+            .panics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(rid, count)| PanicMetric {
+                plugin: *rid,
+                plugin_id: ids.get(rid).cloned(),
+                count: count.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        MetricsSnapshot {
+            events,
+            api_calls,
+            panics,
+        }
+    }
+
+    pub fn export<E: MetricsExporter>(&self, exporter: &E) -> E::Output {
+        exporter.export(&self.snapshot())
+    }
+}
+
+pub trait MetricsExporter {
+    type Output;
+
+    fn export(&self, snapshot: &MetricsSnapshot) -> Self::Output;
+}
+
+/// Exports metrics in Prometheus text format.
+#[derive(Debug, Clone)]
+pub struct PrometheusExporter {
+    prefix: String,
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new("carolina")
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn plugin_labels(rid: PluginRid, id: Option<&str>) -> String {
+    format!(
+        "plugin=\"{}\",plugin_rid=\"{rid}\"",
+        escape_label(id.unwrap_or_default())
+    )
+}
+
+impl PrometheusExporter {
+    /// Creates exporter with metric names prefixed by `prefix`.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &HistogramSnapshot) {
+        for (bound, count) in &histogram.buckets {
+            writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}").unwrap();
+        }
+        writeln!(
+            out,
+            "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+            histogram.count
+        )
+        .unwrap();
+        writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum).unwrap();
+        writeln!(out, "{name}_count{{{labels}}} {}", histogram.count).unwrap();
+    }
+}
+
+impl MetricsExporter for PrometheusExporter {
+    type Output = String;
+
+    fn export(&self, snapshot: &MetricsSnapshot) -> String {
+        let mut out = String::new();
+        let prefix = &self.prefix;
+
+        let name = format!("{prefix}_event_handle_seconds");
+        writeln!(out, "# HELP {name} Latency of plugin event handling.").unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+        for metric in &snapshot.events {
+            let labels = format!(
+                "{},event_type=\"{}\",result=\"{}\"",
+                plugin_labels(metric.plugin, metric.plugin_id.as_deref()),
+                escape_label(&metric.event_type),
+                metric.outcome.as_str(),
+            );
+            Self::write_histogram(&mut out, &name, &labels, &metric.latency);
+        }
+
+        let name = format!("{prefix}_event_intercept_ratio");
+        writeln!(
+            out,
+            "# HELP {name} Ratio of events intercepted by the plugin."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {name} gauge").unwrap();
+        let mut plugins: Vec<_> = snapshot
+            .events
+            .iter()
+            .map(|m| (m.plugin, m.plugin_id.as_deref()))
+            .collect();
+        plugins.sort_unstable();
+        plugins.dedup();
+        for (rid, id) in plugins {
+            if let Some(rate) = snapshot.intercept_rate(rid) {
+                writeln!(out, "{name}{{{}}} {rate}", plugin_labels(rid, id)).unwrap();
+            }
+        }
+
+        let name = format!("{prefix}_api_call_seconds");
+        writeln!(
+            out,
+            "# HELP {name} Latency of plugin api calls by endpoint."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+        for metric in &snapshot.api_calls {
+            let labels = format!(
+                "{},endpoint=\"{}\",result=\"{}\"",
+                plugin_labels(metric.plugin, metric.plugin_id.as_deref()),
+                metric.endpoint,
+                if metric.ok { "ok" } else { "error" },
+            );
+            Self::write_histogram(&mut out, &name, &labels, &metric.latency);
+        }
+
+        let name = format!("{prefix}_plugin_panics_total");
+        writeln!(
+            out,
+            "# HELP {name} Count of panics caught at plugin boundary."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {name} counter").unwrap();
+        for metric in &snapshot.panics {
+            let labels = plugin_labels(metric.plugin, metric.plugin_id.as_deref());
+            writeln!(out, "{name}{{{labels}}} {}", metric.count).unwrap();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::default();
+        for millis in [1, 3, 30_000] {
+            histogram.observe(Duration::from_millis(millis));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 3);
+        assert!((snapshot.sum - 30.004).abs() < 1e-9);
+        assert_eq!(snapshot.buckets.len(), LATENCY_BUCKETS.len());
+        assert_eq!(snapshot.buckets[0], (0.001, 1));
+        assert_eq!(snapshot.buckets[1], (0.005, 2));
+        // Longer than the last bound, only counted by `+Inf`
+        assert_eq!(snapshot.buckets.last(), Some(&(10.0, 2)));
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let metrics = Metrics::default();
+        let rid = PluginRid::new(1);
+        metrics.set_plugin_id(rid, "echo\"x");
+        metrics.record_event(
+            rid,
+            "message",
+            EventOutcome::Intercept,
+            Duration::from_millis(2),
+        );
+        metrics.record_event(
+            rid,
+            "message",
+            EventOutcome::Pass,
+            Duration::from_millis(20),
+        );
+        metrics.record_api_call(rid, Endpoint::new(3), false, Duration::from_millis(1));
+        metrics.record_panic(rid);
+        metrics.record_panic(rid);
+
+        let text = metrics.export(&PrometheusExporter::default());
+        let lines: Vec<_> = text.lines().collect();
+        let labels = r#"plugin="echo\"x",plugin_rid="1""#;
+        for expected in [
+            "# TYPE carolina_event_handle_seconds histogram".to_owned(),
+            format!(
+                r#"carolina_event_handle_seconds_bucket{{{labels},event_type="message",result="intercept",le="0.001"}} 0"#
+            ),
+            format!(
+                r#"carolina_event_handle_seconds_bucket{{{labels},event_type="message",result="intercept",le="0.005"}} 1"#
+            ),
+            format!(
+                r#"carolina_event_handle_seconds_bucket{{{labels},event_type="message",result="pass",le="+Inf"}} 1"#
+            ),
+            format!(
+                r#"carolina_event_handle_seconds_count{{{labels},event_type="message",result="pass"}} 1"#
+            ),
+            "# TYPE carolina_event_intercept_ratio gauge".to_owned(),
+            format!("carolina_event_intercept_ratio{{{labels}}} 0.5"),
+            format!(r#"carolina_api_call_seconds_count{{{labels},endpoint="3",result="error"}} 1"#),
+            "# TYPE carolina_plugin_panics_total counter".to_owned(),
+            format!("carolina_plugin_panics_total{{{labels}}} 2"),
+        ] {
+            assert!(
+                lines.contains(&expected.as_str()),
+                "missing `{expected}` in:\n{text}"
+            );
+        }
+    }
+}
@@ -1,13 +1,37 @@
 use std::fmt::Display;
 use std::{error::Error as StdErr, future::Future};
 
+mod async_rt;
 mod call;
 mod context;
+mod event;
+mod logger;
+mod metrics;
+mod panic;
+mod pick;
 mod plugin;
+mod registry;
+mod schedule;
+mod scope;
+mod session;
+mod shutdown;
+mod storage;
+mod switch;
+mod trace;
+mod wire;
 
 use crate::StdResult;
+use serde::{Deserialize, Serialize};
 
-pub use {call::*, context::*, plugin::*};
+pub use {
+    async_rt::*, call::*, context::*, event::*, logger::*, metrics::*, panic::*, plugin::*,
+    schedule::*, scope::*, session::*, shutdown::*, storage::*, switch::*, wire::*,
+};
+
+pub(crate) use pick::*;
+pub(crate) use trace::*;
+#[cfg(feature = "tracing")]
+pub use trace::{api_call_span, api_handle_span, dispatch_span};
 
 macro_rules! id_type {
     ($name:ident, $ty:ty $(, $doc:literal)?) => {
@@ -53,10 +77,17 @@ id_type!(PluginRid, u64, "Plugin's runtime id.");
 id_type!(Endpoint, u64, "Plugin api call endpoint id.");
 
 pub struct Runtime {
+    /// Host logger and the plugin's level filter, see [`PluginContext::logger`].
     pub logger: Option<(Box<dyn log::Log>, log::LevelFilter)>,
+    /// Host metrics, see [`PluginContext::metrics`].
+    pub metrics: Option<std::sync::Arc<Metrics>>,
+    /// Runtime options for plugins wrapped in [`DynPlugin`](crate::plugin::DynPlugin).
+    pub dyn_runtime: Option<DynRuntimeOptions>,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub enum Priority {
     Lowest,
     Low,
@@ -78,7 +109,7 @@ impl Priority {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EventState {
     #[default]
     Pass,
@@ -93,10 +124,18 @@ pub fn intercept<E>() -> Result<EventState, E> {
     Ok(EventState::Intercept)
 }
 
+/// Event subscription, the host dispatches an event to the plugin only if it matches.
 #[derive(Debug, Clone)]
 pub struct Subscribe {
     pub event_type: String,
     pub detail_type: Option<String>,
+    pub sub_type: Option<String>,
+    /// Only events from this app.
+    pub app: Option<AppRid>,
+    /// Only events whose `self.user_id` equals this.
+    pub self_id: Option<String>,
+    /// Predicates which must all match.
+    pub filters: Vec<EventFilter>,
     pub priority: Priority,
 }
 
@@ -105,6 +144,10 @@ impl Subscribe {
         Self {
             event_type: event_type.into(),
             detail_type: detail_type.map(|r| r.into()),
+            sub_type: None,
+            app: None,
+            self_id: None,
+            filters: vec![],
             priority: Priority::default(),
         }
     }
@@ -113,6 +156,67 @@ impl Subscribe {
         self.priority = priority;
         self
     }
+
+    pub fn sub_type(mut self, sub_type: impl Into<String>) -> Self {
+        self.sub_type = Some(sub_type.into());
+        self
+    }
+
+    pub fn app(mut self, app: AppRid) -> Self {
+        self.app = Some(app);
+        self
+    }
+
+    pub fn self_id(mut self, self_id: impl Into<String>) -> Self {
+        self.self_id = Some(self_id.into());
+        self
+    }
+
+    pub fn filter(mut self, filter: EventFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Checks the event from the app, serialized by [`event_json`].
+    pub fn matches(&self, app: AppRid, event: &serde_json::Value) -> bool {
+        let meta = EventMeta::from_json(event);
+        let self_id = event.pointer("/self/user_id").and_then(|id| id.as_str());
+        self.matches_meta(app, &meta, self_id)
+            && self.filters.iter().all(|filter| filter.matches(event))
+    }
+
+    /// Checks the typed event from the app, which is serialized only if there are filters.
+    ///
+    /// Host matching many subscriptions with filters should serialize the event once by
+    /// [`event_json`], and use [`matches`](Self::matches) instead.
+    pub fn matches_event(&self, app: AppRid, event: &crate::RawEvent) -> bool {
+        let [event_type, detail_type, sub_type, self_id] =
+            pick_strings(event, ["type", "detail_type", "sub_type", "self/user_id"]);
+        let meta = EventMeta {
+            event_type: event_type.unwrap_or_default(),
+            detail_type: detail_type.unwrap_or_default(),
+            sub_type: sub_type.unwrap_or_default(),
+        };
+        self.matches_meta(app, &meta, self_id.as_deref())
+            && (self.filters.is_empty() || {
+                let event = event_json(event);
+                self.filters.iter().all(|filter| filter.matches(&event))
+            })
+    }
+
+    fn matches_meta(&self, app: AppRid, meta: &EventMeta, self_id: Option<&str>) -> bool {
+        self.app.is_none_or(|rid| rid == app)
+            && meta.event_type == self.event_type
+            && self
+                .detail_type
+                .as_ref()
+                .is_none_or(|detail_type| *detail_type == meta.detail_type)
+            && self
+                .sub_type
+                .as_ref()
+                .is_none_or(|sub_type| *sub_type == meta.sub_type)
+            && self.self_id.as_deref().is_none_or(|id| self_id == Some(id))
+    }
 }
 
 pub type BoxedCallbackFn<'a, R = StdResult<()>> = Box<dyn FnOnce() -> PinBoxFut<'a, R> + Send + 'a>;
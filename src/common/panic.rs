@@ -0,0 +1,191 @@
+use std::{
+    any::Any,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use fxhash::FxHashMap;
+
+use super::*;
+
+#[derive(Debug, Clone, thiserror::Error, Serialize, Deserialize)]
+#[error("plugin `{plugin_id}` panicked: {message}")]
+pub struct PluginPanicked {
+    pub plugin_id: String,
+    pub message: String,
+}
+
+impl PluginPanicked {
+    pub fn new(plugin_id: impl Into<String>, payload: &(dyn Any + Send)) -> Self {
+        Self {
+            plugin_id: plugin_id.into(),
+            message: panic_message(payload),
+        }
+    }
+}
+
+/// Extracts message from panic payload.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
+/// Future catching panics when polling the inner future.
+pub struct CatchUnwind<F> {
+    fut: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match catch_unwind(AssertUnwindSafe(|| self.fut.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+pub fn catch_unwind_fut<F: Future>(fut: F) -> CatchUnwind<F> {
+    CatchUnwind { fut: Box::pin(fut) }
+}
+
+/// Runs the plugin's future, converting panic into [`PluginPanicked`].
+pub async fn catch_plugin_panic<F: Future>(
+    plugin_id: &str,
+    fut: F,
+) -> Result<F::Output, PluginPanicked> {
+    catch_unwind_fut(fut)
+        .await
+        .map_err(|payload| PluginPanicked::new(plugin_id, &*payload))
+}
+
+/// Plugin wrapper catching panics at every hook.
+///
+/// Panics become [`PluginPanicked`] errors, or [`APIError::PluginPanicked`] for api calls.
+/// A panicking `subscribe_events` subscribes nothing.
+pub struct CatchPanic<P: CarolinaPlugin> {
+    plugin: P,
+    plugin_id: String,
+}
+
+impl<P: CarolinaPlugin> CatchPanic<P> {
+    pub fn new(plugin: P) -> Self {
+        let plugin_id = plugin.info().id;
+        Self { plugin, plugin_id }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.plugin
+    }
+
+    pub fn into_inner(self) -> P {
+        self.plugin
+    }
+}
+
+impl<P: CarolinaPlugin> CarolinaPlugin for CatchPanic<P> {
+    fn info(&self) -> PluginInfo {
+        self.plugin.info()
+    }
+
+    async fn init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        catch_plugin_panic(&self.plugin_id, self.plugin.init(context)).await?
+    }
+
+    async fn post_init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        catch_plugin_panic(&self.plugin_id, self.plugin.post_init(context)).await?
+    }
+
+    async fn on_enable(&mut self) -> StdResult<()> {
+        catch_plugin_panic(&self.plugin_id, self.plugin.on_enable()).await?
+    }
+
+    async fn on_disable(&mut self) -> StdResult<()> {
+        catch_plugin_panic(&self.plugin_id, self.plugin.on_disable()).await?
+    }
+
+    async fn subscribe_events(&mut self) -> Vec<Subscribe> {
+        catch_plugin_panic(&self.plugin_id, self.plugin.subscribe_events())
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("{e}");
+                vec![]
+            })
+    }
+
+    async fn handle_event<EC>(&self, event: SharedEvent, context: EC) -> StdResult<EventState>
+    where
+        EC: EventContextTrait + Send + 'static,
+    {
+        catch_plugin_panic(&self.plugin_id, self.plugin.handle_event(event, context)).await?
+    }
+
+    async fn handle_api_call(&self, src: PluginRid, call: APICall) -> APIResult {
+        catch_plugin_panic(&self.plugin_id, self.plugin.handle_api_call(src, call)).await?
+    }
+
+    async fn deinit(self) -> StdResult<()> {
+        let CatchPanic { plugin, plugin_id } = self;
+        catch_plugin_panic(&plugin_id, plugin.deinit()).await?
+    }
+}
+
+/// Host side panic counter, decides when a plugin should be disabled.
+#[derive(Default)]
+pub struct PanicTracker {
+    max_panics: Option<u64>,
+    counts: Mutex<FxHashMap<PluginRid, u64>>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl PanicTracker {
+    /// Creates tracker which suggests disabling plugin after `max_panics` panics, `None` never.
+    pub fn new(max_panics: Option<u64>) -> Self {
+        Self {
+            max_panics,
+            ..Default::default()
+        }
+    }
+
+    /// Also counts panics in metrics.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Records a panic of the plugin, returns `true` if the plugin should be disabled.
+    pub fn record(&self, rid: PluginRid, panic: &PluginPanicked) -> bool {
+        log::error!("{panic}");
+        if let Some(metrics) = &self.metrics {
+            metrics.record_panic(rid);
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(rid).or_default();
+        *count += 1;
+        self.max_panics.is_some_and(|max| *count >= max)
+    }
+
+    pub fn count(&self, rid: PluginRid) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(&rid)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Resets the plugin's panic count, e.g. after it is re-enabled.
+    pub fn reset(&self, rid: PluginRid) {
+        self.counts.lock().unwrap().remove(&rid);
+    }
+}
@@ -0,0 +1,422 @@
+use serde::{
+    ser::{self, Impossible, SerializeMap, SerializeStruct, SerializeStructVariant},
+    Serialize, Serializer,
+};
+
+/// Picks string fields of the value by `/` separated paths, e.g. `type` or `self/user_id`,
+/// without serializing other fields into json.
+///
+/// Fields which are absent or not strings are `None`.
+pub(crate) fn pick_strings<T, const N: usize>(value: &T, paths: [&str; N]) -> [Option<String>; N]
+where
+    T: Serialize + ?Sized,
+{
+    let mut picked = std::array::from_fn(|_| None);
+    let targets = paths.into_iter().zip(picked.iter_mut()).collect();
+    let _ = value.serialize(Picker { targets });
+    picked
+}
+
+#[derive(Debug)]
+struct Unpicked;
+
+impl std::fmt::Display for Unpicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("field is not picked")
+    }
+}
+
+impl std::error::Error for Unpicked {}
+
+impl ser::Error for Unpicked {
+    fn custom<T: std::fmt::Display>(_: T) -> Self {
+        Self
+    }
+}
+
+macro_rules! scalars {
+    ($result:expr; $($method:ident: $ty:ty),* $(,)?) => {
+        $(
+            fn $method(self, _: $ty) -> Result<Self::Ok, Self::Error> {
+                $result
+            }
+        )*
+    };
+}
+
+/// Remaining paths of the fields to pick, with their slots.
+struct Picker<'a> {
+    targets: Vec<(&'a str, &'a mut Option<String>)>,
+}
+
+impl Picker<'_> {
+    fn pick<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) {
+        let mut nested = vec![];
+        for (path, slot) in self.targets.iter_mut() {
+            let path: &str = *path;
+            match path.split_once('/') {
+                None if path == key => **slot = value.serialize(StrPicker).ok(),
+                Some((head, rest)) if head == key => nested.push((rest, &mut **slot)),
+                _ => {}
+            }
+        }
+        if !nested.is_empty() {
+            let _ = value.serialize(Picker { targets: nested });
+        }
+    }
+}
+
+impl<'a> Serializer for Picker<'a> {
+    type Ok = ();
+    type Error = Unpicked;
+    type SerializeSeq = Impossible<(), Unpicked>;
+    type SerializeTuple = Impossible<(), Unpicked>;
+    type SerializeTupleStruct = Impossible<(), Unpicked>;
+    type SerializeTupleVariant = Impossible<(), Unpicked>;
+    type SerializeMap = MapPicker<'a>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    scalars!(Ok(());
+        serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+        serialize_i64: i64, serialize_u8: u8, serialize_u16: u16, serialize_u32: u32,
+        serialize_u64: u64, serialize_f32: f32, serialize_f64: f64, serialize_char: char,
+        serialize_str: &str, serialize_bytes: &[u8], serialize_unit_struct: &'static str,
+    );
+
+    fn serialize_none(self) -> Result<(), Unpicked> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Unpicked> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Unpicked> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<(), Unpicked> {
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Unpicked> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Unpicked> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapPicker<'a>, Unpicked> {
+        Ok(MapPicker {
+            picker: self,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Unpicked> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self, Unpicked> {
+        Ok(self)
+    }
+}
+
+impl SerializeStruct for Picker<'_> {
+    type Ok = ();
+    type Error = Unpicked;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Unpicked> {
+        self.pick(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Unpicked> {
+        Ok(())
+    }
+}
+
+impl SerializeStructVariant for Picker<'_> {
+    type Ok = ();
+    type Error = Unpicked;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Unpicked> {
+        self.pick(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Unpicked> {
+        Ok(())
+    }
+}
+
+/// Picker of maps, e.g. flattened fields, keys which are not strings are skipped.
+struct MapPicker<'a> {
+    picker: Picker<'a>,
+    key: Option<String>,
+}
+
+impl SerializeMap for MapPicker<'_> {
+    type Ok = ();
+    type Error = Unpicked;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Unpicked> {
+        self.key = key.serialize(StrPicker).ok();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Unpicked> {
+        if let Some(key) = self.key.take() {
+            self.picker.pick(&key, value);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Unpicked> {
+        Ok(())
+    }
+}
+
+/// Takes a string, or the name of a unit variant like an enum of event types.
+struct StrPicker;
+
+impl Serializer for StrPicker {
+    type Ok = String;
+    type Error = Unpicked;
+    type SerializeSeq = Impossible<String, Unpicked>;
+    type SerializeTuple = Impossible<String, Unpicked>;
+    type SerializeTupleStruct = Impossible<String, Unpicked>;
+    type SerializeTupleVariant = Impossible<String, Unpicked>;
+    type SerializeMap = Impossible<String, Unpicked>;
+    type SerializeStruct = Impossible<String, Unpicked>;
+    type SerializeStructVariant = Impossible<String, Unpicked>;
+
+    scalars!(Err(Unpicked);
+        serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+        serialize_i64: i64, serialize_u8: u8, serialize_u16: u16, serialize_u32: u32,
+        serialize_u64: u64, serialize_f32: f32, serialize_f64: f64, serialize_bytes: &[u8],
+        serialize_unit_struct: &'static str,
+    );
+
+    fn serialize_char(self, v: char) -> Result<String, Unpicked> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, Unpicked> {
+        Ok(v.to_owned())
+    }
+
+    fn serialize_none(self) -> Result<String, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, Unpicked> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<String, Unpicked> {
+        Ok(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<String, Unpicked> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<String, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Unpicked> {
+        Err(Unpicked)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Unpicked> {
+        Err(Unpicked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Kind {
+        Message,
+    }
+
+    #[derive(Serialize)]
+    struct SelfInfo {
+        platform: String,
+        user_id: String,
+    }
+
+    #[derive(Serialize)]
+    struct Event {
+        id: String,
+        #[serde(rename = "type")]
+        kind: Kind,
+        #[serde(rename = "self")]
+        self_info: Option<SelfInfo>,
+        #[serde(flatten)]
+        extra: BTreeMap<String, serde_json::Value>,
+    }
+
+    const PATHS: [&str; 5] = ["type", "detail_type", "self/user_id", "time", "absent"];
+
+    #[test]
+    fn picks_typed_fields() {
+        let event = Event {
+            id: "1".to_owned(),
+            kind: Kind::Message,
+            self_info: Some(SelfInfo {
+                platform: "qq".to_owned(),
+                user_id: "bot".to_owned(),
+            }),
+            extra: BTreeMap::from([
+                ("detail_type".to_owned(), json!("group")),
+                ("time".to_owned(), json!(1.5)),
+            ]),
+        };
+        let [kind, detail, self_id, time, absent] = pick_strings(&event, PATHS);
+        assert_eq!(kind.as_deref(), Some("message"));
+        assert_eq!(detail.as_deref(), Some("group"));
+        assert_eq!(self_id.as_deref(), Some("bot"));
+        assert_eq!(time, None);
+        assert_eq!(absent, None);
+    }
+
+    #[test]
+    fn picks_json_fields() {
+        let event = json!({
+            "type": "message",
+            "detail_type": "private",
+            "self": {"platform": "qq", "user_id": "bot"},
+            "message": [{"type": "text", "data": {"text": "hi"}}],
+        });
+        let [kind, detail, self_id, ..] = pick_strings(&event, PATHS);
+        assert_eq!(kind.as_deref(), Some("message"));
+        assert_eq!(detail.as_deref(), Some("private"));
+        assert_eq!(self_id.as_deref(), Some("bot"));
+    }
+}
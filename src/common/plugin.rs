@@ -1,13 +1,8 @@
-use std::{
-    future::Future,
-    ops::{Deref, DerefMut},
-    pin::Pin,
-    sync::Arc,
-};
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use crate::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PluginInfo {
     pub id: String,
     pub name: String,
@@ -131,11 +126,13 @@ impl<T: OBEventSelector> SelectorExt for T {
 #[plugin_api(
     dyn_t = CarolinaPluginDyn,
     dyn_wrap = plugin::DynPlugin,
+    gen_dyn,
+    dyn_param(G = Box<dyn GlobalContextDyn>, EC = DynEventContext),
 )]
 mod caro_plugin {
     use crate::PluginInfo;
     use crate::{APICall, APIError, APIResult, PluginContext, PluginRid};
-    use crate::{EventContextTrait, GlobalContext};
+    use crate::{DynEventContext, EventContextTrait, GlobalContext, GlobalContextDyn};
     use std::future;
     use std::future::Future;
 
@@ -161,6 +158,21 @@ mod caro_plugin {
             async { Ok(()) }
         }
 
+        /// Called after the host enables the plugin again, plugins are enabled initially.
+        fn on_enable(
+            &mut self,
+        ) -> impl Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + '_ {
+            async { Ok(()) }
+        }
+
+        /// Called after the host disables the plugin, it receives no events and api calls until
+        /// enabled again.
+        fn on_disable(
+            &mut self,
+        ) -> impl Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + '_ {
+            async { Ok(()) }
+        }
+
         fn subscribe_events(&mut self) -> impl Future<Output = Vec<Subscribe>> + Send + '_ {
             future::ready(vec![])
         }
@@ -199,106 +211,3 @@ pub type PinBoxFut<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 pub type PinBoxResult<'a, T> = PinBoxFut<'a, Result<T, Box<dyn StdErr>>>;
 pub type PinBoxAPIResult<'a> = PinBoxFut<'a, APIResult>;
 pub type SharedEvent = Arc<RawEvent>;
-
-pub trait CarolinaPluginDyn: Send + Sync + 'static {
-    fn info(&self) -> PluginInfo;
-
-    fn init(&mut self, context: PluginContext<Box<dyn GlobalContextDyn>>) -> PinBoxResult<()>;
-
-    fn post_init(&mut self, context: PluginContext<Box<dyn GlobalContextDyn>>) -> PinBoxResult<()>;
-
-    fn subscribe_events(&mut self) -> PinBoxFut<Vec<Subscribe>>;
-
-    fn handle_event(
-        &self,
-        event: SharedEvent,
-        context: DynEventContext,
-    ) -> PinBoxResult<EventState>;
-
-    fn handle_api_call(&self, src: PluginRid, call: APICall) -> PinBoxAPIResult;
-
-    fn deinit(self) -> PinBoxResult<'static, ()>;
-}
-
-impl<T: CarolinaPlugin + 'static> CarolinaPluginDyn for T {
-    fn info(&self) -> PluginInfo {
-        self.info()
-    }
-
-    fn init(&mut self, context: PluginContext<Box<dyn GlobalContextDyn>>) -> PinBoxResult<()> {
-        Box::pin(self.init(context))
-    }
-
-    fn post_init(&mut self, context: PluginContext<Box<dyn GlobalContextDyn>>) -> PinBoxResult<()> {
-        Box::pin(self.post_init(context))
-    }
-
-    fn subscribe_events(&mut self) -> PinBoxFut<Vec<Subscribe>> {
-        Box::pin(self.subscribe_events())
-    }
-
-    fn handle_event(
-        &self,
-        event: SharedEvent,
-        context: DynEventContext,
-    ) -> PinBoxResult<EventState> {
-        Box::pin(self.handle_event(event, context))
-    }
-
-    fn handle_api_call(&self, src: PluginRid, call: APICall) -> PinBoxAPIResult {
-        Box::pin(self.handle_api_call(src, call))
-    }
-
-    fn deinit(self) -> PinBoxResult<'static, ()> {
-        Box::pin(self.deinit())
-    }
-}
-
-// For dynamic dispatching
-impl<'a> CarolinaPlugin for Box<dyn CarolinaPluginDyn + 'a> {
-    fn info(&self) -> PluginInfo {
-        self.deref().info()
-    }
-
-    fn init<G: GlobalContext>(
-        &mut self,
-        context: PluginContext<G>,
-    ) -> impl Future<Output = StdResult<()>> + Send + '_ {
-        self.deref_mut().init(context.into_dyn())
-    }
-
-    fn post_init<G: GlobalContext>(
-        &mut self,
-        context: PluginContext<G>,
-    ) -> impl Future<Output = Result<(), Box<dyn StdErr>>> + Send + '_ {
-        self.deref_mut().post_init(context.into_dyn())
-    }
-
-    fn subscribe_events(&mut self) -> impl Future<Output = Vec<Subscribe>> + Send + '_ {
-        self.deref_mut().subscribe_events()
-    }
-
-    fn handle_api_call(
-        &self,
-        src: PluginRid,
-        call: APICall,
-    ) -> impl Future<Output = APIResult> + Send + '_ {
-        self.deref().handle_api_call(src, call)
-    }
-
-    fn deinit(self) -> impl Future<Output = StdResult<()>> + Send {
-        CarolinaPluginDyn::deinit(self)
-    }
-
-    fn handle_event<EC>(
-        &self,
-        event: SharedEvent,
-        context: EC,
-    ) -> impl Future<Output = StdResult<EventState>> + Send + '_
-    where
-        EC: EventContextTrait + Send + 'static,
-    {
-        self.deref()
-            .handle_event(event, DynEventContext::from(context.into_inner()))
-    }
-}
@@ -0,0 +1,99 @@
+/// Declares the link-time registry entry of a plugin trait, used by code generated by
+/// [`plugin_api`](crate::plugin_api).
+///
+/// Registry macros expand to nothing without feature `registry`, so plugins can call
+/// `export_plugin!` either way.
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plugin_registry {
+    ($vis:vis $entry:ident, $dyn_ty:ty) => {
+        /// Compile-time plugin registered by linking its crate, see [`iter`](Self::iter).
+        $vis struct $entry {
+            /// Module path where the plugin is exported.
+            pub module: &'static str,
+            /// Plugin type as written in the export macro.
+            pub type_name: &'static str,
+            make_static: fn() -> ::std::boxed::Box<dyn ::std::any::Any>,
+            into_dyn: fn(::std::boxed::Box<dyn ::std::any::Any>) -> ::std::boxed::Box<$dyn_ty>,
+        }
+
+        impl $entry {
+            #[doc(hidden)]
+            pub const fn new(
+                module: &'static str,
+                type_name: &'static str,
+                make_static: fn() -> ::std::boxed::Box<dyn ::std::any::Any>,
+                into_dyn: fn(::std::boxed::Box<dyn ::std::any::Any>) -> ::std::boxed::Box<$dyn_ty>,
+            ) -> Self {
+                Self {
+                    module,
+                    type_name,
+                    make_static,
+                    into_dyn,
+                }
+            }
+
+            /// Iterates plugins exported by all linked crates, in no particular order.
+            pub fn iter() -> impl ::std::iter::Iterator<Item = &'static Self> {
+                $crate::__private::inventory::iter::<Self>.into_iter()
+            }
+
+            /// Creates the plugin by its `Default` impl, wrapped by `dyn_wrap` of the trait like
+            /// plugins loaded from dynamic libraries.
+            pub fn make(&self) -> ::std::boxed::Box<$dyn_ty> {
+                (self.into_dyn)((self.make_static)())
+            }
+
+            /// Creates the plugin by its `Default` impl unwrapped, e.g. for a static variant of
+            /// the dispatching enum.
+            pub fn make_static(&self) -> ::std::boxed::Box<dyn ::std::any::Any> {
+                (self.make_static)()
+            }
+
+            /// Creates all registered plugins, as static variants of the dispatching enum if it
+            /// lists their crates, otherwise in the boxed form, e.g. its dynamic variant.
+            pub fn load_all<T>() -> ::std::vec::Vec<T>
+            where
+                T: ::std::convert::From<::std::boxed::Box<$dyn_ty>>
+                    + ::std::convert::TryFrom<
+                        ::std::boxed::Box<dyn ::std::any::Any>,
+                        Error = ::std::boxed::Box<dyn ::std::any::Any>,
+                    >,
+            {
+                Self::iter()
+                    .map(|entry| {
+                        T::try_from((entry.make_static)())
+                            .unwrap_or_else(|plug| (entry.into_dyn)(plug).into())
+                    })
+                    .collect()
+            }
+        }
+
+        $crate::__private::inventory::collect!($entry);
+    };
+}
+
+#[cfg(not(feature = "registry"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plugin_registry {
+    ($($tt:tt)*) => {};
+}
+
+/// Submits a registry entry, called by the export macro in plugin crates.
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __submit_plugin {
+    ($($entry:tt)*) => {
+        $crate::__private::inventory::submit! { $($entry)* }
+    };
+}
+
+#[cfg(not(feature = "registry"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __submit_plugin {
+    ($($tt:tt)*) => {};
+}
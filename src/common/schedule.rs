@@ -0,0 +1,262 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use fxhash::FxHashMap;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use super::*;
+
+/// Shortest period of an interval schedule.
+pub const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// When a scheduled task runs.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Run once after the delay.
+    Delay(Duration),
+    /// Run repeatedly with fixed period, the first run happens after one period. Period shorter
+    /// than [`MIN_INTERVAL`] is raised to it.
+    Interval(Duration),
+    /// Run at every time matched by the cron expression, in local time.
+    #[cfg(feature = "cron")]
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn delay(delay: Duration) -> Self {
+        Self::Delay(delay)
+    }
+
+    /// Period shorter than [`MIN_INTERVAL`] is raised to it.
+    pub fn interval(period: Duration) -> Self {
+        Self::Interval(period.max(MIN_INTERVAL))
+    }
+
+    /// Parses a cron expression with seconds field, e.g. `0 0 4 * * *` for 4:00 every day.
+    #[cfg(feature = "cron")]
+    pub fn cron(expr: &str) -> Result<Self, cron::error::Error> {
+        Ok(Self::Cron(Box::new(expr.parse()?)))
+    }
+
+    async fn run<F, FR>(self, mut task: F)
+    where
+        F: FnMut() -> FR + Send + 'static,
+        FR: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Schedule::Delay(delay) => {
+                time::sleep(delay).await;
+                task().await
+            }
+            Schedule::Interval(period) => {
+                // Zero period makes tokio panic inside the task
+                let period = period.max(MIN_INTERVAL);
+                let mut interval = time::interval_at(Instant::now() + period, period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    interval.tick().await;
+                    task().await
+                }
+            }
+            #[cfg(feature = "cron")]
+            Schedule::Cron(schedule) => {
+                for next in schedule.upcoming(chrono::Local) {
+                    let wait = (next - chrono::Local::now()).to_std().unwrap_or_default();
+                    time::sleep(wait).await;
+                    task().await
+                }
+            }
+        }
+    }
+}
+
+/// Handle of a scheduled task.
+///
+/// The handle only captures closures, so it stays usable from host side even when the task is
+/// spawned inside a dynamically loaded plugin.
+#[derive(Clone)]
+pub struct TaskHandle {
+    cancel: Arc<dyn Fn() + Send + Sync>,
+    finished: Arc<dyn Fn() -> bool + Send + Sync>,
+}
+
+impl TaskHandle {
+    pub fn new(
+        cancel: impl Fn() + Send + Sync + 'static,
+        finished: impl Fn() -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            cancel: Arc::new(cancel),
+            finished: Arc::new(finished),
+        }
+    }
+
+    pub fn from_tokio<T>(handle: &tokio::task::JoinHandle<T>) -> Self {
+        let abort = handle.abort_handle();
+        let finished = abort.clone();
+        Self::new(move || abort.abort(), move || finished.is_finished())
+    }
+
+    pub fn cancel(&self) {
+        (self.cancel)()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        (self.finished)()
+    }
+}
+
+impl std::fmt::Debug for TaskHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+/// Spawns the task on current tokio runtime.
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime.
+pub fn spawn_scheduled<F, FR>(schedule: Schedule, task: F) -> TaskHandle
+where
+    F: FnMut() -> FR + Send + 'static,
+    FR: Future<Output = ()> + Send + 'static,
+{
+    TaskHandle::from_tokio(&tokio::spawn(schedule.run(task)))
+}
+
+/// Host side registry of scheduled tasks, grouped by plugin.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<FxHashMap<PluginRid, Vec<TaskHandle>>>,
+}
+
+impl TaskRegistry {
+    pub fn register(&self, rid: PluginRid, task: TaskHandle) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let plugin_tasks = tasks.entry(rid).or_default();
+        plugin_tasks.retain(|t| !t.is_finished());
+        plugin_tasks.push(task);
+    }
+
+    /// Count of tasks of the plugin that are still running.
+    pub fn running(&self, rid: PluginRid) -> usize {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(&rid)
+            .map(|tasks| tasks.iter().filter(|t| !t.is_finished()).count())
+            .unwrap_or_default()
+    }
+
+    /// Cancels all tasks of the plugin, should be called when the plugin is deinitialized or
+    /// reloaded. Returns the count of cancelled tasks.
+    pub fn cancel_plugin(&self, rid: PluginRid) -> usize {
+        let tasks = self.tasks.lock().unwrap().remove(&rid).unwrap_or_default();
+        cancel_tasks(tasks)
+    }
+
+    pub fn cancel_all(&self) -> usize {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        tasks.into_values().map(cancel_tasks).sum()
+    }
+}
+
+fn cancel_tasks(tasks: Vec<TaskHandle>) -> usize {
+    tasks
+        .into_iter()
+        .filter(|t| !t.is_finished())
+        .inspect(TaskHandle::cancel)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Spawns a task counting its runs.
+    fn counting(schedule: Schedule) -> (TaskHandle, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let handle = spawn_scheduled(schedule, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {}
+        });
+        (handle, runs)
+    }
+
+    #[tokio::test]
+    async fn delay_runs_once() {
+        let (handle, runs) = counting(Schedule::delay(Duration::from_millis(10)));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(handle.is_finished());
+    }
+
+    #[tokio::test]
+    async fn interval_repeats_until_cancelled() {
+        let (handle, runs) = counting(Schedule::interval(Duration::from_millis(10)));
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+
+        handle.cancel();
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(handle.is_finished());
+        let cancelled_at = runs.load(Ordering::SeqCst);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), cancelled_at);
+    }
+
+    #[tokio::test]
+    async fn zero_interval_is_clamped() {
+        assert!(matches!(
+            Schedule::interval(Duration::ZERO),
+            Schedule::Interval(period) if period == MIN_INTERVAL
+        ));
+
+        // Built directly, so only `run` clamps it
+        let (handle, runs) = counting(Schedule::Interval(Duration::ZERO));
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(!handle.is_finished());
+        assert!(runs.load(Ordering::SeqCst) > 0);
+        handle.cancel();
+    }
+
+    #[cfg(feature = "cron")]
+    #[tokio::test]
+    async fn cron_fires_every_second() {
+        let (handle, runs) = counting(Schedule::cron("* * * * * *").unwrap());
+        time::sleep(Duration::from_millis(2100)).await;
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+        handle.cancel();
+    }
+
+    #[tokio::test]
+    async fn registry_cancels_tasks_of_plugin() {
+        let registry = TaskRegistry::default();
+        let (first, _) = counting(Schedule::interval(Duration::from_secs(60)));
+        let (second, _) = counting(Schedule::delay(Duration::from_secs(60)));
+        let (other, _) = counting(Schedule::interval(Duration::from_secs(60)));
+        registry.register(PluginRid::new(1), first.clone());
+        registry.register(PluginRid::new(1), second.clone());
+        registry.register(PluginRid::new(2), other.clone());
+        assert_eq!(registry.running(PluginRid::new(1)), 2);
+
+        assert_eq!(registry.cancel_plugin(PluginRid::new(1)), 2);
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(first.is_finished() && second.is_finished());
+        assert_eq!(registry.running(PluginRid::new(1)), 0);
+        assert_eq!(registry.running(PluginRid::new(2)), 1);
+
+        assert_eq!(registry.cancel_all(), 1);
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(other.is_finished());
+    }
+}
@@ -0,0 +1,263 @@
+use std::{collections::BTreeSet, sync::RwLock};
+
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+use crate::RawEvent;
+
+/// A chat or sender an event may come from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatScope {
+    /// App by its runtime id, [`ScopeRegistry`] persists it as [`AppKey`](Self::AppKey).
+    App(AppRid),
+    /// App by the stable key it is bound to, see [`ScopeRegistry::bind_app`].
+    AppKey(String),
+    Group(String),
+    Guild(String),
+    Channel {
+        guild_id: String,
+        channel_id: String,
+    },
+    User(String),
+}
+
+impl ChatScope {
+    /// `AppKey` scopes never match without the key of the app, see [`ScopeRegistry::allows`].
+    pub fn matches(&self, app: AppRid, chat: &EventChat) -> bool {
+        self.matches_in(app, None, chat)
+    }
+
+    fn matches_in(&self, app: AppRid, app_key: Option<&str>, chat: &EventChat) -> bool {
+        let eq = |id: &str, field: &Option<String>| field.as_deref() == Some(id);
+        match self {
+            Self::App(rid) => *rid == app,
+            Self::AppKey(key) => app_key == Some(key),
+            Self::Group(id) => eq(id, &chat.group_id),
+            Self::Guild(id) => eq(id, &chat.guild_id),
+            Self::Channel {
+                guild_id,
+                channel_id,
+            } => eq(guild_id, &chat.guild_id) && eq(channel_id, &chat.channel_id),
+            Self::User(id) => eq(id, &chat.user_id),
+        }
+    }
+}
+
+/// Allow and deny lists of a plugin.
+///
+/// Events matching any denied scope are dropped. If any scope is allowed, events must also
+/// match one of them, otherwise all events are allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScopeRules {
+    #[serde(default)]
+    pub allow: BTreeSet<ChatScope>,
+    #[serde(default)]
+    pub deny: BTreeSet<ChatScope>,
+}
+
+impl ScopeRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, scope: ChatScope) -> Self {
+        self.allow.insert(scope);
+        self
+    }
+
+    pub fn deny(mut self, scope: ChatScope) -> Self {
+        self.deny.insert(scope);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Rules with `App` scopes replaced by the keys of the apps, whose runtime ids mean nothing
+    /// after restart.
+    fn persistent(&self, app_keys: &FxHashMap<AppRid, String>) -> Result<Self, ScopeError> {
+        let persist = |scope: &ChatScope| match scope {
+            ChatScope::App(rid) => app_keys
+                .get(rid)
+                .map(|key| ChatScope::AppKey(key.clone()))
+                .ok_or(ScopeError::UnboundApp(*rid)),
+            scope => Ok(scope.clone()),
+        };
+        Ok(Self {
+            allow: self.allow.iter().map(persist).collect::<Result<_, _>>()?,
+            deny: self.deny.iter().map(persist).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn allows(&self, app: AppRid, chat: &EventChat) -> bool {
+        self.allows_in(app, None, chat)
+    }
+
+    fn allows_in(&self, app: AppRid, app_key: Option<&str>, chat: &EventChat) -> bool {
+        let matches = |s: &ChatScope| s.matches_in(app, app_key, chat);
+        if self.deny.iter().any(matches) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(matches)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScopeError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("app {0} is not bound to a key, its scope can not be persisted")]
+    UnboundApp(AppRid),
+}
+
+/// Host side scope rules of all plugins, keyed by plugin id and persisted in a [`KvStore`].
+///
+/// Host should [`bind_app`](Self::bind_app) each app once connected, so `App` scopes are
+/// persisted by the app's key, and check [`allows_event`](Self::allows_event) before calling
+/// `handle_event`.
+#[derive(Default)]
+pub struct ScopeRegistry {
+    rules: RwLock<FxHashMap<String, ScopeRules>>,
+    app_keys: RwLock<FxHashMap<AppRid, String>>,
+    store: Option<KvStore>,
+}
+
+impl ScopeRegistry {
+    /// Loads rules from the store, changes are saved into it.
+    pub async fn open(store: KvStore) -> Result<Self, StorageError> {
+        let rules = store.scan_prefix::<ScopeRules>("").await?;
+        Ok(Self {
+            rules: RwLock::new(rules.into_iter().collect()),
+            app_keys: Default::default(),
+            store: Some(store),
+        })
+    }
+
+    /// Creates registry without persistence.
+    pub fn memory() -> Self {
+        Self::default()
+    }
+
+    pub fn rules(&self, plugin_id: &str) -> ScopeRules {
+        self.rules
+            .read()
+            .unwrap()
+            .get(plugin_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Binds the app to a key stable across restarts, e.g. its platform and self id, which
+    /// `App` scopes of the app are persisted by.
+    pub fn bind_app(&self, app: AppRid, key: impl Into<String>) {
+        self.app_keys.write().unwrap().insert(app, key.into());
+    }
+
+    /// Removes the key of a disconnected app.
+    pub fn unbind_app(&self, app: AppRid) {
+        self.app_keys.write().unwrap().remove(&app);
+    }
+
+    /// Replaces the plugin's rules, empty rules remove them.
+    ///
+    /// Fails with [`ScopeError::UnboundApp`] if the rules have an `App` scope of an app which is
+    /// not bound, as it can not be persisted.
+    pub async fn set_rules(&self, plugin_id: &str, rules: ScopeRules) -> Result<(), ScopeError> {
+        if let Some(store) = &self.store {
+            let persistent = rules.persistent(&self.app_keys.read().unwrap())?;
+            if persistent.is_empty() {
+                store.remove(plugin_id).await?;
+            } else {
+                store.set(plugin_id, &persistent).await?;
+            }
+        }
+
+        let mut all = self.rules.write().unwrap();
+        if rules.is_empty() {
+            all.remove(plugin_id);
+        } else {
+            all.insert(plugin_id.to_owned(), rules);
+        }
+        Ok(())
+    }
+
+    /// Checks the plugin's rules, `AppKey` scopes match apps bound to the key.
+    pub fn allows(&self, plugin_id: &str, app: AppRid, chat: &EventChat) -> bool {
+        let rules = self.rules.read().unwrap();
+        let Some(rules) = rules.get(plugin_id) else {
+            return true;
+        };
+        let app_keys = self.app_keys.read().unwrap();
+        rules.allows_in(app, app_keys.get(&app).map(String::as_str), chat)
+    }
+
+    pub fn allows_event(&self, plugin_id: &str, app: AppRid, event: &RawEvent) -> bool {
+        if self.rules.read().unwrap().is_empty() {
+            return true;
+        }
+        self.allows(plugin_id, app, &EventChat::from_event(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn group(id: &str) -> EventChat {
+        EventChat {
+            group_id: Some(id.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn app_scopes_persist_by_key() {
+        let backend = Arc::new(MemoryBackend::default());
+        let (app, other) = (AppRid::new(1), AppRid::new(2));
+        let registry = ScopeRegistry::open(KvStore::open(backend.clone(), "scopes").await.unwrap())
+            .await
+            .unwrap();
+        let rules = ScopeRules::new().allow(ChatScope::App(app));
+        assert!(matches!(
+            registry.set_rules("echo", rules.clone()).await,
+            Err(ScopeError::UnboundApp(rid)) if rid == app
+        ));
+
+        registry.bind_app(app, "qq:bot");
+        registry.set_rules("echo", rules).await.unwrap();
+        assert!(registry.allows("echo", app, &group("1")));
+        assert!(!registry.allows("echo", other, &group("1")));
+
+        // After restart the app gets a new rid
+        let registry = ScopeRegistry::open(KvStore::open(backend, "scopes").await.unwrap())
+            .await
+            .unwrap();
+        let restarted = AppRid::new(3);
+        assert_eq!(
+            registry.rules("echo"),
+            ScopeRules::new().allow(ChatScope::AppKey("qq:bot".to_owned()))
+        );
+        // Still restrictive before the app is bound again
+        assert!(!registry.allows("echo", restarted, &group("1")));
+        registry.bind_app(restarted, "qq:bot");
+        assert!(registry.allows("echo", restarted, &group("1")));
+        assert!(!registry.allows("echo", other, &group("1")));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let app = AppRid::new(1);
+        let rules = ScopeRules::new()
+            .allow(ChatScope::App(app))
+            .deny(ChatScope::Group("2".to_owned()));
+        assert!(rules.allows(app, &group("1")));
+        assert!(!rules.allows(app, &group("2")));
+        assert!(!rules.allows(AppRid::new(2), &group("1")));
+        assert!(ScopeRules::new().allows(app, &group("2")));
+    }
+}
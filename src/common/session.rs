@@ -0,0 +1,194 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::sync::oneshot;
+
+use super::*;
+use crate::RawEvent;
+
+/// Events a session waits for, from the same app and chat.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub app: AppRid,
+    /// Chat fields, which must all equal those of the event.
+    pub chat: EventChat,
+    /// Further filter, message events by default.
+    pub subscribe: Subscribe,
+}
+
+impl Session {
+    /// Next message from the same user in the same chat as the event.
+    pub fn reply_to(app: AppRid, event: &RawEvent) -> Self {
+        Self {
+            app,
+            chat: EventChat::from_event(event),
+            subscribe: Subscribe::new("message", None::<String>),
+        }
+    }
+
+    pub fn subscribe(mut self, subscribe: Subscribe) -> Self {
+        self.subscribe = subscribe;
+        self
+    }
+
+    pub fn matches(&self, app: AppRid, event: &serde_json::Value) -> bool {
+        self.app == app
+            && EventChat::from_json(event) == self.chat
+            && self.subscribe.matches(app, event)
+    }
+}
+
+struct Waiter {
+    id: u64,
+    rid: PluginRid,
+    session: Session,
+    tx: oneshot::Sender<SharedEvent>,
+}
+
+#[derive(Default)]
+struct SessionInner {
+    next_id: AtomicU64,
+    waiters: Mutex<Vec<Waiter>>,
+}
+
+/// Host side waiters of sessions.
+///
+/// Host should [`offer`](Self::offer) every event before dispatching it to subscribers, an
+/// event taken by a waiter is intercepted.
+///
+/// Cloning is cheap, clones share the same waiters.
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    inner: Arc<SessionInner>,
+}
+
+/// Removes the waiter when the waiting future is dropped, e.g. on timeout.
+struct WaiterGuard {
+    inner: Arc<SessionInner>,
+    id: u64,
+}
+
+impl Drop for WaiterGuard {
+    fn drop(&mut self) {
+        let mut waiters = self.inner.waiters.lock().unwrap();
+        waiters.retain(|w| w.id != self.id);
+    }
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits for the next event of the session, `None` on timeout or if the waiter is cancelled.
+    ///
+    /// The waiter is removed once the future completes or is dropped. Its timer is driven by the
+    /// runtime polling the future, e.g. a plugin's own, which must have the time driver enabled.
+    pub fn wait(
+        &self,
+        rid: PluginRid,
+        session: Session,
+        timeout: Duration,
+    ) -> impl Future<Output = Option<SharedEvent>> + Send + 'static {
+        let (tx, rx) = oneshot::channel();
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.waiters.lock().unwrap().push(Waiter {
+            id,
+            rid,
+            session,
+            tx,
+        });
+
+        let guard = WaiterGuard {
+            inner: self.inner.clone(),
+            id,
+        };
+        async move {
+            let _guard = guard;
+            tokio::time::timeout(timeout, rx).await.ok()?.ok()
+        }
+    }
+
+    /// Offers the event to waiters in the order they started waiting, returns `true` if one
+    /// took it.
+    pub fn offer(&self, app: AppRid, event: &SharedEvent) -> bool {
+        let mut waiters = self.inner.waiters.lock().unwrap();
+        if waiters.is_empty() {
+            return false;
+        }
+        let json = event_json(event);
+        while let Some(i) = waiters.iter().position(|w| w.session.matches(app, &json)) {
+            let waiter = waiters.remove(i);
+            // The waiting future may be dropped but not yet removed
+            if waiter.tx.send(event.clone()).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Cancels all waiters of the plugin, e.g. when it is disabled or deinitialized.
+    pub fn cancel_plugin(&self, rid: PluginRid) {
+        self.inner.waiters.lock().unwrap().retain(|w| w.rid != rid);
+    }
+
+    /// Count of waiting sessions.
+    pub fn waiting(&self) -> usize {
+        self.inner.waiters.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(group_id: &str, user_id: &str) -> SharedEvent {
+        let event = json!({"id": "1", "type": "message", "detail_type": "group",
+            "group_id": group_id, "user_id": user_id, "message": []});
+        Arc::new(serde_json::from_value(event).unwrap())
+    }
+
+    #[tokio::test]
+    async fn offer_intercepts_matching_chat() {
+        let registry = SessionRegistry::new();
+        let app = AppRid::new(1);
+        let session = Session::reply_to(app, &message("g", "alice"));
+        let waiting = registry.wait(PluginRid::new(1), session, Duration::from_secs(5));
+        assert_eq!(registry.waiting(), 1);
+
+        assert!(!registry.offer(app, &message("g", "bob")));
+        assert!(!registry.offer(AppRid::new(2), &message("g", "alice")));
+        let reply = message("g", "alice");
+        assert!(registry.offer(app, &reply));
+        assert!(Arc::ptr_eq(&waiting.await.unwrap(), &reply));
+        assert_eq!(registry.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn wait_times_out() {
+        let registry = SessionRegistry::new();
+        let session = Session::reply_to(AppRid::new(1), &message("g", "alice"));
+        let waiting = registry.wait(PluginRid::new(1), session, Duration::from_millis(10));
+        assert!(waiting.await.is_none());
+        assert_eq!(registry.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_waiter_is_removed() {
+        let registry = SessionRegistry::new();
+        let app = AppRid::new(1);
+        let session = Session::reply_to(app, &message("g", "alice"));
+        let waiting = registry.wait(PluginRid::new(1), session, Duration::from_secs(5));
+        assert_eq!(registry.waiting(), 1);
+        drop(waiting);
+        assert_eq!(registry.waiting(), 0);
+        assert!(!registry.offer(app, &message("g", "alice")));
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Notify, time};
+
+use super::*;
+
+#[derive(Default)]
+struct InFlightState {
+    closed: AtomicBool,
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+impl InFlightState {
+    fn leave(&self) {
+        if self.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+
+    async fn drained(&self) {
+        loop {
+            let mut notified = pin!(self.idle.notified());
+            notified.as_mut().enable();
+            if self.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Marks an event handling or api call in flight, until dropped.
+pub struct InFlightGuard {
+    state: Arc<InFlightState>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.state.leave();
+    }
+}
+
+#[derive(Debug)]
+pub enum ShutdownFailure {
+    Error(String),
+    TimedOut,
+    Panicked(PluginPanicked),
+}
+
+impl Display for ShutdownFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error(e) => write!(f, "{e}"),
+            Self::TimedOut => write!(f, "timed out"),
+            Self::Panicked(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PluginShutdownFailure {
+    pub rid: PluginRid,
+    pub id: String,
+    pub failure: ShutdownFailure,
+}
+
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// Count of event handlings and api calls still running when the drain deadline passed.
+    pub in_flight_left: usize,
+    /// Plugins whose `deinit` failed.
+    pub deinit_failed: Vec<PluginShutdownFailure>,
+    /// Plugins whose connection close callbacks failed.
+    pub close_failed: Vec<PluginShutdownFailure>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.in_flight_left == 0 && self.deinit_failed.is_empty() && self.close_failed.is_empty()
+    }
+}
+
+/// Host side coordinator shutting down all plugins gracefully.
+///
+/// On [`shutdown`](Self::shutdown) it
+/// 1. stops intake, [`begin`](Self::begin) returns `None` afterwards;
+/// 2. waits for in-flight event handlings and api calls until the drain timeout;
+/// 3. in reverse of the given order, cancels each plugin's scheduled tasks, runs its `deinit`
+///    and then its connections' close callbacks, each with the deinit timeout.
+///
+/// `DynPlugin` runtimes are dropped along with their `deinit`.
+pub struct ShutdownCoordinator {
+    in_flight: Arc<InFlightState>,
+    close_callbacks: Mutex<Vec<(PluginRid, BoxedCallbackFn<'static>)>>,
+    tasks: Option<Arc<TaskRegistry>>,
+    drain_timeout: Duration,
+    deinit_timeout: Duration,
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self {
+            in_flight: Default::default(),
+            close_callbacks: Default::default(),
+            tasks: None,
+            drain_timeout: Duration::from_secs(10),
+            deinit_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Max time waiting for in-flight calls, 10 seconds by default.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Max time of each plugin's `deinit` and close callback, 5 seconds by default.
+    pub fn deinit_timeout(mut self, timeout: Duration) -> Self {
+        self.deinit_timeout = timeout;
+        self
+    }
+
+    /// Cancels plugin's scheduled tasks before its `deinit`.
+    pub fn with_tasks(mut self, tasks: Arc<TaskRegistry>) -> Self {
+        self.tasks = Some(tasks);
+        self
+    }
+
+    /// Marks an event handling or api call in flight, `None` if shutting down, in which case the
+    /// host should drop the event or reject the call.
+    pub fn begin(&self) -> Option<InFlightGuard> {
+        self.in_flight.count.fetch_add(1, Ordering::AcqRel);
+        let guard = InFlightGuard {
+            state: self.in_flight.clone(),
+        };
+        if self.in_flight.closed.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(guard)
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        !self.in_flight.closed.load(Ordering::Acquire)
+    }
+
+    /// Keeps the close callback passed to [`GlobalContext::register_connect`], it runs after the
+    /// plugin's `deinit`.
+    pub fn add_close_callback(&self, rid: PluginRid, callback: BoxedCallbackFn<'static>) {
+        self.close_callbacks.lock().unwrap().push((rid, callback));
+    }
+
+    /// Shuts down the plugins, which are given in dependency order, i.e. dependencies first.
+    pub async fn shutdown<P: CarolinaPlugin>(
+        &self,
+        plugins: Vec<(PluginRid, P)>,
+    ) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+
+        self.in_flight.closed.store(true, Ordering::Release);
+        if time::timeout(self.drain_timeout, self.in_flight.drained())
+            .await
+            .is_err()
+        {
+            report.in_flight_left = self.in_flight.count.load(Ordering::Acquire);
+            log::warn!(
+                "{} event handlings or api calls still running, shutting down anyway",
+                report.in_flight_left
+            );
+        }
+
+        let mut callbacks = std::mem::take(&mut *self.close_callbacks.lock().unwrap());
+        for (rid, plugin) in plugins.into_iter().rev() {
+            let id = plugin.info().id;
+            if let Some(tasks) = &self.tasks {
+                tasks.cancel_plugin(rid);
+            }
+
+            if let Err(failure) = self.run_step(&id, plugin.deinit()).await {
+                log::error!("failed to deinit plugin `{id}`: {failure}");
+                report.deinit_failed.push(PluginShutdownFailure {
+                    rid,
+                    id: id.clone(),
+                    failure,
+                });
+            }
+
+            let (plugin_callbacks, rest) = callbacks.into_iter().partition(|(r, _)| *r == rid);
+            callbacks = rest;
+            for (_, callback) in plugin_callbacks {
+                if let Err(failure) = self.run_step(&id, callback()).await {
+                    log::error!("failed to close connection of plugin `{id}`: {failure}");
+                    report.close_failed.push(PluginShutdownFailure {
+                        rid,
+                        id: id.clone(),
+                        failure,
+                    });
+                }
+            }
+        }
+
+        // Connections of plugins not given
+        for (rid, callback) in callbacks {
+            let id = rid.to_string();
+            if let Err(failure) = self.run_step(&id, callback()).await {
+                report
+                    .close_failed
+                    .push(PluginShutdownFailure { rid, id, failure });
+            }
+        }
+
+        report
+    }
+
+    async fn run_step<F, E>(&self, id: &str, fut: F) -> Result<(), ShutdownFailure>
+    where
+        F: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        match time::timeout(self.deinit_timeout, catch_plugin_panic(id, fut)).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(e))) => Err(ShutdownFailure::Error(e.to_string())),
+            Ok(Err(panicked)) => Err(ShutdownFailure::Panicked(panicked)),
+            Err(_) => Err(ShutdownFailure::TimedOut),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Deinit {
+        Ok,
+        Fail,
+        Panic,
+        Hang,
+    }
+
+    struct TestPlugin {
+        id: &'static str,
+        deinit: Deinit,
+        order: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl CarolinaPlugin for TestPlugin {
+        fn info(&self) -> PluginInfo {
+            PluginInfoBuilder::new(self.id).build()
+        }
+
+        async fn deinit(self) -> StdResult<()> {
+            self.order.lock().unwrap().push(self.id);
+            match self.deinit {
+                Deinit::Ok => Ok(()),
+                Deinit::Fail => Err("deinit failed".into()),
+                Deinit::Panic => panic!("deinit panicked"),
+                Deinit::Hang => std::future::pending().await,
+            }
+        }
+    }
+
+    fn plugins(
+        steps: Vec<(&'static str, Deinit)>,
+    ) -> (Vec<(PluginRid, TestPlugin)>, Arc<Mutex<Vec<&'static str>>>) {
+        let order = Arc::new(Mutex::new(vec![]));
+        let plugins = steps
+            .into_iter()
+            .zip(1..)
+            .map(|((id, deinit), rid)| {
+                let order = order.clone();
+                (PluginRid::new(rid), TestPlugin { id, deinit, order })
+            })
+            .collect();
+        (plugins, order)
+    }
+
+    #[tokio::test]
+    async fn deinit_in_reverse_order_and_report_failures() {
+        let (plugins, order) = plugins(vec![
+            ("base", Deinit::Ok),
+            ("failing", Deinit::Fail),
+            ("panicking", Deinit::Panic),
+            ("hanging", Deinit::Hang),
+        ]);
+        let coordinator = ShutdownCoordinator::new().deinit_timeout(Duration::from_millis(50));
+        let report = coordinator.shutdown(plugins).await;
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["hanging", "panicking", "failing", "base"]
+        );
+        assert!(!report.is_clean());
+        let failures: Vec<_> = report
+            .deinit_failed
+            .iter()
+            .map(|failure| (failure.id.as_str(), &failure.failure))
+            .collect();
+        assert!(matches!(
+            failures[..],
+            [
+                ("hanging", ShutdownFailure::TimedOut),
+                ("panicking", ShutdownFailure::Panicked(PluginPanicked { message, .. })),
+                ("failing", ShutdownFailure::Error(error)),
+            ] if message == "deinit panicked" && error == "deinit failed"
+        ));
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_in_flight_calls() {
+        let coordinator = ShutdownCoordinator::new().drain_timeout(Duration::from_secs(5));
+        let guard = coordinator.begin().unwrap();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(20)).await;
+            drop(guard);
+        });
+
+        let report = coordinator
+            .shutdown(Vec::<(PluginRid, TestPlugin)>::new())
+            .await;
+        assert!(report.is_clean());
+        assert!(!coordinator.is_accepting());
+        assert!(coordinator.begin().is_none());
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_reports_in_flight_calls() {
+        let coordinator = ShutdownCoordinator::new().drain_timeout(Duration::from_millis(20));
+        let _first = coordinator.begin().unwrap();
+        let _second = coordinator.begin().unwrap();
+
+        let report = coordinator
+            .shutdown(Vec::<(PluginRid, TestPlugin)>::new())
+            .await;
+        assert_eq!(report.in_flight_left, 2);
+        assert!(!report.is_clean());
+    }
+
+    #[tokio::test]
+    async fn tasks_and_close_callbacks_run_with_deinit() {
+        let tasks = Arc::new(TaskRegistry::default());
+        let (plugins, _) = plugins(vec![("plugin", Deinit::Ok)]);
+        let rid = plugins[0].0;
+        let task = spawn_scheduled(Schedule::interval(Duration::from_secs(60)), || async {});
+        tasks.register(rid, task.clone());
+
+        let coordinator = ShutdownCoordinator::new().with_tasks(tasks.clone());
+        let closed = Arc::new(AtomicBool::new(false));
+        let flag = closed.clone();
+        coordinator.add_close_callback(
+            rid,
+            boxed_async_cb(move || async move {
+                flag.store(true, Ordering::SeqCst);
+                Ok(())
+            }),
+        );
+
+        let report = coordinator.shutdown(plugins).await;
+        assert!(report.is_clean());
+        assert_eq!(tasks.running(rid), 0);
+        assert!(closed.load(Ordering::SeqCst));
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(task.is_finished());
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+#[cfg(not(target_family = "wasm"))]
+use std::{
+    path::{Path, PathBuf},
+    sync::{OnceLock, Weak},
+};
+
+use fxhash::FxHashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+#[cfg(not(target_family = "wasm"))]
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("invalid namespace: {0:?}")]
+    InvalidNamespace(String),
+    #[error("storage io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("storage serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A stored value with its optional expiry time, in milliseconds since unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredEntry {
+    pub value: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl StoredEntry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+pub type Entries = BTreeMap<String, StoredEntry>;
+
+/// Persistence layer of [`KvStore`], each namespace is loaded and saved as a whole.
+pub trait StorageBackend: Send + Sync {
+    fn load<'a>(&'a self, namespace: &'a str) -> PinBoxFut<'a, Result<Entries, StorageError>>;
+
+    fn save<'a>(
+        &'a self,
+        namespace: &'a str,
+        entries: &'a Entries,
+    ) -> PinBoxFut<'a, Result<(), StorageError>>;
+}
+
+/// Backend keeping everything in memory, mainly for tests.
+#[derive(Default)]
+pub struct MemoryBackend {
+    namespaces: StdMutex<FxHashMap<String, Entries>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn load<'a>(&'a self, namespace: &'a str) -> PinBoxFut<'a, Result<Entries, StorageError>> {
+        let entries = self
+            .namespaces
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .cloned()
+            .unwrap_or_default();
+        Box::pin(std::future::ready(Ok(entries)))
+    }
+
+    fn save<'a>(
+        &'a self,
+        namespace: &'a str,
+        entries: &'a Entries,
+    ) -> PinBoxFut<'a, Result<(), StorageError>> {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .insert(namespace.to_owned(), entries.clone());
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Backend storing every namespace as `<namespace>.json` under a directory.
+///
+/// Writes go to a temporary file first and are renamed into place, so a crash never leaves a
+/// half-written namespace behind.
+#[cfg(not(target_family = "wasm"))]
+pub struct FileBackend {
+    dir: PathBuf,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl FileBackend {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path_of(&self, namespace: &str) -> PathBuf {
+        self.dir.join(format!("{namespace}.json"))
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl StorageBackend for FileBackend {
+    fn load<'a>(&'a self, namespace: &'a str) -> PinBoxFut<'a, Result<Entries, StorageError>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path_of(namespace)).await {
+                Ok(data) => Ok(serde_json::from_slice(&data)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Entries::new()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn save<'a>(
+        &'a self,
+        namespace: &'a str,
+        entries: &'a Entries,
+    ) -> PinBoxFut<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let data = serde_json::to_vec(entries)?;
+            tokio::fs::create_dir_all(&self.dir).await?;
+
+            let path = self.path_of(namespace);
+            let tmp_path = self.dir.join(format!("{namespace}.json.tmp"));
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            file.write_all(&data).await?;
+            file.sync_all().await?;
+            drop(file);
+
+            tokio::fs::rename(tmp_path, path).await?;
+            Ok(())
+        })
+    }
+}
+
+struct KvInner {
+    namespace: String,
+    backend: Arc<dyn StorageBackend>,
+    entries: Mutex<Entries>,
+}
+
+/// Namespaced key-value store holding serde values.
+///
+/// Cloning is cheap, clones share the same state.
+#[derive(Clone)]
+pub struct KvStore {
+    inner: Arc<KvInner>,
+}
+
+pub(super) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+fn check_namespace(namespace: &str) -> Result<(), StorageError> {
+    let valid = !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !namespace.starts_with('.');
+    if valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidNamespace(namespace.to_owned()))
+    }
+}
+
+impl KvStore {
+    /// Opens the namespace from the backend.
+    ///
+    /// Namespace may only contain ascii alphanumerics, `-`, `_` and `.`, and must not start with `.`.
+    pub async fn open(
+        backend: Arc<dyn StorageBackend>,
+        namespace: impl Into<String>,
+    ) -> Result<Self, StorageError> {
+        let namespace = namespace.into();
+        check_namespace(&namespace)?;
+
+        let mut entries = backend.load(&namespace).await?;
+        let now = now_millis();
+        entries.retain(|_, entry| !entry.is_expired(now));
+
+        Ok(Self {
+            inner: Arc::new(KvInner {
+                namespace,
+                backend,
+                entries: Mutex::new(entries),
+            }),
+        })
+    }
+
+    /// Opens an in-memory store, nothing is persisted.
+    pub async fn memory(namespace: impl Into<String>) -> Result<Self, StorageError> {
+        Self::open(Arc::new(MemoryBackend::default()), namespace).await
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.inner.namespace
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, StorageError> {
+        let entries = self.inner.entries.lock().await;
+        match entries.get(key) {
+            Some(entry) if !entry.is_expired(now_millis()) => {
+                Ok(Some(T::deserialize(&entry.value)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        let entries = self.inner.entries.lock().await;
+        entries
+            .get(key)
+            .is_some_and(|entry| !entry.is_expired(now_millis()))
+    }
+
+    pub async fn set<T: Serialize>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), StorageError> {
+        self.insert(key.into(), value, None).await
+    }
+
+    /// Sets the value, which expires after `ttl`.
+    pub async fn set_with_ttl<T: Serialize>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = now_millis().saturating_add(ttl);
+        self.insert(key.into(), value, Some(expires_at)).await
+    }
+
+    async fn insert<T: Serialize>(
+        &self,
+        key: String,
+        value: &T,
+        expires_at: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let value = serde_json::to_value(value)?;
+        let mut entries = self.inner.entries.lock().await;
+        let old = entries.insert(key.clone(), StoredEntry { value, expires_at });
+        if let Err(e) = self.persist(&entries).await {
+            match old {
+                Some(old) => entries.insert(key, old),
+                None => entries.remove(&key),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Removes the key, returns whether a live value was removed.
+    pub async fn remove(&self, key: &str) -> Result<bool, StorageError> {
+        let mut entries = self.inner.entries.lock().await;
+        let Some(old) = entries.remove(key) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.persist(&entries).await {
+            entries.insert(key.to_owned(), old);
+            return Err(e);
+        }
+        Ok(!old.is_expired(now_millis()))
+    }
+
+    /// Returns all live entries whose key starts with `prefix`, ordered by key.
+    pub async fn scan_prefix<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, T)>, StorageError> {
+        let entries = self.inner.entries.lock().await;
+        let now = now_millis();
+        entries
+            .range(prefix.to_owned()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| Ok((key.clone(), T::deserialize(&entry.value)?)))
+            .collect()
+    }
+
+    /// Drops expired entries from storage, returns the count of dropped entries.
+    pub async fn purge_expired(&self) -> Result<usize, StorageError> {
+        let mut entries = self.inner.entries.lock().await;
+        let now = now_millis();
+        let before = entries.len();
+        entries.retain(|_, entry| !entry.is_expired(now));
+        let purged = before - entries.len();
+        if purged > 0 {
+            self.persist(&entries).await?;
+        }
+        Ok(purged)
+    }
+
+    async fn persist(&self, entries: &Entries) -> Result<(), StorageError> {
+        self.inner
+            .backend
+            .save(&self.inner.namespace, entries)
+            .await
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+type OpenedStores = StdMutex<FxHashMap<PathBuf, Weak<KvInner>>>;
+
+/// Opens a file backed store, the same file is never opened twice at a time.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) async fn open_file_store(
+    dir: PathBuf,
+    namespace: &str,
+) -> Result<KvStore, StorageError> {
+    static OPENED: OnceLock<OpenedStores> = OnceLock::new();
+
+    check_namespace(namespace)?;
+    let key = dir.join(namespace);
+    let opened = OPENED.get_or_init(Default::default);
+    if let Some(inner) = opened.lock().unwrap().get(&key).and_then(Weak::upgrade) {
+        return Ok(KvStore { inner });
+    }
+
+    let store = KvStore::open(Arc::new(FileBackend::new(dir)), namespace).await?;
+    let mut opened = opened.lock().unwrap();
+    // Another task may have opened it while loading
+    if let Some(inner) = opened.get(&key).and_then(Weak::upgrade) {
+        return Ok(KvStore { inner });
+    }
+    opened.retain(|_, inner| inner.strong_count() > 0);
+    opened.insert(key, Arc::downgrade(&store.inner));
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ttl_expiry() {
+        let store = KvStore::memory("ttl").await.unwrap();
+        store
+            .set_with_ttl("short", &1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        store.set_with_ttl("long", &2, Duration::MAX).await.unwrap();
+        store.set("forever", &3).await.unwrap();
+        assert_eq!(store.get::<i32>("short").await.unwrap(), Some(1));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(store.get::<i32>("short").await.unwrap(), None);
+        assert!(!store.contains("short").await);
+        assert_eq!(store.get::<i32>("long").await.unwrap(), Some(2));
+        assert_eq!(store.get::<i32>("forever").await.unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn scan_prefix() {
+        let store = KvStore::memory("scan").await.unwrap();
+        for key in ["user.b", "user.a", "group.a", "user"] {
+            store.set(key, &key).await.unwrap();
+        }
+        store
+            .set_with_ttl("user.c", &"user.c", Duration::ZERO)
+            .await
+            .unwrap();
+
+        let found = store.scan_prefix::<String>("user.").await.unwrap();
+        let keys: Vec<_> = found.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["user.a", "user.b"]);
+        assert!(found.iter().all(|(key, value)| key == value));
+    }
+
+    #[tokio::test]
+    async fn purge_expired() {
+        let backend = Arc::new(MemoryBackend::default());
+        let store = KvStore::open(backend.clone(), "purge").await.unwrap();
+        store.set("kept", &1).await.unwrap();
+        store
+            .set_with_ttl("expired", &2, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(backend.load("purge").await.unwrap().len(), 2);
+
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+        assert_eq!(store.purge_expired().await.unwrap(), 0);
+        let saved = backend.load("purge").await.unwrap();
+        assert_eq!(saved.keys().collect::<Vec<_>>(), ["kept"]);
+    }
+
+    #[test]
+    fn invalid_namespace() {
+        for namespace in ["", ".hidden", "a/b", "a b"] {
+            assert!(check_namespace(namespace).is_err(), "{namespace:?}");
+        }
+        assert!(check_namespace("plugin.data-1_x").is_ok());
+    }
+
+    #[tokio::test]
+    async fn file_writes_replace_whole_namespace() {
+        let dir = std::env::temp_dir().join(format!("carolina-kv-test-{}", std::process::id()));
+        let backend = Arc::new(FileBackend::new(&dir));
+        let store = KvStore::open(backend.clone(), "data").await.unwrap();
+        store.set("a", &1).await.unwrap();
+        store.set("b", &2).await.unwrap();
+        assert!(store.remove("a").await.unwrap());
+
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["data.json"]);
+        let reopened = KvStore::open(backend, "data").await.unwrap();
+        assert_eq!(reopened.get::<i32>("a").await.unwrap(), None);
+        assert_eq!(reopened.get::<i32>("b").await.unwrap(), Some(2));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::RwLock;
+
+use fxhash::FxHashSet;
+
+use super::*;
+
+/// Host side enabled flags of plugins, plugins are enabled unless disabled.
+///
+/// A disabled plugin stays loaded and keeps its state, but it is skipped in event dispatch and
+/// api calls to it fail with [`APIError::PluginDisabled`].
+#[derive(Default)]
+pub struct PluginSwitches {
+    disabled: RwLock<FxHashSet<PluginRid>>,
+}
+
+impl PluginSwitches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self, rid: PluginRid) -> bool {
+        !self.disabled.read().unwrap().contains(&rid)
+    }
+
+    /// Sets the flag, returns `true` if it changed.
+    pub fn set_enabled(&self, rid: PluginRid, enabled: bool) -> bool {
+        let mut disabled = self.disabled.write().unwrap();
+        if enabled {
+            disabled.remove(&rid)
+        } else {
+            disabled.insert(rid)
+        }
+    }
+
+    /// Sets the flag and notifies the plugin via `on_enable` or `on_disable` if it changed.
+    ///
+    /// The flag is kept even if the hook fails.
+    pub async fn toggle<P: CarolinaPlugin>(
+        &self,
+        rid: PluginRid,
+        plugin: &mut P,
+        enabled: bool,
+    ) -> StdResult<bool> {
+        if !self.set_enabled(rid, enabled) {
+            return Ok(false);
+        }
+        if enabled {
+            plugin.on_enable().await?;
+        } else {
+            plugin.on_disable().await?;
+        }
+        Ok(true)
+    }
+
+    /// Keeps only enabled plugins, used to filter event dispatch targets.
+    pub fn retain_enabled<T>(&self, targets: &mut Vec<T>, rid: impl Fn(&T) -> PluginRid) {
+        let disabled = self.disabled.read().unwrap();
+        targets.retain(|t| !disabled.contains(&rid(t)));
+    }
+
+    /// Checks the target of an api call.
+    pub fn check_call(&self, target: PluginRid) -> Result<(), APIError> {
+        if self.is_enabled(target) {
+            Ok(())
+        } else {
+            Err(APIError::PluginDisabled(target))
+        }
+    }
+
+    /// Records the panic, disabling the plugin if the tracker decides so.
+    ///
+    /// Returns `true` if the plugin is newly disabled, host should then call its `on_disable`.
+    pub fn record_panic(
+        &self,
+        tracker: &PanicTracker,
+        rid: PluginRid,
+        panic: &PluginPanicked,
+    ) -> bool {
+        if !tracker.record(rid, panic) {
+            return false;
+        }
+        let disabled = self.set_enabled(rid, false);
+        if disabled {
+            log::warn!(
+                "plugin `{}` disabled after too many panics",
+                panic.plugin_id
+            );
+        }
+        disabled
+    }
+
+    /// Currently disabled plugins.
+    pub fn disabled(&self) -> Vec<PluginRid> {
+        self.disabled.read().unwrap().iter().copied().collect()
+    }
+}
@@ -0,0 +1,86 @@
+use super::*;
+
+#[cfg(feature = "tracing")]
+pub use tracing_impl::*;
+
+#[cfg(feature = "tracing")]
+mod tracing_impl {
+    use tracing::{info_span, Span};
+
+    use super::*;
+    use crate::RawEvent;
+
+    /// Span for dispatching an event to a plugin, host should enter it around `handle_event`.
+    pub fn dispatch_span(app: AppRid, plugin: PluginRid, event: &RawEvent) -> Span {
+        let meta = EventMeta::from_event(event);
+        info_span!(
+            "dispatch_event",
+            app_rid = %app,
+            plugin_rid = %plugin,
+            event_type = %meta.event_type,
+            detail_type = %meta.detail_type,
+        )
+    }
+
+    /// Span for an api call from `src` to `target`.
+    pub fn api_call_span(src: PluginRid, target: PluginRid, endpoint: Endpoint) -> Span {
+        info_span!(
+            "call_plugin_api",
+            src_rid = %src,
+            target_rid = %target,
+            endpoint = %endpoint,
+        )
+    }
+
+    /// Span for handling an api call in [`APIRouter`](crate::plugin::APIRouter).
+    pub fn api_handle_span(src: PluginRid, endpoint: Endpoint) -> Span {
+        info_span!("handle_api_call", src_rid = %src, endpoint = %endpoint)
+    }
+}
+
+pub(crate) fn instrument_api_call<F: Future>(
+    fut: F,
+    src: PluginRid,
+    target: PluginRid,
+    endpoint: Endpoint,
+) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::Instrument::instrument(fut, api_call_span(src, target, endpoint))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (src, target, endpoint);
+        fut
+    }
+}
+
+#[cfg(feature = "plugin")]
+pub(crate) fn instrument_api_handle<F: Future>(
+    fut: F,
+    src: PluginRid,
+    endpoint: Endpoint,
+) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::Instrument::instrument(fut, api_handle_span(src, endpoint))
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (src, endpoint);
+        fut
+    }
+}
+
+/// Keeps current span for futures spawned onto other tasks or runtimes.
+#[cfg(feature = "plugin")]
+pub(crate) fn in_current_span<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "tracing")]
+    {
+        tracing::Instrument::in_current_span(fut)
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        fut
+    }
+}
@@ -0,0 +1,248 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use onebot_connect_interface::value::Value;
+use serde::de::DeserializeOwned;
+
+use super::{storage::now_millis, *};
+
+/// Version of [`APIRequest`] and [`APIResponse`], envelopes of other versions are rejected.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WireError {
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("failed to encode msgpack: {0}")]
+    MsgpackEncode(#[from] rmp_serde::encode::Error),
+    #[cfg(feature = "msgpack")]
+    #[error("invalid msgpack: {0}")]
+    MsgpackDecode(#[from] rmp_serde::decode::Error),
+    #[error("unsupported envelope version {found}, expected {expected}")]
+    Version { found: u32, expected: u32 },
+}
+
+/// Encoding of values sent across process or network boundaries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// MessagePack with structs encoded as maps, so fields can be added like in json.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, WireError> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(value)?),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, WireError> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(bytes)?),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+
+    /// Checks the version before decoding the rest, whose layout may differ between versions.
+    fn decode_envelope<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, WireError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+
+        let Versioned { version } = self.decode(bytes)?;
+        if version != ENVELOPE_VERSION {
+            return Err(WireError::Version {
+                found: version,
+                expected: ENVELOPE_VERSION,
+            });
+        }
+        self.decode(bytes)
+    }
+}
+
+/// [`APICall`] from one plugin to another, with what is needed to route and answer it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct APIRequest {
+    pub version: u32,
+    /// Chosen by the sender, and echoed by the [`APIResponse`].
+    pub id: u64,
+    pub src: PluginRid,
+    pub target: PluginRid,
+    pub endpoint: Endpoint,
+    pub payload: Value,
+    /// Unix time in milliseconds, after which the sender no longer waits for the response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+}
+
+impl APIRequest {
+    pub fn new(id: u64, src: PluginRid, target: PluginRid, call: APICall) -> Self {
+        Self {
+            version: ENVELOPE_VERSION,
+            id,
+            src,
+            target,
+            endpoint: call.endpoint,
+            payload: call.payload,
+            deadline: None,
+        }
+    }
+
+    /// Sets the deadline to `timeout` from now.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        let timeout = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        self.deadline = Some(now_millis().saturating_add(timeout));
+        self
+    }
+
+    /// Deadline as system time, `None` if there is none or it is too far to represent.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+            .and_then(|millis| UNIX_EPOCH.checked_add(Duration::from_millis(millis)))
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|millis| Duration::from_millis(millis.saturating_sub(now_millis())))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|millis| millis <= now_millis())
+    }
+
+    pub fn call(&self) -> APICall {
+        APICall {
+            endpoint: self.endpoint,
+            payload: self.payload.clone(),
+        }
+    }
+
+    pub fn into_call(self) -> APICall {
+        APICall {
+            endpoint: self.endpoint,
+            payload: self.payload,
+        }
+    }
+
+    /// Response to this request carrying `result`.
+    pub fn respond(&self, result: APIResult) -> APIResponse {
+        APIResponse {
+            version: ENVELOPE_VERSION,
+            id: self.id,
+            result,
+        }
+    }
+
+    pub fn encode(&self, format: WireFormat) -> Result<Vec<u8>, WireError> {
+        format.encode(self)
+    }
+
+    pub fn decode(format: WireFormat, bytes: &[u8]) -> Result<Self, WireError> {
+        format.decode_envelope(bytes)
+    }
+}
+
+/// Result of the [`APIRequest`] with the same id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct APIResponse {
+    pub version: u32,
+    pub id: u64,
+    pub result: APIResult,
+}
+
+impl APIResponse {
+    pub fn encode(&self, format: WireFormat) -> Result<Vec<u8>, WireError> {
+        format.encode(self)
+    }
+
+    pub fn decode(format: WireFormat, bytes: &[u8]) -> Result<Self, WireError> {
+        format.decode_envelope(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request() -> APIRequest {
+        let call = APICall {
+            endpoint: Endpoint::new(2),
+            payload: json!({"user": "alice", "count": 3, "tags": ["a", "b"]}),
+        };
+        APIRequest::new(7, PluginRid::new(1), PluginRid::new(3), call)
+            .timeout(Duration::from_secs(5))
+    }
+
+    fn assert_round_trip(format: WireFormat) {
+        let request = request();
+        let decoded = APIRequest::decode(format, &request.encode(format).unwrap()).unwrap();
+        assert_eq!(decoded.version, ENVELOPE_VERSION);
+        assert_eq!(decoded.id, request.id);
+        assert_eq!(decoded.src, request.src);
+        assert_eq!(decoded.target, request.target);
+        assert_eq!(decoded.endpoint, request.endpoint);
+        assert_eq!(decoded.payload, request.payload);
+        assert_eq!(decoded.deadline, request.deadline);
+
+        let response = request.respond(Err(APIError::EndpointNotFound(request.endpoint)));
+        let decoded = APIResponse::decode(format, &response.encode(format).unwrap()).unwrap();
+        assert_eq!(decoded.id, request.id);
+        assert!(matches!(
+            decoded.result,
+            Err(APIError::EndpointNotFound(endpoint)) if endpoint == request.endpoint
+        ));
+    }
+
+    fn assert_version_rejected(format: WireFormat) {
+        let mut request = request();
+        request.version = ENVELOPE_VERSION + 1;
+        let bytes = request.encode(format).unwrap();
+        assert!(matches!(
+            APIRequest::decode(format, &bytes),
+            Err(WireError::Version { found, expected: ENVELOPE_VERSION })
+                if found == ENVELOPE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn far_deadline_does_not_overflow() {
+        let mut request = request();
+        assert!(request.deadline().is_some());
+        request.deadline = Some(u64::MAX);
+        let _ = request.deadline();
+        assert!(request.remaining().is_some());
+        assert!(!request.is_expired());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        assert_round_trip(WireFormat::Json);
+    }
+
+    #[test]
+    fn json_version_rejected() {
+        assert_version_rejected(WireFormat::Json);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trip() {
+        assert_round_trip(WireFormat::MessagePack);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_version_rejected() {
+        assert_version_rejected(WireFormat::MessagePack);
+    }
+}
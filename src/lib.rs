@@ -1,8 +1,19 @@
+extern crate self as carolina_api;
+
 pub mod common;
 
 #[cfg(feature = "plugin")]
 pub mod plugin;
 
+#[cfg(feature = "protocol")]
+pub mod process;
+
+#[cfg(feature = "script")]
+pub mod script;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 pub use carolina_api_macros::plugin_api;
 pub use common::*;
 pub use onebot_connect_interface as oc_interface;
@@ -12,3 +23,9 @@ pub use types::{ob12::event::RawEvent, OBEventSelector};
 pub use std::error::Error as StdErr;
 
 pub type StdResult<T> = Result<T, Box<dyn StdErr>>;
+
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "registry")]
+    pub use inventory;
+}
@@ -144,6 +144,7 @@ type Handlers = Arc<tokio::sync::RwLock<FxHashMap<Endpoint, Box<dyn APICallHandl
 #[derive(Default)]
 pub struct APIRouter {
     handlers: Handlers,
+    metrics: Option<(Arc<Metrics>, PluginRid)>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -153,6 +154,12 @@ pub enum RegError {
 }
 
 impl APIRouter {
+    /// Records calls handled by this router as calls to the plugin `rid`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>, rid: PluginRid) -> Self {
+        self.metrics = Some((metrics, rid));
+        self
+    }
+
     pub async fn register(
         &mut self,
         handler: impl APICallHandler + 'static,
@@ -172,11 +179,19 @@ impl APIRouter {
             endpoint, payload, ..
         } = call;
 
-        if let Some(handler) = self.handlers.read().await.get(&endpoint) {
-            let result = handler.handle(src, payload).await?;
-            Ok(result)
-        } else {
-            Err(APIError::EndpointNotFound(endpoint))
+        let fut = async move {
+            if let Some(handler) = self.handlers.read().await.get(&endpoint) {
+                let result = handler.handle(src, payload).await?;
+                Ok(result)
+            } else {
+                Err(APIError::EndpointNotFound(endpoint))
+            }
+        };
+        let fut = instrument_api_handle(fut, src, endpoint);
+
+        match &self.metrics {
+            Some((metrics, rid)) => metrics.observe_api_call(*rid, endpoint, fut).await,
+            None => fut.await,
         }
     }
 
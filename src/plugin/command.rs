@@ -0,0 +1,1033 @@
+use std::{fmt::Display, future::Future, iter::Peekable, sync::Arc, vec};
+
+use fxhash::FxHashMap;
+use serde_json::Value as JsonValue;
+
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum CommandError {
+    #[error("unclosed quote")]
+    UnclosedQuote,
+    #[error("missing argument `{0}`")]
+    MissingArg(String),
+    #[error("invalid argument `{name}`, expected {expected}")]
+    InvalidArg { name: String, expected: ArgKind },
+    #[error("too many arguments")]
+    TooManyArgs,
+    #[error("argument `{0}` after the rest argument")]
+    ArgAfterRest(String),
+    #[error("unknown command `{0}`")]
+    UnknownCommand(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandRegError {
+    #[error("command already registered, {0}")]
+    Conflicted(String),
+    #[error("invalid command `{0}`: {1}")]
+    Invalid(String, CommandError),
+}
+
+/// Token of a command line, from text, mention and image segments of the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Text(String),
+    /// Word starting with a quote, `text` has quotes removed and escapes resolved, and `raw` is
+    /// the word as written. Quoted words are never taken as flags.
+    Quoted {
+        text: String,
+        raw: String,
+    },
+    /// Quote without closing quote, as written up to the end of its text segment. Fails to
+    /// parse unless it is in the rest argument.
+    Unclosed(String),
+    Mention(String),
+    Image(String),
+    /// Whitespace between tokens, kept for [`ArgKind::Rest`] and skipped by other arguments.
+    Space(String),
+}
+
+impl Token {
+    /// The token as written, `None` for mentions and images.
+    pub fn raw(&self) -> Option<&str> {
+        match self {
+            Self::Text(s) | Self::Space(s) | Self::Unclosed(s) => Some(s),
+            Self::Quoted { raw, .. } => Some(raw),
+            Self::Mention(_) | Self::Image(_) => None,
+        }
+    }
+}
+
+/// Splits text by whitespace, `"` and `'` at the start of a word quote a string containing
+/// whitespace, in which `\` escapes the next character. Quotes inside a word are kept, e.g.
+/// `don't`.
+///
+/// Tokens follow one another in the text, so the rest argument is taken as written.
+fn split_text(text: &str, tokens: &mut Vec<Token>) {
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        let token = if first.is_whitespace() {
+            Token::Space(rest[..span(rest, char::is_whitespace)].to_owned())
+        } else if first == '"' || first == '\'' {
+            match unquote(rest) {
+                Some((mut text, quoted)) => {
+                    // Rest of the word after the closing quote is kept as written
+                    let len = quoted + span(&rest[quoted..], |c| !c.is_whitespace());
+                    text.push_str(&rest[quoted..len]);
+                    let raw = rest[..len].to_owned();
+                    Token::Quoted { text, raw }
+                }
+                None => Token::Unclosed(rest.to_owned()),
+            }
+        } else {
+            Token::Text(rest[..span(rest, |c| !c.is_whitespace())].to_owned())
+        };
+        rest = &rest[token.raw().map_or(0, str::len)..];
+        tokens.push(token);
+    }
+}
+
+/// Length of the leading chars of `s` matching `pred`.
+fn span(s: &str, pred: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !pred(c)).unwrap_or(s.len())
+}
+
+/// Unquotes the string starting with a quote, returns it and the length up to the closing
+/// quote, `None` if the quote is not closed.
+fn unquote(s: &str) -> Option<(String, usize)> {
+    let mut chars = s.char_indices();
+    let (_, quote) = chars.next()?;
+    let mut text = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some((text, i + c.len_utf8())),
+            '\\' => text.push(chars.next()?.1),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+/// Tokenizes message segments of the serialized event, unknown segments are skipped.
+///
+/// Unclosed quotes do not fail here, but when parsed as an argument other than the rest.
+pub fn tokenize(event: &JsonValue) -> Vec<Token> {
+    let mut tokens = vec![];
+    let segments = event.get("message").and_then(|m| m.as_array());
+    for seg in segments.into_iter().flatten() {
+        let data = |key: &str| {
+            seg.get("data")
+                .and_then(|d| d.get(key))
+                .and_then(|v| v.as_str())
+                .map(ToOwned::to_owned)
+        };
+        match seg.get("type").and_then(|t| t.as_str()) {
+            Some("text") => split_text(&data("text").unwrap_or_default(), &mut tokens),
+            Some("mention") => tokens.extend(data("user_id").map(Token::Mention)),
+            Some("image") => tokens.extend(data("file_id").map(Token::Image)),
+            _ => {}
+        }
+    }
+    tokens
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Int,
+    /// One word, or a quoted string.
+    Str,
+    /// All remaining tokens, taken as written, must be the last argument besides flags.
+    Rest,
+    Mention,
+    Image,
+    /// `--name` anywhere before the rest argument, quoted words are not flags.
+    Flag,
+}
+
+impl Display for ArgKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Int => "integer",
+            Self::Str => "string",
+            Self::Rest => "text",
+            Self::Mention => "mention",
+            Self::Image => "image",
+            Self::Flag => "flag",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArgSpec {
+    pub name: String,
+    pub kind: ArgKind,
+    pub optional: bool,
+    pub description: Option<String>,
+}
+
+impl ArgSpec {
+    pub fn new(name: impl Into<String>, kind: ArgKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            optional: false,
+            description: None,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+    Int(i64),
+    Str(String),
+    Mention(String),
+    Image(String),
+    Rest(Rest),
+    Flag,
+}
+
+/// Parsed arguments, missing optional arguments are absent.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedArgs {
+    values: Vec<(String, ArgValue)>,
+}
+
+impl ParsedArgs {
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values
+            .iter()
+            .find_map(|(n, value)| (n == name).then_some(value))
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            ArgValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// String argument, or text of the rest argument.
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            ArgValue::Str(s) => Some(s),
+            ArgValue::Rest(rest) => Some(rest.text()),
+            _ => None,
+        }
+    }
+
+    pub fn rest(&self, name: &str) -> Option<&Rest> {
+        match self.get(name)? {
+            ArgValue::Rest(rest) => Some(rest),
+            _ => None,
+        }
+    }
+
+    pub fn mention(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            ArgValue::Mention(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn image(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            ArgValue::Image(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        matches!(self.get(name), Some(ArgValue::Flag))
+    }
+
+    /// Converts the argument, `None` if it is absent.
+    pub fn value<T: FromArg>(&self, name: &str) -> Result<Option<T>, CommandError> {
+        let Some(value) = self.get(name) else {
+            return Ok(None);
+        };
+        T::from_arg(value.clone())
+            .map(Some)
+            .ok_or_else(|| CommandError::InvalidArg {
+                name: name.to_owned(),
+                expected: T::KIND,
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ArgValue)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+    pub args: Vec<ArgSpec>,
+}
+
+impl CommandSpec {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            aliases: vec![],
+            description: None,
+            args: vec![],
+        }
+    }
+
+    pub fn alias(mut self, alias: impl Into<String>) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn arg(mut self, arg: ArgSpec) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    /// Usage line like `/echo <text> [times]`.
+    pub fn usage(&self, prefix: &str) -> String {
+        let mut usage = format!("{prefix}{}", self.name);
+        for arg in &self.args {
+            let name = match arg.kind {
+                ArgKind::Rest => format!("{}...", arg.name),
+                ArgKind::Flag => format!("--{}", arg.name),
+                _ => arg.name.clone(),
+            };
+            if arg.optional || arg.kind == ArgKind::Flag {
+                usage += &format!(" [{name}]");
+            } else {
+                usage += &format!(" <{name}>");
+            }
+        }
+        usage
+    }
+
+    /// Usage, description, aliases and argument descriptions.
+    pub fn help(&self, prefix: &str) -> String {
+        let mut help = self.usage(prefix);
+        if let Some(description) = &self.description {
+            help += &format!(" - {description}");
+        }
+        if !self.aliases.is_empty() {
+            help += &format!("\n  aliases: {}", self.aliases.join(", "));
+        }
+        for arg in &self.args {
+            if let Some(description) = &arg.description {
+                help += &format!("\n  {}: {description}", arg.name);
+            }
+        }
+        help
+    }
+
+    /// Message replied to user for the error, with usage of the command.
+    pub fn error_message(&self, error: &CommandError, prefix: &str) -> String {
+        format!("{error}\nusage: {}", self.usage(prefix))
+    }
+
+    /// Checks that the rest argument, if any, is the last argument besides flags.
+    pub fn check(&self) -> Result<(), CommandError> {
+        let mut positional = self.args.iter().filter(|arg| arg.kind != ArgKind::Flag);
+        if positional.by_ref().any(|arg| arg.kind == ArgKind::Rest) {
+            if let Some(arg) = positional.next() {
+                return Err(CommandError::ArgAfterRest(arg.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the tokens after command name.
+    pub fn parse_args(&self, tokens: Vec<Token>) -> Result<ParsedArgs, CommandError> {
+        let mut values = vec![];
+        let is_space = |token: &Token| matches!(token, Token::Space(_));
+        let mut tokens = tokens.into_iter().peekable();
+        for arg in self.args.iter().filter(|arg| arg.kind != ArgKind::Flag) {
+            let invalid = || CommandError::InvalidArg {
+                name: arg.name.clone(),
+                expected: arg.kind,
+            };
+            self.take_flags(&mut tokens, &mut values);
+
+            let value = if arg.kind == ArgKind::Rest {
+                let mut rest: Vec<_> = tokens.by_ref().collect();
+                while rest.last().is_some_and(is_space) {
+                    rest.pop();
+                }
+                (!rest.is_empty()).then(|| ArgValue::Rest(Rest::new(rest)))
+            } else {
+                match tokens.next_if(|token| !arg.optional || Self::accepts(arg.kind, token)) {
+                    Some(token) => Some(match (arg.kind, token) {
+                        (_, Token::Unclosed(_)) => return Err(CommandError::UnclosedQuote),
+                        (ArgKind::Int, Token::Text(s) | Token::Quoted { text: s, .. }) => {
+                            ArgValue::Int(s.parse().map_err(|_| invalid())?)
+                        }
+                        (ArgKind::Str, Token::Text(s) | Token::Quoted { text: s, .. }) => {
+                            ArgValue::Str(s)
+                        }
+                        (ArgKind::Mention, Token::Mention(id)) => ArgValue::Mention(id),
+                        (ArgKind::Image, Token::Image(id)) => ArgValue::Image(id),
+                        _ => return Err(invalid()),
+                    }),
+                    None => None,
+                }
+            };
+
+            match value {
+                Some(value) => values.push((arg.name.clone(), value)),
+                None if arg.optional => {}
+                None => return Err(CommandError::MissingArg(arg.name.clone())),
+            }
+        }
+
+        self.take_flags(&mut tokens, &mut values);
+        match tokens.next() {
+            None => Ok(ParsedArgs { values }),
+            Some(Token::Unclosed(_)) => Err(CommandError::UnclosedQuote),
+            Some(_) => Err(CommandError::TooManyArgs),
+        }
+    }
+
+    /// Skips whitespace and takes flags before the next argument.
+    fn take_flags(
+        &self,
+        tokens: &mut Peekable<vec::IntoIter<Token>>,
+        values: &mut Vec<(String, ArgValue)>,
+    ) {
+        loop {
+            match tokens.peek() {
+                Some(Token::Space(_)) => {}
+                Some(Token::Text(word)) => {
+                    let Some(arg) = self.flag(word) else {
+                        return;
+                    };
+                    if !values.iter().any(|(name, _)| *name == arg.name) {
+                        values.push((arg.name.clone(), ArgValue::Flag));
+                    }
+                }
+                _ => return,
+            }
+            tokens.next();
+        }
+    }
+
+    fn flag(&self, word: &str) -> Option<&ArgSpec> {
+        let name = word.strip_prefix("--")?;
+        self.args
+            .iter()
+            .find(|arg| arg.kind == ArgKind::Flag && arg.name == name)
+    }
+
+    fn accepts(kind: ArgKind, token: &Token) -> bool {
+        match (kind, token) {
+            (ArgKind::Int, Token::Text(s) | Token::Quoted { text: s, .. }) => {
+                s.parse::<i64>().is_ok()
+            }
+            (ArgKind::Str | ArgKind::Rest, Token::Text(_) | Token::Quoted { .. }) => true,
+            (ArgKind::Mention, Token::Mention(_)) => true,
+            (ArgKind::Image, Token::Image(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Mentioned user id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention(pub String);
+
+/// Image file id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image(pub String);
+
+/// Remaining tokens of the message, with text as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest {
+    tokens: Vec<Token>,
+    text: String,
+}
+
+impl Rest {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let text = tokens.iter().filter_map(Token::raw).collect();
+        Self { tokens, text }
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Text as written, including quotes and whitespace, mentions and images are left out.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Types converted from command arguments.
+pub trait FromArg: Sized {
+    const KIND: ArgKind;
+
+    fn from_arg(value: ArgValue) -> Option<Self>;
+}
+
+macro_rules! int_from_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl FromArg for $ty {
+                const KIND: ArgKind = ArgKind::Int;
+
+                fn from_arg(value: ArgValue) -> Option<Self> {
+                    match value {
+                        ArgValue::Int(i) => i.try_into().ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_from_arg!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromArg for String {
+    const KIND: ArgKind = ArgKind::Str;
+
+    fn from_arg(value: ArgValue) -> Option<Self> {
+        match value {
+            ArgValue::Str(s) => Some(s),
+            ArgValue::Rest(rest) => Some(rest.text),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for Rest {
+    const KIND: ArgKind = ArgKind::Rest;
+
+    fn from_arg(value: ArgValue) -> Option<Self> {
+        match value {
+            ArgValue::Rest(rest) => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for Mention {
+    const KIND: ArgKind = ArgKind::Mention;
+
+    fn from_arg(value: ArgValue) -> Option<Self> {
+        match value {
+            ArgValue::Mention(id) => Some(Self(id)),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for Image {
+    const KIND: ArgKind = ArgKind::Image;
+
+    fn from_arg(value: ArgValue) -> Option<Self> {
+        match value {
+            ArgValue::Image(id) => Some(Self(id)),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for bool {
+    const KIND: ArgKind = ArgKind::Flag;
+
+    fn from_arg(value: ArgValue) -> Option<Self> {
+        Some(matches!(value, ArgValue::Flag))
+    }
+}
+
+/// Typed commands, usually derived by [`Command`](carolina_api_macros::Command).
+///
+/// A struct is one command, and each variant of an enum is a command.
+pub trait Command: Sized {
+    fn specs() -> Vec<CommandSpec>;
+
+    /// Converts arguments of the command `name`.
+    fn from_args(name: &str, args: &ParsedArgs) -> Result<Self, CommandError>;
+}
+
+/// A matched command, passed to its handler.
+pub struct CommandCall {
+    /// Registered name of the command, not the alias used.
+    pub name: String,
+    pub args: ParsedArgs,
+    pub event: SharedEvent,
+}
+
+pub trait CommandHandler: Send + Sync {
+    fn handle(&self, call: CommandCall, context: DynEventContext) -> PinBoxResult<'static, ()>;
+}
+
+impl<F, FR> CommandHandler for F
+where
+    F: Fn(CommandCall, DynEventContext) -> FR + Send + Sync,
+    FR: Future<Output = StdResult<()>> + Send + 'static,
+{
+    fn handle(&self, call: CommandCall, context: DynEventContext) -> PinBoxResult<'static, ()> {
+        Box::pin((self)(call, context))
+    }
+}
+
+/// Handler of commands which fail to parse, e.g. to reply with usage.
+pub trait CommandErrorHandler: Send + Sync {
+    fn handle(
+        &self,
+        spec: &CommandSpec,
+        error: CommandError,
+        context: DynEventContext,
+    ) -> PinBoxResult<'static, ()>;
+}
+
+impl<F, FR> CommandErrorHandler for F
+where
+    F: Fn(&CommandSpec, CommandError, DynEventContext) -> FR + Send + Sync,
+    FR: Future<Output = StdResult<()>> + Send + 'static,
+{
+    fn handle(
+        &self,
+        spec: &CommandSpec,
+        error: CommandError,
+        context: DynEventContext,
+    ) -> PinBoxResult<'static, ()> {
+        Box::pin((self)(spec, error, context))
+    }
+}
+
+/// Routes message events to registered commands.
+///
+/// A message is a command if its first word starts with one of the prefixes, `/` by default,
+/// followed by a command name or alias. Leading mentions of the bot itself are skipped.
+/// Routing returns [`EventState::Intercept`] if a command is matched, even if its arguments
+/// fail to parse, otherwise [`EventState::Pass`].
+pub struct CommandRouter {
+    prefixes: Vec<String>,
+    commands: Vec<(CommandSpec, Box<dyn CommandHandler>)>,
+    names: FxHashMap<String, usize>,
+    on_error: Option<Box<dyn CommandErrorHandler>>,
+}
+
+impl Default for CommandRouter {
+    fn default() -> Self {
+        Self {
+            prefixes: vec!["/".to_owned()],
+            commands: vec![],
+            names: Default::default(),
+            on_error: None,
+        }
+    }
+}
+
+impl CommandRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces command prefixes, an empty prefix matches commands without prefix.
+    pub fn prefixes<S: Into<String>>(mut self, prefixes: impl IntoIterator<Item = S>) -> Self {
+        self.prefixes = prefixes.into_iter().map(Into::into).collect();
+        // Longer prefixes first, so `//` is not taken as `/`
+        self.prefixes.sort_by_key(|p| std::cmp::Reverse(p.len()));
+        self
+    }
+
+    pub fn on_error(mut self, handler: impl CommandErrorHandler + 'static) -> Self {
+        self.on_error = Some(Box::new(handler));
+        self
+    }
+
+    pub fn register(
+        &mut self,
+        spec: CommandSpec,
+        handler: impl CommandHandler + 'static,
+    ) -> Result<(), CommandRegError> {
+        spec.check()
+            .map_err(|e| CommandRegError::Invalid(spec.name.clone(), e))?;
+        let names = std::iter::once(&spec.name).chain(&spec.aliases);
+        for name in names.clone() {
+            if self.names.contains_key(name) {
+                return Err(CommandRegError::Conflicted(name.clone()));
+            }
+        }
+        let index = self.commands.len();
+        self.names.extend(names.map(|name| (name.clone(), index)));
+        self.commands.push((spec, Box::new(handler)));
+        Ok(())
+    }
+
+    /// Registers all commands of `C`, arguments are converted before calling the handler.
+    pub fn register_command<C, F, FR>(&mut self, handler: F) -> Result<(), CommandRegError>
+    where
+        C: Command + 'static,
+        F: Fn(C, CommandCall, DynEventContext) -> FR + Send + Sync + 'static,
+        FR: Future<Output = StdResult<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        for spec in C::specs() {
+            let handler = handler.clone();
+            self.register(spec, move |call: CommandCall, context| {
+                let fut =
+                    C::from_args(&call.name, &call.args).map(|cmd| handler(cmd, call, context));
+                async move { fut?.await }
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn command(&self, name: &str) -> Option<&CommandSpec> {
+        self.names.get(name).map(|i| &self.commands[*i].0)
+    }
+
+    /// Help of all commands, in registration order.
+    pub fn help(&self) -> String {
+        let prefix = self
+            .prefixes
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default();
+        self.commands
+            .iter()
+            .map(|(spec, _)| spec.help(prefix))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Subscriptions of message events which may be commands, one per prefix.
+    pub fn subscribe(&self) -> Vec<Subscribe> {
+        self.prefixes
+            .iter()
+            .map(|prefix| {
+                Subscribe::new("message", None::<String>)
+                    .filter(EventFilter::MessagePrefix(prefix.clone()))
+            })
+            .collect()
+    }
+
+    /// Finds the command and tokens of its arguments.
+    fn match_command(&self, event: &JsonValue) -> Option<(usize, Vec<Token>)> {
+        if EventMeta::from_json(event).event_type != "message" {
+            return None;
+        }
+        let mut tokens = tokenize(event);
+        let self_id = event.pointer("/self/user_id").and_then(|id| id.as_str());
+        let skip = tokens
+            .iter()
+            .take_while(|t| match t {
+                Token::Space(_) => true,
+                Token::Mention(id) => Some(id.as_str()) == self_id,
+                _ => false,
+            })
+            .count();
+        tokens.drain(..skip);
+
+        let Some(Token::Text(first)) = tokens.first() else {
+            return None;
+        };
+        let index = self.prefixes.iter().find_map(|prefix| {
+            let name = first.strip_prefix(prefix.as_str())?;
+            self.names.get(name)
+        })?;
+        Some((*index, tokens.split_off(1)))
+    }
+
+    pub async fn route<EC>(&self, event: SharedEvent, context: EC) -> StdResult<EventState>
+    where
+        EC: EventContextTrait + Send + 'static,
+    {
+        let json = event_json(&event);
+        let Some((index, tokens)) = self.match_command(&json) else {
+            return pass();
+        };
+
+        let (spec, handler) = &self.commands[index];
+        let context = DynEventContext::from(context.into_inner());
+        match spec.parse_args(tokens) {
+            Ok(args) => {
+                let call = CommandCall {
+                    name: spec.name.clone(),
+                    args,
+                    event,
+                };
+                handler.handle(call, context).await?;
+            }
+            Err(e) => match &self.on_error {
+                Some(on_error) => on_error.handle(spec, e, context).await?,
+                None => log::debug!("invalid command `{}`: {e}", spec.name),
+            },
+        }
+        intercept()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use onebot_connect_interface::app::OBApp;
+    use serde_json::json;
+
+    use super::*;
+
+    fn text(text: &str) -> Token {
+        Token::Text(text.to_owned())
+    }
+
+    fn space() -> Token {
+        Token::Space(" ".to_owned())
+    }
+
+    fn quoted(text: &str, raw: &str) -> Token {
+        Token::Quoted {
+            text: text.to_owned(),
+            raw: raw.to_owned(),
+        }
+    }
+
+    fn tokens(line: &str) -> Vec<Token> {
+        tokenize(&json!({"message": [{"type": "text", "data": {"text": line}}]}))
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        assert_eq!(
+            tokens(r#"say "a b" 'c \' d'x"#),
+            [
+                text("say"),
+                space(),
+                quoted("a b", r#""a b""#),
+                space(),
+                quoted("c ' dx", r#"'c \' d'x"#)
+            ]
+        );
+        assert_eq!(
+            tokens("say don't  it\"s\""),
+            [
+                text("say"),
+                space(),
+                text("don't"),
+                Token::Space("  ".to_owned()),
+                text("it\"s\"")
+            ]
+        );
+        assert_eq!(
+            tokens("say \"a b"),
+            [text("say"), space(), Token::Unclosed("\"a b".to_owned())]
+        );
+        assert_eq!(
+            tokens("say 'a\\"),
+            [text("say"), space(), Token::Unclosed("'a\\".to_owned())]
+        );
+    }
+
+    #[test]
+    fn tokenize_segments() {
+        let event = json!({"message": [
+            {"type": "mention", "data": {"user_id": "bot"}},
+            {"type": "text", "data": {"text": " hi "}},
+            {"type": "image", "data": {"file_id": "img"}},
+            {"type": "face", "data": {"id": "1"}},
+        ]});
+        assert_eq!(
+            tokenize(&event),
+            [
+                Token::Mention("bot".to_owned()),
+                space(),
+                text("hi"),
+                space(),
+                Token::Image("img".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_positional() {
+        let spec = CommandSpec::new("ban")
+            .arg(ArgSpec::new("who", ArgKind::Mention))
+            .arg(ArgSpec::new("minutes", ArgKind::Int).optional())
+            .arg(ArgSpec::new("reason", ArgKind::Str).optional());
+        let mention = Token::Mention("u".to_owned());
+
+        let args = spec
+            .parse_args(vec![mention.clone(), space(), text("5")])
+            .unwrap();
+        assert_eq!(args.mention("who"), Some("u"));
+        assert_eq!(args.int("minutes"), Some(5));
+        assert_eq!(args.str("reason"), None);
+
+        let args = spec
+            .parse_args(vec![mention.clone(), text("spam")])
+            .unwrap();
+        assert_eq!(args.int("minutes"), None);
+        assert_eq!(args.str("reason"), Some("spam"));
+
+        assert_eq!(
+            spec.parse_args(vec![space()]).unwrap_err(),
+            CommandError::MissingArg("who".to_owned())
+        );
+        assert!(matches!(
+            spec.parse_args(vec![text("u")]),
+            Err(CommandError::InvalidArg {
+                expected: ArgKind::Mention,
+                ..
+            })
+        ));
+        assert_eq!(
+            spec.parse_args(vec![mention, text("1"), text("a"), text("b")])
+                .unwrap_err(),
+            CommandError::TooManyArgs
+        );
+    }
+
+    #[test]
+    fn parse_rest_and_flags() {
+        let spec = CommandSpec::new("echo")
+            .arg(ArgSpec::new("times", ArgKind::Int).optional())
+            .arg(ArgSpec::new("text", ArgKind::Rest))
+            .arg(ArgSpec::new("loud", ArgKind::Flag));
+
+        let mut line = tokens("2 --loud  hello   --loud  \"world ");
+        line.push(Token::Mention("u".to_owned()));
+        let args = spec.parse_args(line).unwrap();
+        assert_eq!(args.int("times"), Some(2));
+        assert!(args.flag("loud"));
+        let rest = args.rest("text").unwrap();
+        assert_eq!(rest.text(), "hello   --loud  \"world ");
+        assert_eq!(rest.tokens().last(), Some(&Token::Mention("u".to_owned())));
+        assert_eq!(
+            args.value::<String>("text").unwrap().as_deref(),
+            Some("hello   --loud  \"world ")
+        );
+
+        let args = spec.parse_args(tokens(r#"say "a  b" --loud"#)).unwrap();
+        assert!(!args.flag("loud"));
+        assert_eq!(args.str("text"), Some(r#"say "a  b" --loud"#));
+        assert_eq!(
+            spec.parse_args(tokens("--loud ")).unwrap_err(),
+            CommandError::MissingArg("text".to_owned())
+        );
+    }
+
+    #[test]
+    fn quoted_words_are_not_flags() {
+        let spec = CommandSpec::new("say")
+            .arg(ArgSpec::new("word", ArgKind::Str))
+            .arg(ArgSpec::new("loud", ArgKind::Flag));
+
+        let args = spec.parse_args(tokens(r#""--loud""#)).unwrap();
+        assert_eq!(args.str("word"), Some("--loud"));
+        assert!(!args.flag("loud"));
+
+        let args = spec.parse_args(tokens("hi --loud")).unwrap();
+        assert_eq!(args.str("word"), Some("hi"));
+        assert!(args.flag("loud"));
+
+        assert_eq!(
+            spec.parse_args(tokens(r#""hi"#)).unwrap_err(),
+            CommandError::UnclosedQuote
+        );
+        assert_eq!(
+            spec.parse_args(tokens(r#"hi "there"#)).unwrap_err(),
+            CommandError::UnclosedQuote
+        );
+    }
+
+    #[test]
+    fn rest_must_be_last() {
+        let spec = CommandSpec::new("echo")
+            .arg(ArgSpec::new("text", ArgKind::Rest))
+            .arg(ArgSpec::new("times", ArgKind::Int));
+        assert_eq!(
+            spec.check(),
+            Err(CommandError::ArgAfterRest("times".to_owned()))
+        );
+
+        let mut router = CommandRouter::new();
+        let handler = |_: CommandCall, _: DynEventContext| async { Ok(()) };
+        assert!(matches!(
+            router.register(spec, handler),
+            Err(CommandRegError::Invalid(..))
+        ));
+    }
+
+    #[test]
+    fn subscribe_per_prefix() {
+        let router = CommandRouter::new().prefixes(["/", "!"]);
+        let subscribes = router.subscribe();
+        assert_eq!(subscribes.len(), 2);
+        assert!(subscribes
+            .iter()
+            .all(|s| s.filters.iter().all(|f| !f.is_custom())));
+
+        let event = json!({"type": "message", "message": [
+            {"type": "mention", "data": {"user_id": "bot"}},
+            {"type": "text", "data": {"text": " !echo hi"}}
+        ]});
+        let matched = |s: &&Subscribe| s.matches(AppRid::new(1), &event);
+        assert_eq!(subscribes.iter().filter(matched).count(), 1);
+    }
+
+    struct TestApp;
+
+    impl OBApp for TestApp {
+        async fn send_action_impl(
+            &self,
+            _action: String,
+            _params: JsonValue,
+        ) -> Result<Option<JsonValue>, String> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn route_errors_to_handler() {
+        let errors = Arc::new(Mutex::new(vec![]));
+        let on_error = {
+            let errors = errors.clone();
+            move |spec: &CommandSpec, e: CommandError, _: DynEventContext| {
+                errors.lock().unwrap().push(format!("{}: {e}", spec.name));
+                async { Ok(()) }
+            }
+        };
+        let mut router = CommandRouter::new().on_error(on_error);
+        let spec = CommandSpec::new("say").arg(ArgSpec::new("text", ArgKind::Rest));
+        router
+            .register(spec, |_: CommandCall, _: DynEventContext| async { Ok(()) })
+            .unwrap();
+
+        let route = |line: &str| {
+            let event = json!({"id": "1", "type": "message", "message": [
+                {"type": "text", "data": {"text": line}}
+            ]});
+            let event = Arc::new(serde_json::from_value(event).unwrap());
+            router.route(event, EventContext::new(AppRid::new(1), TestApp))
+        };
+        assert_eq!(route("/say don't").await.unwrap(), EventState::Intercept);
+        assert_eq!(route("/say \"hi").await.unwrap(), EventState::Intercept);
+        assert_eq!(route("/say").await.unwrap(), EventState::Intercept);
+        assert_eq!(route("say \"hi").await.unwrap(), EventState::Pass);
+        assert_eq!(*errors.lock().unwrap(), ["say: missing argument `text`"]);
+    }
+}
@@ -1,7 +1,10 @@
 mod call;
+mod command;
 mod wrap;
 
 pub use call::*;
+pub use command::*;
 pub use wrap::*;
+pub use carolina_api_macros::Command;
 pub use super::*;
 pub use oc_interface::value;
@@ -3,85 +3,155 @@ use tokio::runtime as tok_rt;
 
 use common::ErrorDisplay;
 use std::future::Future;
-use std::ops::{Deref, DerefMut};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use tokio::sync::RwLock;
+use tokio::task::{JoinError, JoinHandle};
 
-struct UnsafePlugin<P: CarolinaPlugin>(P);
+#[derive(Debug, thiserror::Error)]
+pub enum DynPluginError {
+    #[error("plugin `{0}` is already deinitialized")]
+    Deinitialized(String),
+    #[error("failed to build runtime for plugin `{0}`: {1}")]
+    RuntimeBuild(String, io::Error),
+}
+
+/// Plugin shared with the tasks spawned on plugin's runtime, `None` after deinitialized.
+type SharedPlugin<P> = Arc<RwLock<Option<P>>>;
+
+/// Task on the plugin's runtime, aborted when dropped, like the future of a hook would be
+/// cancelled when not wrapped.
+///
+/// `&self` hooks hold a read lock of the [`SharedPlugin`] during the call, `&mut self` hooks and
+/// `deinit` a write lock. The lock is write-preferring, so a queued write blocks later reads,
+/// e.g. a handler calling its own plugin's api. Aborting the task when the caller stops waiting
+/// holds a lock no longer than the borrow of the [`DynPlugin`], and as `&mut self` hooks can not
+/// be called while a `&self` hook is running, a write is never queued while a read it would
+/// block is needed to finish.
+struct ScopedTask<T>(JoinHandle<T>);
 
-impl<P: CarolinaPlugin> UnsafePlugin<P> {
-    fn into_inner(self) -> P {
-        self.0
+impl<T> Future for ScopedTask<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
     }
 }
 
-struct UnsafePluginMutRef<P: CarolinaPlugin>(*mut P);
-struct UnsafePluginRef<P: CarolinaPlugin>(*const P);
+impl<T> Drop for ScopedTask<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
-impl<P: CarolinaPlugin> Deref for UnsafePluginRef<P> {
-    type Target = P;
+trait SpawnScoped {
+    fn spawn_scoped<F>(&self, future: F) -> ScopedTask<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static;
+}
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
+impl SpawnScoped for tok_rt::Handle {
+    fn spawn_scoped<F>(&self, future: F) -> ScopedTask<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        ScopedTask(self.spawn(future))
     }
 }
 
-impl<P: CarolinaPlugin> Deref for UnsafePluginMutRef<P> {
-    type Target = P;
+/// Wrapper running the plugin on its own tokio runtime, used for dynamically loaded plugins.
+///
+/// Unless given on creation, the runtime is built on first use, with options from
+/// [`Runtime::dyn_runtime`] of the init context or default options.
+/// Plugin info is taken once on creation.
+pub struct DynPlugin<P: CarolinaPlugin + 'static> {
+    plugin: SharedPlugin<P>,
+    info: PluginInfo,
+    async_rt: OnceLock<DynRuntime>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
+impl<P: CarolinaPlugin> DynPlugin<P> {
+    pub fn new(plug: P) -> Self {
+        Self {
+            info: plug.info(),
+            plugin: Arc::new(RwLock::new(Some(plug))),
+            async_rt: OnceLock::new(),
+        }
     }
-}
-impl<P: CarolinaPlugin> DerefMut for UnsafePluginMutRef<P> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0 }
+
+    /// Wraps the plugin running on given runtime.
+    pub fn with_runtime(plug: P, async_rt: DynRuntime) -> Self {
+        let plugin = Self::new(plug);
+        let _ = plugin.async_rt.set(async_rt);
+        plugin
     }
-}
 
-unsafe impl<P: CarolinaPlugin> Sync for UnsafePluginRef<P> {}
-unsafe impl<P: CarolinaPlugin> Send for UnsafePluginRef<P> {}
-unsafe impl<P: CarolinaPlugin> Sync for UnsafePluginMutRef<P> {}
-unsafe impl<P: CarolinaPlugin> Send for UnsafePluginMutRef<P> {}
+    fn build_rt(&self, options: Option<&DynRuntimeOptions>) -> Result<DynRuntime, DynPluginError> {
+        options
+            .cloned()
+            .unwrap_or_default()
+            .build()
+            .map_err(|e| DynPluginError::RuntimeBuild(self.info.id.clone(), e))
+    }
 
-impl<P: CarolinaPlugin> UnsafePlugin<P> {
-    fn new(plug: P) -> Self {
-        Self(plug)
+    fn init_rt(
+        &self,
+        options: Option<&DynRuntimeOptions>,
+    ) -> Result<&tok_rt::Handle, DynPluginError> {
+        if let Some(rt) = self.async_rt.get() {
+            return Ok(rt.handle());
+        }
+        let rt = self.build_rt(options)?;
+        Ok(self.async_rt.get_or_init(|| rt).handle())
     }
 
-    fn as_ref(&self) -> UnsafePluginRef<P> {
-        UnsafePluginRef(&self.0 as *const _)
+    fn async_rt(&self) -> Result<&tok_rt::Handle, DynPluginError> {
+        self.init_rt(None)
     }
 
-    fn as_ref_mut(&mut self) -> UnsafePluginMutRef<P> {
-        UnsafePluginMutRef(&mut self.0 as *mut _)
+    fn deinitialized(&self) -> DynPluginError {
+        DynPluginError::Deinitialized(self.info.id.clone())
     }
-}
 
-pub struct DynPlugin<P: CarolinaPlugin + 'static> {
-    plugin: UnsafePlugin<P>,
-    async_rt: tok_rt::Runtime,
-}
+    /// Converts error of the plugin's task, panics become [`PluginPanicked`].
+    fn panicked(&self, e: JoinError) -> Result<PluginPanicked, JoinError> {
+        e.try_into_panic()
+            .map(|payload| PluginPanicked::new(self.info.id.clone(), &*payload))
+    }
 
-impl<P: CarolinaPlugin> DynPlugin<P> {
-    pub fn new(plug: P) -> Self {
-        Self {
-            plugin: UnsafePlugin::new(plug),
-            async_rt: tok_rt::Builder::new_multi_thread().build().unwrap(),
+    fn task_error(&self, e: JoinError) -> Box<dyn StdErr> {
+        match self.panicked(e) {
+            Ok(panicked) => Box::new(panicked),
+            Err(e) => Box::new(e),
         }
     }
 }
 
 impl<P: CarolinaPlugin> CarolinaPlugin for DynPlugin<P> {
     fn info(&self) -> PluginInfo {
-        let _guard = self.async_rt.enter();
-        self.plugin.as_ref().info()
+        self.info.clone()
     }
 
     #[allow(unused)]
     async fn init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
-        let mut plugin = self.plugin.as_ref_mut();
-        self.async_rt
-            .spawn(async move { plugin.init(context).await.map_err(ErrorDisplay::boxed_send) })
-            .await?
+        let options = context.runtime().and_then(|rt| rt.dyn_runtime.as_ref());
+        let async_rt = self.init_rt(options)?;
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        async_rt
+            .spawn_scoped(in_current_span(async move {
+                let mut plugin = plugin.write_owned().await;
+                let plugin = plugin
+                    .as_mut()
+                    .ok_or_else(|| ErrorDisplay::boxed_send(deinitialized))?;
+                plugin.init(context).await.map_err(ErrorDisplay::boxed_send)
+            }))
+            .await
+            .map_err(|e| self.task_error(e))?
             .map_err(|e| e as _)
     }
 
@@ -90,25 +160,81 @@ impl<P: CarolinaPlugin> CarolinaPlugin for DynPlugin<P> {
         &mut self,
         context: PluginContext<G>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut plugin = self.plugin.as_ref_mut();
-        self.async_rt
-            .spawn(async move {
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        self.async_rt()?
+            .spawn_scoped(in_current_span(async move {
+                let mut plugin = plugin.write_owned().await;
+                let plugin = plugin
+                    .as_mut()
+                    .ok_or_else(|| ErrorDisplay::boxed_send(deinitialized))?;
                 plugin
                     .post_init(context)
                     .await
                     .map_err(ErrorDisplay::boxed_send)
-            })
-            .await?
+            }))
+            .await
+            .map_err(|e| self.task_error(e))?
+            .map_err(|e| e as _)
+    }
+
+    async fn on_enable(&mut self) -> StdResult<()> {
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        self.async_rt()?
+            .spawn_scoped(in_current_span(async move {
+                let mut plugin = plugin.write_owned().await;
+                let plugin = plugin
+                    .as_mut()
+                    .ok_or_else(|| ErrorDisplay::boxed_send(deinitialized))?;
+                plugin.on_enable().await.map_err(ErrorDisplay::boxed_send)
+            }))
+            .await
+            .map_err(|e| self.task_error(e))?
+            .map_err(|e| e as _)
+    }
+
+    async fn on_disable(&mut self) -> StdResult<()> {
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        self.async_rt()?
+            .spawn_scoped(in_current_span(async move {
+                let mut plugin = plugin.write_owned().await;
+                let plugin = plugin
+                    .as_mut()
+                    .ok_or_else(|| ErrorDisplay::boxed_send(deinitialized))?;
+                plugin.on_disable().await.map_err(ErrorDisplay::boxed_send)
+            }))
+            .await
+            .map_err(|e| self.task_error(e))?
             .map_err(|e| e as _)
     }
 
     fn subscribe_events(&mut self) -> impl Future<Output = Vec<Subscribe>> + Send + '_ {
-        let mut plugin = self.plugin.as_ref_mut();
+        let plugin = self.plugin.clone();
         async move {
-            self.async_rt
-                .spawn(async move { plugin.subscribe_events().await })
+            let async_rt = match self.async_rt() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    log::error!("{e}");
+                    return vec![];
+                }
+            };
+            async_rt
+                .spawn_scoped(in_current_span(async move {
+                    match plugin.write_owned().await.as_mut() {
+                        Some(plugin) => plugin.subscribe_events().await,
+                        None => vec![],
+                    }
+                }))
                 .await
-                .unwrap()
+                .unwrap_or_else(|e| {
+                    match self.panicked(e) {
+                        Ok(panicked) => log::error!("{panicked}"),
+                        Err(e) => log::error!("failed to subscribe events: {e}"),
+                    }
+                    vec![]
+                })
         }
     }
 
@@ -120,37 +246,70 @@ impl<P: CarolinaPlugin> CarolinaPlugin for DynPlugin<P> {
     where
         EC: EventContextTrait + Send + 'static,
     {
-        let plugin = self.plugin.as_ref();
-        self.async_rt
-            .spawn(async move {
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        self.async_rt()?
+            .spawn_scoped(in_current_span(async move {
+                let plugin = plugin.read_owned().await;
+                let plugin = plugin
+                    .as_ref()
+                    .ok_or_else(|| ErrorDisplay::boxed_send(deinitialized))?;
                 plugin
                     .handle_event(event, context)
                     .await
                     .map_err(ErrorDisplay::boxed_send)
-            })
-            .await?
+            }))
+            .await
+            .map_err(|e| self.task_error(e))?
             .map_err(|e| e as _)
     }
 
     async fn handle_api_call(&self, src: PluginRid, call: APICall) -> APIResult {
-        let plugin = self.plugin.as_ref();
-        self.async_rt
-            .spawn(async move { plugin.handle_api_call(src, call).await })
-            .await
+        let plugin = self.plugin.clone();
+        let deinitialized = self.deinitialized();
+        self.async_rt()
             .map_err(APIError::other)?
+            .spawn_scoped(in_current_span(async move {
+                let plugin = plugin.read_owned().await;
+                match plugin.as_ref() {
+                    Some(plugin) => plugin.handle_api_call(src, call).await,
+                    None => Err(APIError::other(deinitialized)),
+                }
+            }))
+            .await
+            .map_err(|e| match self.panicked(e) {
+                Ok(panicked) => APIError::PluginPanicked(panicked),
+                Err(e) => APIError::other(e),
+            })?
     }
 
     async fn deinit(self) -> Result<(), Box<dyn std::error::Error>> {
-        let DynPlugin { plugin, async_rt } = self;
+        let DynPlugin {
+            plugin,
+            info,
+            async_rt,
+        } = self;
+        // Dropped after the task completes, shutting down in background
+        let async_rt = match async_rt.into_inner() {
+            Some(rt) => rt,
+            None => DynRuntimeOptions::default()
+                .build()
+                .map_err(|e| DynPluginError::RuntimeBuild(info.id.clone(), e))?,
+        };
         async_rt
-            .spawn(async move {
-                plugin
-                    .into_inner()
-                    .deinit()
-                    .await
-                    .map_err(ErrorDisplay::boxed_send)
-            })
-            .await?
+            .handle()
+            .spawn(in_current_span(async move {
+                // Waits for running hooks, later calls see the plugin deinitialized
+                let Some(plugin) = plugin.write_owned().await.take() else {
+                    return Ok(());
+                };
+                plugin.deinit().await.map_err(ErrorDisplay::boxed_send)
+            }))
+            .await
+            .map_err(|e| match e.try_into_panic() {
+                Ok(payload) => Box::new(PluginPanicked::new(info.id, &*payload)) as _,
+                Err(e) => Box::new(e) as Box<dyn StdErr>,
+            })?
             .map_err(|e| e as _)
     }
 }
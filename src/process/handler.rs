@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use onebot_connect_interface::app::OBApp;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::*;
+use crate::*;
+
+pub(crate) fn to_value(value: impl Serialize) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+/// Serves the call with the plugin's context, calls of other plugins are made on its behalf.
+pub async fn handle_host_call(
+    context: &PluginContext<Box<dyn GlobalContextDyn>>,
+    call: HostCall,
+) -> Result<Value, String> {
+    let global: &dyn GlobalContextDyn = &**context.global();
+    match call {
+        HostCall::GetPluginRid { id } => to_value(global.get_plugin_rid(&id)),
+        HostCall::GetPluginId { rid } => to_value(global.get_plugin_id(rid)),
+        HostCall::CallPluginApi {
+            target,
+            endpoint,
+            payload,
+        } => to_value(
+            global
+                .call_plugin_api(context.rid(), target, APICall { endpoint, payload })
+                .await,
+        ),
+        HostCall::IsPluginEnabled { rid } => to_value(global.is_plugin_enabled(rid)),
+        HostCall::SetPluginEnabled { rid, enabled } => {
+            global.set_plugin_enabled(rid, enabled);
+            Ok(Value::Null)
+        }
+        HostCall::GetPluginScope { rid } => to_value(global.get_plugin_scope(rid)),
+        HostCall::SetPluginScope { rid, rules } => global
+            .set_plugin_scope(rid, rules)
+            .await
+            .map(|_| Value::Null)
+            .map_err(|e| e.to_string()),
+        HostCall::WaitSession {
+            app,
+            chat,
+            subscribe,
+            timeout_ms,
+        } => {
+            let session = Session {
+                app,
+                chat,
+                subscribe: subscribe.into(),
+            };
+            let timeout = Duration::from_millis(timeout_ms);
+            let event = global.wait_session(context.rid(), session, timeout).await;
+            Ok(event.map(|event| event_json(&event)).unwrap_or_default())
+        }
+        HostCall::GetConfigDir { rid } => global
+            .get_config_dir(rid)
+            .map_err(|e| e.to_string())
+            .and_then(to_value),
+        HostCall::GetDataDir { rid } => global
+            .get_data_dir(rid)
+            .map_err(|e| e.to_string())
+            .and_then(to_value),
+        HostCall::AppAction {
+            app,
+            action,
+            params,
+        } => {
+            let app = global
+                .get_shared_app(app)
+                .ok_or_else(|| format!("app not found: {app}"))?;
+            let data = app
+                .send_action_impl(action, params)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(data.unwrap_or_default())
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    process::{Child, Command},
+    sync::mpsc,
+    time::timeout,
+};
+
+use super::*;
+use crate::*;
+
+/// How the host talks to the plugin process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Stdin and stdout of the process, its stderr is inherited for logs.
+    #[default]
+    Stdio,
+    /// Unix socket whose path is passed by [`SOCKET_ENV`], stdio of the process is inherited.
+    #[cfg(unix)]
+    UnixSocket,
+}
+
+/// How the plugin process is connected and how long it may take.
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    transport: Transport,
+    connect_timeout: Duration,
+    call_timeout: Duration,
+    exit_timeout: Duration,
+}
+
+impl ProcessOptions {
+    /// Options connecting by `transport`, the process has 10 seconds to connect and reply its
+    /// info, 30 seconds to reply each call, and 5 seconds to exit on deinit.
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            connect_timeout: Duration::from_secs(10),
+            call_timeout: Duration::from_secs(30),
+            exit_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Sets the time to connect and reply its info, the process is killed if it expires.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the time to reply each call, e.g. handling an event, the call is cancelled and fails
+    /// with [`ProcessError::Timeout`] if it expires.
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    /// Sets the time to reply deinit and exit, the process is killed if it expires.
+    pub fn exit_timeout(mut self, timeout: Duration) -> Self {
+        self.exit_timeout = timeout;
+        self
+    }
+}
+
+type BoxedReader = Box<dyn AsyncRead + Unpin + Send>;
+type BoxedWriter = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// Plugin running as a child process, speaking the protocol in [`PluginCall`] and [`HostCall`].
+///
+/// A crashed process fails the calls with [`ProcessError::Closed`] instead of taking the host
+/// down, and the process is killed when the plugin is dropped.
+pub struct ProcessPlugin {
+    info: PluginInfo,
+    peer: Arc<Peer<PluginCall>>,
+    /// `None` if connected to a running plugin.
+    child: Option<Child>,
+    context: Arc<OnceLock<SharedPContext>>,
+    call_timeout: Duration,
+    exit_timeout: Duration,
+}
+
+impl ProcessPlugin {
+    /// Spawns the plugin process, connects to it and fetches its info.
+    ///
+    /// Fails with [`ProcessError::Timeout`] if the process does not reply in time, which kills
+    /// it.
+    pub async fn spawn(
+        mut command: Command,
+        options: ProcessOptions,
+    ) -> Result<Self, ProcessError> {
+        // The process is killed when the child is dropped on timeout
+        command.kill_on_drop(true);
+        let connect = async {
+            let (child, reader, writer) = match options.transport {
+                Transport::Stdio => spawn_stdio(command)?,
+                #[cfg(unix)]
+                Transport::UnixSocket => spawn_unix(command).await?,
+            };
+            Self::handshake(Some(child), reader, writer, &options).await
+        };
+        timeout(options.connect_timeout, connect)
+            .await
+            .map_err(|_| ProcessError::Timeout)?
+    }
+
+    /// Connects to a plugin already running, e.g. over a tcp stream, and fetches its info.
+    ///
+    /// The transport of the options is not used, and there is no process to wait for or kill.
+    pub async fn connect<R, W>(
+        reader: R,
+        writer: W,
+        options: ProcessOptions,
+    ) -> Result<Self, ProcessError>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let connect = Self::handshake(None, Box::new(reader), Box::new(writer), &options);
+        timeout(options.connect_timeout, connect)
+            .await
+            .map_err(|_| ProcessError::Timeout)?
+    }
+
+    async fn handshake(
+        child: Option<Child>,
+        reader: BoxedReader,
+        writer: BoxedWriter,
+        options: &ProcessOptions,
+    ) -> Result<Self, ProcessError> {
+        let (peer, calls) = Peer::connect(reader, writer);
+        let context = Arc::new(OnceLock::new());
+        tokio::spawn(serve_host_calls(
+            Arc::downgrade(&peer),
+            context.clone(),
+            calls,
+        ));
+
+        let info = peer
+            .call_as(PluginCall::Info {
+                protocol: PROTOCOL_VERSION,
+            })
+            .await?;
+        Ok(Self {
+            info,
+            peer,
+            child,
+            context,
+            call_timeout: options.call_timeout,
+            exit_timeout: options.exit_timeout,
+        })
+    }
+
+    /// Id of the process, `None` if it has exited or the plugin is not spawned by the host.
+    pub fn process_id(&self) -> Option<u32> {
+        self.child.as_ref().and_then(Child::id)
+    }
+
+    /// Calls the plugin, which is cancelled if the call timeout expires.
+    async fn call(&self, call: PluginCall) -> Result<Value, ProcessError> {
+        timeout(self.call_timeout, self.peer.call(call))
+            .await
+            .map_err(|_| ProcessError::Timeout)?
+    }
+
+    async fn call_as<T: DeserializeOwned>(&self, call: PluginCall) -> Result<T, ProcessError> {
+        Ok(serde_json::from_value(self.call(call).await?)?)
+    }
+
+    async fn call_unit(&self, call: PluginCall) -> StdResult<()> {
+        self.call(call).await?;
+        Ok(())
+    }
+}
+
+fn spawn_stdio(mut command: Command) -> Result<(Child, BoxedReader, BoxedWriter), ProcessError> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().ok_or(ProcessError::Closed)?;
+    let stdin = child.stdin.take().ok_or(ProcessError::Closed)?;
+    Ok((child, Box::new(stdout), Box::new(stdin)))
+}
+
+#[cfg(unix)]
+async fn spawn_unix(
+    mut command: Command,
+) -> Result<(Child, BoxedReader, BoxedWriter), ProcessError> {
+    use std::os::unix::fs::DirBuilderExt;
+    use tokio::net::UnixListener;
+
+    /// Private directory of the socket, removed once connected or given up.
+    struct SocketDir(std::path::PathBuf);
+
+    impl Drop for SocketDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    static NEXT_SOCKET: AtomicU64 = AtomicU64::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "carolina-plugin-{}-{}",
+        std::process::id(),
+        NEXT_SOCKET.fetch_add(1, Ordering::Relaxed)
+    ));
+    // Only the owner can reach the socket, and creating fails if someone else made the directory
+    std::fs::DirBuilder::new().mode(0o700).create(&path)?;
+    let dir = SocketDir(path);
+    let path = dir.0.join("plugin.sock");
+    let listener = UnixListener::bind(&path)?;
+    command.env(SOCKET_ENV, &path);
+    let mut child = command.spawn();
+
+    let accepted = match &mut child {
+        Ok(child) => tokio::select! {
+            accepted = listener.accept() => accepted.map_err(ProcessError::from),
+            status = child.wait() => Err(status.map_or_else(ProcessError::from, ProcessError::Exited)),
+        },
+        Err(_) => Err(ProcessError::Closed),
+    };
+    drop(dir);
+
+    let child = child?;
+    let (stream, _) = accepted?;
+    let (reader, writer) = stream.into_split();
+    Ok((child, Box::new(reader), Box::new(writer)))
+}
+
+/// Holds the peer weakly, so dropping the plugin closes the connection.
+async fn serve_host_calls(
+    peer: Weak<Peer<PluginCall>>,
+    context: Arc<OnceLock<SharedPContext>>,
+    mut calls: mpsc::UnboundedReceiver<(u64, HostCall)>,
+) {
+    while let Some((id, call)) = calls.recv().await {
+        let Some(peer) = peer.upgrade() else {
+            break;
+        };
+        let context = context.clone();
+        peer.spawn_reply(id, async move {
+            match context.get() {
+                Some(context) => handle_host_call(context, call).await,
+                None => Err("plugin is not initialized".to_owned()),
+            }
+        });
+    }
+}
+
+impl CarolinaPlugin for ProcessPlugin {
+    fn info(&self) -> PluginInfo {
+        self.info.clone()
+    }
+
+    async fn init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        let rid = context.rid();
+        let _ = self.context.set(context.into_dyn().into_shared());
+        self.call_unit(PluginCall::Init { rid }).await
+    }
+
+    #[allow(unused)]
+    async fn post_init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        self.call_unit(PluginCall::PostInit).await
+    }
+
+    async fn on_enable(&mut self) -> StdResult<()> {
+        self.call_unit(PluginCall::OnEnable).await
+    }
+
+    async fn on_disable(&mut self) -> StdResult<()> {
+        self.call_unit(PluginCall::OnDisable).await
+    }
+
+    async fn subscribe_events(&mut self) -> Vec<Subscribe> {
+        match self
+            .call_as::<Vec<SubscribeWire>>(PluginCall::SubscribeEvents)
+            .await
+        {
+            Ok(subscribes) => subscribes.into_iter().map(Subscribe::from).collect(),
+            Err(e) => {
+                log::error!("failed to get subscriptions of `{}`: {e}", self.info.id);
+                vec![]
+            }
+        }
+    }
+
+    async fn handle_event<EC>(&self, event: SharedEvent, context: EC) -> StdResult<EventState>
+    where
+        EC: EventContextTrait + Send + 'static,
+    {
+        let call = PluginCall::HandleEvent {
+            app: context.app_marker(),
+            event: event_json(&event),
+        };
+        Ok(self.call_as(call).await?)
+    }
+
+    async fn handle_api_call(&self, src: PluginRid, call: APICall) -> APIResult {
+        let call = PluginCall::HandleApiCall {
+            src,
+            endpoint: call.endpoint,
+            payload: call.payload,
+        };
+        api_result(self.call(call).await)
+    }
+
+    async fn deinit(mut self) -> StdResult<()> {
+        let exit = async {
+            self.peer.call(PluginCall::Deinit).await?;
+            if let Some(child) = &mut self.child {
+                child.wait().await?;
+            }
+            Ok::<_, ProcessError>(())
+        };
+        let result = match timeout(self.exit_timeout, exit).await {
+            Ok(result) => result,
+            Err(_) => Err(ProcessError::Timeout),
+        };
+        if let (Err(_), Some(child)) = (&result, &mut self.child) {
+            child.kill().await?;
+        }
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::duplex, sync::oneshot};
+
+    use super::*;
+
+    /// Plugin end which replies its info, and never replies other calls until they are
+    /// cancelled, which drops the sender of `cancelled`.
+    fn fake_plugin<S>(stream: S, cancelled: oneshot::Sender<()>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let (peer, mut calls) = Peer::<HostCall>::connect::<PluginCall, _, _>(reader, writer);
+        let mut cancelled = Some(cancelled);
+        tokio::spawn(async move {
+            while let Some((id, call)) = calls.recv().await {
+                if let PluginCall::Info { .. } = call {
+                    peer.reply(
+                        id,
+                        serde_json::to_value(PluginInfoBuilder::new("fake").build()),
+                    );
+                    continue;
+                }
+                let cancelled = cancelled.take();
+                peer.spawn_reply(id, async move {
+                    let _cancelled = cancelled;
+                    std::future::pending::<Result<Value, String>>().await
+                });
+            }
+        });
+    }
+
+    fn options() -> ProcessOptions {
+        ProcessOptions::new(Transport::Stdio)
+            .connect_timeout(Duration::from_secs(1))
+            .call_timeout(Duration::from_millis(50))
+    }
+
+    #[tokio::test]
+    async fn call_timeout_cancels_the_call() {
+        let (host, plugin) = duplex(4096);
+        let (cancelled_tx, cancelled) = oneshot::channel();
+        fake_plugin(plugin, cancelled_tx);
+
+        let (reader, writer) = tokio::io::split(host);
+        let plugin = ProcessPlugin::connect(reader, writer, options())
+            .await
+            .unwrap();
+        assert_eq!(plugin.info().id, "fake");
+        assert_eq!(plugin.process_id(), None);
+
+        let call = APICall {
+            endpoint: Endpoint::new(1),
+            payload: Value::Null,
+        };
+        assert!(plugin
+            .handle_api_call(PluginRid::new(1), call)
+            .await
+            .is_err());
+        let aborted = timeout(Duration::from_secs(1), cancelled).await.unwrap();
+        assert!(aborted.is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_without_info() {
+        let (host, _plugin) = duplex(4096);
+        let (reader, writer) = tokio::io::split(host);
+        let options = options().connect_timeout(Duration::from_millis(50));
+        let result = ProcessPlugin::connect(reader, writer, options).await;
+        assert!(matches!(result, Err(ProcessError::Timeout)));
+    }
+}
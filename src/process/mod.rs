@@ -0,0 +1,13 @@
+mod handler;
+#[cfg(feature = "process")]
+mod host;
+#[cfg(feature = "process")]
+mod peer;
+mod protocol;
+
+pub use handler::*;
+#[cfg(feature = "process")]
+pub use host::*;
+#[cfg(feature = "process")]
+pub use peer::*;
+pub use protocol::*;
@@ -0,0 +1,379 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use fxhash::FxHashMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+    task::AbortHandle,
+};
+
+use super::*;
+
+enum Outgoing {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Senders of pending calls, `None` once the reader reaches the end.
+type Pending = Arc<Mutex<Option<FxHashMap<u64, oneshot::Sender<Reply>>>>>;
+
+/// Tasks answering calls from the other end, aborted if it cancels them.
+type Serving = Arc<Mutex<FxHashMap<u64, AbortHandle>>>;
+
+/// One end of the connection, sending calls of type `C` and replies as json lines.
+///
+/// The connection is closed once the peer is dropped.
+pub struct Peer<C> {
+    tx: mpsc::UnboundedSender<Outgoing>,
+    pending: Pending,
+    serving: Serving,
+    next_id: AtomicU64,
+    reader: AbortHandle,
+    _call: PhantomData<fn(C)>,
+}
+
+impl<C> Drop for Peer<C> {
+    fn drop(&mut self) {
+        // The writer stops as senders are dropped
+        self.reader.abort();
+    }
+}
+
+impl<C: Serialize> Peer<C> {
+    /// Connects over the reader and writer, spawning io tasks on current tokio runtime.
+    ///
+    /// Calls from the other end are received from the returned channel, and should be answered
+    /// by [`reply`](Self::reply) or [`spawn_reply`](Self::spawn_reply). Pending calls fail with
+    /// [`ProcessError::Closed`] once the reader reaches the end.
+    pub fn connect<I, R, W>(reader: R, writer: W) -> (Arc<Self>, mpsc::UnboundedReceiver<(u64, I)>)
+    where
+        I: DeserializeOwned + Send + 'static,
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, out_rx) = mpsc::unbounded_channel();
+        let (call_tx, call_rx) = mpsc::unbounded_channel();
+        let pending = Pending::new(Mutex::new(Some(Default::default())));
+        let serving = Serving::default();
+
+        tokio::spawn(write_lines(writer, out_rx));
+        let reader = tokio::spawn(read_lines(
+            reader,
+            tx.clone(),
+            call_tx,
+            pending.clone(),
+            serving.clone(),
+        ));
+
+        let peer = Self {
+            tx,
+            pending,
+            serving,
+            next_id: AtomicU64::new(0),
+            reader: reader.abort_handle(),
+            _call: PhantomData,
+        };
+        (Arc::new(peer), call_rx)
+    }
+
+    /// Calls the other end, which is told to abort the call if the future is dropped before the
+    /// reply, e.g. on timeout.
+    pub async fn call(&self, call: C) -> Result<Value, ProcessError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let line = serde_json::to_string(&Message::Call { id, call })?;
+
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, tx),
+            None => return Err(ProcessError::Closed),
+        };
+        let mut guard = CallGuard {
+            peer: self,
+            id,
+            done: false,
+        };
+        if self.tx.send(Outgoing::Line(line)).is_err() {
+            return Err(ProcessError::Closed);
+        }
+        let reply = rx.await;
+        guard.done = true;
+        match reply.map_err(|_| ProcessError::Closed)? {
+            Reply::Ok(value) => Ok(value),
+            Reply::Err(e) => Err(ProcessError::Remote(e)),
+        }
+    }
+
+    /// Calls and deserializes the reply.
+    pub async fn call_as<T: DeserializeOwned>(&self, call: C) -> Result<T, ProcessError> {
+        Ok(serde_json::from_value(self.call(call).await?)?)
+    }
+
+    pub fn reply(&self, id: u64, reply: impl Into<Reply>) {
+        send_reply(&self.tx, id, reply.into());
+    }
+
+    /// Answers the call from the other end by the future, on a new task which is aborted if
+    /// the other end cancels the call.
+    pub fn spawn_reply<F>(self: &Arc<Self>, id: u64, reply: F)
+    where
+        C: 'static,
+        F: Future<Output: Into<Reply>> + Send + 'static,
+    {
+        let peer = self.clone();
+        // Locked until the handle is inserted, so the task can not finish before
+        let mut serving = self.serving.lock().unwrap();
+        let task = tokio::spawn(async move {
+            let reply = reply.await;
+            peer.serving.lock().unwrap().remove(&id);
+            peer.reply(id, reply);
+        });
+        serving.insert(id, task.abort_handle());
+    }
+
+    /// Waits until all lines sent before are written.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(Outgoing::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+/// Removes the pending call and cancels it on the other end, unless it is done.
+struct CallGuard<'a, C: Serialize> {
+    peer: &'a Peer<C>,
+    id: u64,
+    done: bool,
+}
+
+impl<C: Serialize> Drop for CallGuard<'_, C> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(pending) = self.peer.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.id);
+        }
+        let message = Message::<C>::Cancel { id: self.id };
+        if let Ok(line) = serde_json::to_string(&message) {
+            let _ = self.peer.tx.send(Outgoing::Line(line));
+        }
+    }
+}
+
+fn send_reply(tx: &mpsc::UnboundedSender<Outgoing>, id: u64, reply: Reply) {
+    match serde_json::to_string(&Message::<()>::Reply { id, reply }) {
+        Ok(line) => {
+            let _ = tx.send(Outgoing::Line(line));
+        }
+        Err(e) => log::error!("failed to serialize reply {id}: {e}"),
+    }
+}
+
+/// Head of a message which failed to parse, to answer a malformed call.
+#[derive(Deserialize)]
+struct MessageHead {
+    #[serde(rename = "type")]
+    kind: String,
+    id: Option<u64>,
+}
+
+async fn write_lines<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut rx: mpsc::UnboundedReceiver<Outgoing>,
+) {
+    while let Some(out) = rx.recv().await {
+        let result = match out {
+            Outgoing::Line(mut line) => {
+                line.push('\n');
+                match writer.write_all(line.as_bytes()).await {
+                    // Stdout of tokio is buffered
+                    Ok(()) if rx.is_empty() => writer.flush().await,
+                    result => result,
+                }
+            }
+            Outgoing::Flush(done) => {
+                let result = writer.flush().await;
+                let _ = done.send(());
+                result
+            }
+        };
+        if let Err(e) = result {
+            log::error!("failed to write to plugin connection: {e}");
+            break;
+        }
+    }
+}
+
+async fn read_lines<I, R>(
+    reader: R,
+    tx: mpsc::UnboundedSender<Outgoing>,
+    calls: mpsc::UnboundedSender<(u64, I)>,
+    pending: Pending,
+    serving: Serving,
+) where
+    I: DeserializeOwned,
+    R: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("failed to read from plugin connection: {e}");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Message<I>>(&line) {
+            Ok(Message::Call { id, call }) => {
+                let _ = calls.send((id, call));
+            }
+            Ok(Message::Reply { id, reply }) => {
+                let tx = pending.lock().unwrap().as_mut().and_then(|p| p.remove(&id));
+                if let Some(tx) = tx {
+                    let _ = tx.send(reply);
+                }
+            }
+            Ok(Message::Cancel { id }) => {
+                if let Some(task) = serving.lock().unwrap().remove(&id) {
+                    task.abort();
+                }
+            }
+            Err(e) => {
+                log::warn!("invalid message from plugin connection: {e}");
+                // The caller would wait for the reply forever
+                if let Ok(MessageHead { kind, id: Some(id) }) = serde_json::from_str(&line) {
+                    if kind == "call" {
+                        send_reply(&tx, id, Reply::Err(format!("invalid call: {e}")));
+                    }
+                }
+            }
+        }
+    }
+    // Drops senders, failing pending calls
+    pending.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::{
+        io::{duplex, split, DuplexStream},
+        time::timeout,
+    };
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(unused)]
+    struct Add {
+        n: i64,
+    }
+
+    type TestPeer = (Arc<Peer<Value>>, mpsc::UnboundedReceiver<(u64, Value)>);
+
+    fn pair() -> (TestPeer, TestPeer) {
+        let (a, b) = duplex(4096);
+        let connect = |stream: DuplexStream| {
+            let (reader, writer) = split(stream);
+            Peer::connect(reader, writer)
+        };
+        (connect(a), connect(b))
+    }
+
+    #[tokio::test]
+    async fn call_and_reply() {
+        let ((caller, _), (callee, mut calls)) = pair();
+        tokio::spawn(async move {
+            while let Some((id, call)) = calls.recv().await {
+                let reply = match call["n"].as_i64() {
+                    Some(n) => Ok(json!(n + 1)),
+                    None => Err("missing n"),
+                };
+                callee.reply(id, reply);
+            }
+        });
+        assert_eq!(caller.call(json!({"n": 1})).await.unwrap(), json!(2));
+        assert!(matches!(
+            caller.call(json!({})).await,
+            Err(ProcessError::Remote(e)) if e == "missing n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_call_cancels_the_reply_task() {
+        let ((caller, _), (callee, mut calls)) = pair();
+        let (cancelled_tx, cancelled) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (id, _) = calls.recv().await.unwrap();
+            callee.spawn_reply(id, async move {
+                let _cancelled = cancelled_tx;
+                std::future::pending::<Result<Value, String>>().await
+            });
+            // Keeps the peer open
+            calls.recv().await;
+        });
+
+        let call = timeout(Duration::from_millis(50), caller.call(json!({})));
+        assert!(call.await.is_err());
+        let aborted = timeout(Duration::from_secs(1), cancelled).await.unwrap();
+        assert!(aborted.is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_call_is_replied_with_error() {
+        let (a, b) = duplex(4096);
+        let (reader, writer) = split(a);
+        let (peer, _calls) = Peer::<Value>::connect::<Add, _, _>(reader, writer);
+
+        let (reader, mut writer) = split(b);
+        writer
+            .write_all(b"{\"type\": \"call\", \"id\": 3, \"n\": \"one\"}\n")
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let line = timeout(Duration::from_secs(1), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let reply: Message<()> = serde_json::from_str(&line).unwrap();
+        assert!(matches!(
+            reply,
+            Message::Reply { id: 3, reply: Reply::Err(e) } if e.starts_with("invalid call")
+        ));
+        drop(peer);
+    }
+
+    #[tokio::test]
+    async fn closed_connection_fails_pending_calls() {
+        let ((caller, _), (callee, mut calls)) = pair();
+        tokio::spawn(async move {
+            calls.recv().await;
+            drop(callee);
+        });
+        assert!(matches!(
+            caller.call(json!({})).await,
+            Err(ProcessError::Closed)
+        ));
+    }
+}
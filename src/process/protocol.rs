@@ -0,0 +1,239 @@
+use std::{fmt::Display, io};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::*;
+
+/// Version of the protocol, checked by the `info` call.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Env var holding the unix socket path, the plugin uses stdio if it is absent.
+pub const SOCKET_ENV: &str = "CAROLINA_PLUGIN_SOCKET";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid message: {0}")]
+    Codec(#[from] serde_json::Error),
+    #[error("connection to the plugin process is closed")]
+    Closed,
+    #[error("plugin process exited before connecting: {0}")]
+    Exited(std::process::ExitStatus),
+    #[error("plugin process timed out")]
+    Timeout,
+    #[error("remote error: {0}")]
+    Remote(String),
+}
+
+impl From<ProcessError> for APIError {
+    fn from(e: ProcessError) -> Self {
+        match e {
+            ProcessError::Remote(e) => Self::Error(e),
+            e => Self::other(e),
+        }
+    }
+}
+
+/// Call from host to the plugin process.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum PluginCall {
+    /// First call after connecting, replied with [`PluginInfo`].
+    Info {
+        protocol: u32,
+    },
+    Init {
+        rid: PluginRid,
+    },
+    PostInit,
+    OnEnable,
+    OnDisable,
+    /// Replied with a list of [`SubscribeWire`].
+    SubscribeEvents,
+    /// Replied with [`EventState`].
+    HandleEvent {
+        app: AppRid,
+        event: Value,
+    },
+    /// Replied with the [`APIResult`], see [`api_result`].
+    HandleApiCall {
+        src: PluginRid,
+        endpoint: Endpoint,
+        payload: Value,
+    },
+    /// Last call, the process exits after replying.
+    Deinit,
+}
+
+/// Call from the plugin process to host, for the plugin's [`GlobalContext`] and apps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum HostCall {
+    GetPluginRid {
+        id: String,
+    },
+    GetPluginId {
+        rid: PluginRid,
+    },
+    /// Replied with the [`APIResult`], see [`api_result`].
+    CallPluginApi {
+        target: PluginRid,
+        endpoint: Endpoint,
+        payload: Value,
+    },
+    IsPluginEnabled {
+        rid: PluginRid,
+    },
+    SetPluginEnabled {
+        rid: PluginRid,
+        enabled: bool,
+    },
+    GetPluginScope {
+        rid: PluginRid,
+    },
+    SetPluginScope {
+        rid: PluginRid,
+        rules: ScopeRules,
+    },
+    /// Replied with the event, or `null` on timeout or if the waiter is cancelled.
+    WaitSession {
+        app: AppRid,
+        chat: EventChat,
+        subscribe: SubscribeWire,
+        timeout_ms: u64,
+    },
+    GetConfigDir {
+        rid: Option<PluginRid>,
+    },
+    GetDataDir {
+        rid: Option<PluginRid>,
+    },
+    /// Sends an action by the app, replied with its response data.
+    AppAction {
+        app: AppRid,
+        action: String,
+        params: Value,
+    },
+}
+
+/// Plugin's own directory, which a sandboxed plugin accesses by relative paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginDir {
+    Config,
+    Data,
+}
+
+/// Call from a sandboxed plugin to host, which has no file system or logger of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum SandboxCall {
+    /// Replied with the content, or `null` if the file does not exist.
+    ReadFile { dir: PluginDir, path: String },
+    WriteFile {
+        dir: PluginDir,
+        path: String,
+        content: String,
+    },
+    /// `level` is one of `error`, `warn`, `info`, `debug` and `trace`.
+    Log { level: String, message: String },
+}
+
+/// Call from a sandboxed plugin to host.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GuestCall {
+    Host(HostCall),
+    Sandbox(SandboxCall),
+}
+
+/// Reply to a call, `{"ok": ..}` or `{"err": ".."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Ok(Value),
+    Err(String),
+}
+
+impl<E: Display> From<Result<Value, E>> for Reply {
+    fn from(result: Result<Value, E>) -> Self {
+        match result {
+            Ok(value) => Self::Ok(value),
+            Err(e) => Self::Err(e.to_string()),
+        }
+    }
+}
+
+/// Reads the reply of an api call, which carries the whole [`APIResult`] so its [`APIError`]
+/// stays typed, failures of the call itself become [`APIError::Error`].
+pub fn api_result<E: Into<APIError>>(reply: Result<Value, E>) -> APIResult {
+    serde_json::from_value(reply.map_err(Into::into)?).unwrap_or_else(|e| Err(APIError::other(e)))
+}
+
+/// A line of the protocol, calls of each side are numbered by their own ids.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message<C> {
+    Call {
+        id: u64,
+        #[serde(flatten)]
+        call: C,
+    },
+    Reply {
+        id: u64,
+        #[serde(flatten)]
+        reply: Reply,
+    },
+    /// The caller no longer waits for the call, which is aborted without a reply.
+    Cancel { id: u64 },
+}
+
+/// [`Subscribe`] on the wire, custom filters can not be sent and are checked by the plugin
+/// itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeWire {
+    pub event_type: String,
+    pub detail_type: Option<String>,
+    pub sub_type: Option<String>,
+    pub app: Option<AppRid>,
+    pub self_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<EventFilter>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+impl From<&Subscribe> for SubscribeWire {
+    fn from(subscribe: &Subscribe) -> Self {
+        Self {
+            event_type: subscribe.event_type.clone(),
+            detail_type: subscribe.detail_type.clone(),
+            sub_type: subscribe.sub_type.clone(),
+            app: subscribe.app,
+            self_id: subscribe.self_id.clone(),
+            filters: subscribe
+                .filters
+                .iter()
+                .filter(|filter| !filter.is_custom())
+                .cloned()
+                .collect(),
+            priority: subscribe.priority,
+        }
+    }
+}
+
+impl From<SubscribeWire> for Subscribe {
+    fn from(wire: SubscribeWire) -> Self {
+        Self {
+            event_type: wire.event_type,
+            detail_type: wire.detail_type,
+            sub_type: wire.sub_type,
+            app: wire.app,
+            self_id: wire.self_id,
+            filters: wire.filters,
+            priority: wire.priority,
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use onebot_connect_interface::app::OBApp;
+use rhai::{
+    module_resolvers::FileModuleResolver,
+    packages::{Package, StandardPackage},
+    serde::{from_dynamic, to_dynamic},
+    CallFnOptions, Dynamic, Engine, EvalAltResult, Module, Scope, Shared, AST,
+};
+use serde_json::{json, Value};
+use tokio::{runtime::Handle, time::Instant};
+
+use crate::*;
+
+pub(super) type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Debug, thiserror::Error)]
+pub(super) enum ScriptError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("parse error: {0}")]
+    Parse(#[from] rhai::ParseError),
+    #[error("{0}")]
+    Eval(#[from] Box<EvalAltResult>),
+}
+
+/// Handler of events, called with the event and returning `true` to intercept it.
+const ON_EVENT: &str = "on_event";
+
+/// Script compiled from a file of the config dir, with its declarations.
+pub(super) struct Script {
+    pub name: String,
+    pub modified: SystemTime,
+    pub subscribes: Vec<Subscribe>,
+    pub time_limit: Duration,
+    ast: AST,
+}
+
+#[derive(Default)]
+struct Declarations {
+    subscribes: Vec<Subscribe>,
+    time_limit: Option<Duration>,
+}
+
+fn parse_priority(priority: &str) -> ScriptResult<Priority> {
+    Ok(match priority.to_ascii_lowercase().as_str() {
+        "lowest" => Priority::Lowest,
+        "low" => Priority::Low,
+        "medium" => Priority::Medium,
+        "high" => Priority::High,
+        "highest" => Priority::Highest,
+        _ => return Err(format!("invalid priority `{priority}`").into()),
+    })
+}
+
+fn detail_type(detail_type: Dynamic) -> ScriptResult<Option<String>> {
+    if detail_type.is_unit() {
+        return Ok(None);
+    }
+    detail_type
+        .into_string()
+        .map(Some)
+        .map_err(|ty| format!("detail type must be a string or `()`, got {ty}").into())
+}
+
+/// Deadline after `limit`, saturating as the host may set a huge limit.
+fn deadline_after(limit: Duration) -> Instant {
+    let now = Instant::now();
+    // Same far future as tokio, roughly 30 years
+    now.checked_add(limit)
+        .unwrap_or_else(|| now + Duration::from_secs(86400 * 365 * 30))
+}
+
+/// Standard library of rhai, built once and shared by the engines of all runs.
+fn standard_package() -> Shared<Module> {
+    static PACKAGE: OnceLock<Shared<Module>> = OnceLock::new();
+    PACKAGE
+        .get_or_init(|| StandardPackage::new().as_shared_module())
+        .clone()
+}
+
+/// Engine failing the script once `deadline` is passed, with `print` and `debug` going to the
+/// plugin's logger.
+///
+/// Like `Engine::new`, without building the standard library again for each run.
+fn engine(name: &str, deadline: Instant, logger: &PluginLogger) -> Engine {
+    let mut engine = Engine::new_raw();
+    engine.register_global_module(standard_package());
+    engine.set_module_resolver(FileModuleResolver::new());
+    // Defaults of release builds, debug builds of rhai are stricter
+    engine.set_max_expr_depths(64, 32);
+    engine.on_progress(move |_| {
+        (Instant::now() >= deadline).then(|| Dynamic::from("time limit exceeded"))
+    });
+    let (print, name) = (logger.clone(), name.to_owned());
+    engine.on_print(move |text| log::info!(logger: print, "[{name}] {text}"));
+    let debug = logger.clone();
+    engine.on_debug(move |text, source, pos| {
+        log::debug!(logger: debug, "[{}:{pos}] {text}", source.unwrap_or_default())
+    });
+    engine
+}
+
+/// Reports termination by [`engine`] as what it is.
+fn terminated(e: Box<EvalAltResult>) -> Box<EvalAltResult> {
+    match *e {
+        EvalAltResult::ErrorTerminated(_, pos) => {
+            EvalAltResult::ErrorRuntime("time limit exceeded".into(), pos).into()
+        }
+        _ => e,
+    }
+}
+
+/// Runs the future on the runtime, failing it once `deadline` is passed.
+fn block_on<F: Future>(handle: &Handle, deadline: Instant, future: F) -> ScriptResult<F::Output> {
+    handle
+        .block_on(tokio::time::timeout_at(deadline, future))
+        .map_err(|_| "time limit exceeded".into())
+}
+
+fn to_json(value: &Dynamic) -> ScriptResult<Value> {
+    from_dynamic(value)
+}
+
+/// Params of `send_message` replying to the chat of the event.
+fn reply_params(event: &Value, message: Value) -> Value {
+    let chat = EventChat::from_json(event);
+    match chat {
+        EventChat {
+            group_id: Some(group_id),
+            ..
+        } => json!({"detail_type": "group", "group_id": group_id, "message": message}),
+        EventChat {
+            guild_id: Some(guild_id),
+            channel_id: Some(channel_id),
+            ..
+        } => json!({
+            "detail_type": "channel",
+            "guild_id": guild_id,
+            "channel_id": channel_id,
+            "message": message,
+        }),
+        EventChat { user_id, .. } => {
+            json!({"detail_type": "private", "user_id": user_id, "message": message})
+        }
+    }
+}
+
+/// What a handler of the script may reach, besides the event itself.
+pub(super) struct EventEnv<A> {
+    pub app: Arc<A>,
+    pub context: SharedPContext,
+    pub handle: Handle,
+}
+
+impl Script {
+    /// Compiles the script and runs its top-level statements, which declare subscriptions.
+    ///
+    /// Blocks the current thread, `time_limit` is used unless the script declares a lower one.
+    pub fn load(
+        path: &Path,
+        modified: SystemTime,
+        time_limit: Duration,
+        logger: &PluginLogger,
+    ) -> Result<Self, ScriptError> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let source = std::fs::read_to_string(path)?;
+
+        let declarations = Arc::new(Mutex::new(Declarations::default()));
+        let mut engine = engine(&name, deadline_after(time_limit), logger);
+        let subscribe = {
+            let declarations = declarations.clone();
+            move |event_type: &str, detail: Option<String>, priority: Priority| {
+                let subscribe = Subscribe::new(event_type, detail).priority(priority);
+                declarations.lock().unwrap().subscribes.push(subscribe);
+            }
+        };
+        let subscribe1 = subscribe.clone();
+        engine.register_fn("subscribe", move |event_type: &str| {
+            subscribe1(event_type, None, Priority::default())
+        });
+        let subscribe2 = subscribe.clone();
+        engine.register_fn("subscribe", move |event_type: &str, detail: Dynamic| {
+            subscribe2(event_type, detail_type(detail)?, Priority::default());
+            ScriptResult::Ok(())
+        });
+        engine.register_fn(
+            "subscribe",
+            move |event_type: &str, detail: Dynamic, priority: &str| {
+                subscribe(event_type, detail_type(detail)?, parse_priority(priority)?);
+                ScriptResult::Ok(())
+            },
+        );
+        let limit = declarations.clone();
+        engine.register_fn("time_limit", move |millis: i64| {
+            let millis = u64::try_from(millis).map_err(|_| "time limit must not be negative")?;
+            limit.lock().unwrap().time_limit = Some(Duration::from_millis(millis));
+            ScriptResult::Ok(())
+        });
+
+        let mut ast = engine.compile(source)?;
+        ast.set_source(name.as_str());
+        engine.run_ast(&ast).map_err(terminated)?;
+        drop(engine);
+
+        let declarations = std::mem::take(&mut *declarations.lock().unwrap());
+        Ok(Self {
+            name,
+            modified,
+            subscribes: declarations.subscribes,
+            time_limit: declarations
+                .time_limit
+                .map_or(time_limit, |declared| declared.min(time_limit)),
+            ast,
+        })
+    }
+
+    pub fn handles_events(&self) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == ON_EVENT && f.params.len() == 1)
+    }
+
+    /// Calls the event handler of the script, blocking the current thread.
+    pub fn on_event<A: OBApp + Send + Sync + 'static>(
+        &self,
+        event: &Value,
+        env: EventEnv<A>,
+    ) -> ScriptResult<EventState> {
+        let deadline = deadline_after(self.time_limit);
+        let mut engine = engine(&self.name, deadline, env.context.logger());
+        let env = Arc::new(env);
+
+        let send_action = {
+            let env = env.clone();
+            move |action: &str, params: Value| -> ScriptResult<Dynamic> {
+                let send = env.app.send_action_impl(action.to_owned(), params);
+                let data = block_on(&env.handle, deadline, send)?
+                    .map_err(|e| format!("failed to send action `{action}`: {e}"))?;
+                to_dynamic(data.unwrap_or_default())
+            }
+        };
+        let reply = send_action.clone();
+        let chat = event.clone();
+        engine.register_fn("reply", move |message: Dynamic| {
+            let message = if message.is_string() {
+                json!([{"type": "text", "data": {"text": message.to_string()}}])
+            } else {
+                to_json(&message)?
+            };
+            reply("send_message", reply_params(&chat, message))
+        });
+        engine.register_fn("send_action", move |action: &str, params: rhai::Map| {
+            send_action(action, to_json(&params.into())?)
+        });
+        engine.register_fn("message_text", |event: rhai::Map| {
+            let text = to_json(&event.into())?;
+            ScriptResult::Ok(message_text(&text).map_or(Dynamic::UNIT, Dynamic::from))
+        });
+        let api = env.clone();
+        engine.register_fn(
+            "call_api",
+            move |plugin: &str, endpoint: i64, payload: Dynamic| {
+                let context = &api.context;
+                let target = context
+                    .get_plugin_rid(plugin)
+                    .ok_or_else(|| format!("plugin `{plugin}` not found"))?;
+                let endpoint = Endpoint::new(
+                    u64::try_from(endpoint).map_err(|_| "endpoint must not be negative")?,
+                );
+                let call = APICall {
+                    endpoint,
+                    payload: to_json(&payload)?,
+                };
+                let global: &dyn GlobalContextDyn = &**context.global();
+                let result = block_on(
+                    &api.handle,
+                    deadline,
+                    instrument_api_call(
+                        global.call_plugin_api(context.rid(), target, call),
+                        context.rid(),
+                        target,
+                        endpoint,
+                    ),
+                )?
+                .map_err(|e| e.to_string())?;
+                to_dynamic(result)
+            },
+        );
+
+        let options = CallFnOptions::new().eval_ast(false);
+        let state: Dynamic = engine
+            .call_fn_with_options(
+                options,
+                &mut Scope::new(),
+                &self.ast,
+                ON_EVENT,
+                (to_dynamic(event)?,),
+            )
+            .map_err(terminated)?;
+        Ok(match state.as_bool() {
+            Ok(true) => EventState::Intercept,
+            _ => EventState::Pass,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn logger() -> PluginLogger {
+        PluginLogger::new(None, "scripts", PluginRid::new(1), log::LevelFilter::Off)
+    }
+
+    /// Writes the script to a file of its own, named by the test.
+    fn script_file(test: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "carolina-script-test-{}-{test}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{test}.rhai"));
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn load(path: &Path, time_limit: Duration) -> Result<Script, ScriptError> {
+        Script::load(path, SystemTime::now(), time_limit, &logger())
+    }
+
+    #[test]
+    fn load_declarations() {
+        let path = script_file(
+            "load",
+            r#"
+            subscribe("message", "group", "high");
+            subscribe("notice");
+            time_limit(200);
+
+            fn on_event(event) {
+                true
+            }
+            "#,
+        );
+        let script = load(&path, Duration::from_secs(1)).unwrap();
+        assert_eq!(script.name, "load.rhai");
+        assert!(script.handles_events());
+        assert_eq!(script.time_limit, Duration::from_millis(200));
+
+        let [group, notice] = &script.subscribes[..] else {
+            panic!("expected 2 subscriptions, got {:?}", script.subscribes);
+        };
+        assert_eq!(group.event_type, "message");
+        assert_eq!(group.detail_type.as_deref(), Some("group"));
+        assert_eq!(group.priority, Priority::High);
+        assert_eq!(notice.event_type, "notice");
+        assert_eq!(notice.detail_type, None);
+    }
+
+    #[test]
+    fn declared_time_limit_is_capped() {
+        let path = script_file("capped", "time_limit(5000);");
+        let script = load(&path, Duration::from_secs(1)).unwrap();
+        assert_eq!(script.time_limit, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn time_limit_terminates_the_script() {
+        let path = script_file("endless", "loop {}");
+        let started = std::time::Instant::now();
+        let Err(ScriptError::Eval(e)) = load(&path, Duration::from_millis(50)) else {
+            panic!("endless script loaded");
+        };
+        assert!(e.to_string().contains("time limit exceeded"), "{e}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn invalid_declarations_fail_to_load() {
+        let path = script_file("negative", "time_limit(-1);");
+        assert!(load(&path, Duration::from_secs(1)).is_err());
+        let path = script_file("syntax", "fn on_event(event) {");
+        assert!(matches!(
+            load(&path, Duration::from_secs(1)),
+            Err(ScriptError::Parse(_))
+        ));
+    }
+}
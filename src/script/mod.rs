@@ -0,0 +1,5 @@
+mod engine;
+mod plugin;
+
+use engine::*;
+pub use plugin::*;
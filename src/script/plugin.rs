@@ -0,0 +1,372 @@
+use std::{
+    cmp::Reverse,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use fxhash::FxHashMap;
+use tokio::runtime::Handle;
+
+use super::*;
+use crate::*;
+
+/// Extension of script files in the config dir.
+const EXTENSION: &str = "rhai";
+
+/// Limits of scripts and how they are reloaded.
+#[derive(Debug, Clone)]
+pub struct ScriptOptions {
+    time_limit: Duration,
+    reload_interval: Option<Duration>,
+}
+
+impl Default for ScriptOptions {
+    /// Each run of a script is limited to 1 second, and the config dir is checked for changed
+    /// scripts every 2 seconds.
+    fn default() -> Self {
+        Self {
+            time_limit: Duration::from_secs(1),
+            reload_interval: Some(Duration::from_secs(2)),
+        }
+    }
+}
+
+impl ScriptOptions {
+    /// Sets the time limit of each run of a script, a script may declare a lower one by calling
+    /// `time_limit(millis)`.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Sets how often the config dir is checked for changed scripts, `None` disables reloading.
+    pub fn reload_interval(mut self, interval: Option<Duration>) -> Self {
+        self.reload_interval = interval;
+        self
+    }
+}
+
+/// Scripts of the plugin, shared with its reloading task.
+struct Scripts {
+    dir: PathBuf,
+    time_limit: Duration,
+    context: SharedPContext,
+    loaded: RwLock<Vec<Arc<Script>>>,
+    /// Modified time of files which failed to load, so they are not retried until changed.
+    failed: Mutex<FxHashMap<PathBuf, SystemTime>>,
+}
+
+/// Script files with their modified time, sorted by path.
+fn scan(dir: &Path) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == EXTENSION) && path.is_file() {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            files.push((path, modified));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+impl Scripts {
+    /// Loads new and changed scripts of the dir, blocking the current thread.
+    ///
+    /// A script failing to load is logged, and its previous version is kept.
+    fn reload(&self) {
+        let logger = self.context.logger();
+        let files = match scan(&self.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                let dir = self.dir.display();
+                log::error!(logger: logger, "failed to scan scripts in {dir}: {e}");
+                return;
+            }
+        };
+
+        let previous = self.loaded.read().unwrap().clone();
+        let mut failed = self.failed.lock().unwrap();
+        let mut loaded = Vec::with_capacity(files.len());
+        for (path, modified) in files {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let old = previous.iter().find(|script| script.name == name);
+            let unchanged = old.is_some_and(|script| script.modified == modified)
+                || failed.get(&path) == Some(&modified);
+            if unchanged {
+                loaded.extend(old.cloned());
+                continue;
+            }
+
+            match Script::load(&path, modified, self.time_limit, logger) {
+                Ok(script) => {
+                    log::info!(logger: logger, "loaded script `{name}`");
+                    failed.remove(&path);
+                    loaded.push(Arc::new(script));
+                }
+                Err(e) => {
+                    log::error!(logger: logger, "failed to load script `{name}`: {e}");
+                    failed.insert(path.clone(), modified);
+                    loaded.extend(old.cloned());
+                }
+            }
+        }
+        for script in &previous {
+            if !loaded.iter().any(|loaded| loaded.name == script.name) {
+                log::info!(logger: logger, "unloaded script `{}`", script.name);
+            }
+        }
+        *self.loaded.write().unwrap() = loaded;
+    }
+}
+
+/// Plugin running the [Rhai](https://rhai.rs) scripts in its config dir, files ending with
+/// `.rhai`.
+///
+/// Top-level statements of a script run once when it loads, and declare what it handles:
+///
+/// ```rhai
+/// subscribe("message", "group", "high");  // event type, detail type or `()`, priority
+/// time_limit(500);                        // lowers the time limit, in milliseconds
+///
+/// fn on_event(event) {
+///     if message_text(event) == "ping" {
+///         reply("pong");
+///         return true;  // intercepts the event
+///     }
+/// }
+/// ```
+///
+/// `on_event` gets the event as a map of its OneBot 12 fields, and may call `reply(message)`,
+/// `send_action(action, params)` on the app of the event, and `call_api(plugin_id, endpoint,
+/// payload)` of other plugins. `print` and `debug` go to the plugin's logger. Scripts run by
+/// priority of their subscriptions matching the event, until one intercepts it.
+///
+/// Changed scripts are reloaded while the plugin runs. Subscriptions of a reloaded script are
+/// checked right away, but the host only dispatches events of the subscriptions it got from
+/// [`subscribe_events`](CarolinaPlugin::subscribe_events).
+pub struct ScriptPlugin {
+    info: PluginInfo,
+    options: ScriptOptions,
+    scripts: OnceLock<Arc<Scripts>>,
+}
+
+impl ScriptPlugin {
+    pub fn new(info: PluginInfo, options: ScriptOptions) -> Self {
+        Self {
+            info,
+            options,
+            scripts: OnceLock::new(),
+        }
+    }
+
+    fn scripts(&self) -> StdResult<&Arc<Scripts>> {
+        Ok(self.scripts.get().ok_or("plugin is not initialized")?)
+    }
+}
+
+impl CarolinaPlugin for ScriptPlugin {
+    fn info(&self) -> PluginInfo {
+        self.info.clone()
+    }
+
+    async fn init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        let scripts = Arc::new(Scripts {
+            dir: context.get_config_dir()?,
+            time_limit: self.options.time_limit,
+            context: context.into_shared(),
+            loaded: Default::default(),
+            failed: Default::default(),
+        });
+        let load = scripts.clone();
+        tokio::task::spawn_blocking(move || load.reload()).await?;
+
+        if let Some(interval) = self.options.reload_interval {
+            // The task is kept by the host, so it must not keep the scripts alive
+            let weak = Arc::downgrade(&scripts);
+            let reload = move || {
+                let scripts = weak.upgrade();
+                async move {
+                    if let Some(scripts) = scripts {
+                        let _ = tokio::task::spawn_blocking(move || scripts.reload()).await;
+                    }
+                }
+            };
+            scripts
+                .context
+                .schedule(Schedule::interval(interval), reload);
+        }
+        let _ = self.scripts.set(scripts);
+        Ok(())
+    }
+
+    async fn subscribe_events(&mut self) -> Vec<Subscribe> {
+        let Some(scripts) = self.scripts.get() else {
+            return vec![];
+        };
+        let loaded = scripts.loaded.read().unwrap();
+        loaded
+            .iter()
+            .filter(|script| script.handles_events())
+            .flat_map(|script| script.subscribes.iter().cloned())
+            .collect()
+    }
+
+    async fn handle_event<EC>(&self, event: SharedEvent, context: EC) -> StdResult<EventState>
+    where
+        EC: EventContextTrait + Send + 'static,
+    {
+        let scripts = self.scripts()?;
+        let (app, app_rid) = context.into_inner();
+        let event = Arc::new(event_json(&event));
+
+        let mut matched: Vec<_> = scripts
+            .loaded
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|script| script.handles_events())
+            .filter_map(|script| {
+                let priority = script
+                    .subscribes
+                    .iter()
+                    .filter(|subscribe| subscribe.matches(app_rid, &event))
+                    .map(|subscribe| subscribe.priority)
+                    .max()?;
+                Some((priority, script.clone()))
+            })
+            .collect();
+        matched.sort_by_key(|(priority, _)| Reverse(*priority));
+
+        let app = Arc::new(app);
+        let logger = scripts.context.logger();
+        for (_, script) in matched {
+            let env = EventEnv {
+                app: app.clone(),
+                context: scripts.context.clone(),
+                handle: Handle::current(),
+            };
+            let name = script.name.clone();
+            let event = event.clone();
+            match tokio::task::spawn_blocking(move || script.on_event(&event, env)).await {
+                Ok(Ok(EventState::Intercept)) => return Ok(EventState::Intercept),
+                Ok(Ok(EventState::Pass)) => {}
+                Ok(Err(e)) => {
+                    log::error!(logger: logger, "script `{name}` failed to handle event: {e}")
+                }
+                Err(e) => log::error!(logger: logger, "script `{name}` panicked: {e}"),
+            }
+        }
+        Ok(EventState::Pass)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, time::UNIX_EPOCH};
+
+    use onebot_connect_interface::app::{AppDyn, MessageSource, OBAppProvider};
+
+    use super::*;
+
+    /// Host with only the config dir of the plugin.
+    struct TestGlobal {
+        dir: PathBuf,
+    }
+
+    impl GlobalContext for TestGlobal {
+        fn get_shared_app(&self, _id: AppRid) -> Option<Box<dyn AppDyn>> {
+            None
+        }
+
+        fn get_plugin_rid(&self, _id: &str) -> Option<PluginRid> {
+            None
+        }
+
+        fn get_plugin_id(&self, _rid: impl Into<PluginRid>) -> Option<String> {
+            None
+        }
+
+        async fn call_plugin_api(
+            &self,
+            _src: PluginRid,
+            target: PluginRid,
+            _call: APICall,
+        ) -> APIResult {
+            Err(APIError::PluginNotFound(target))
+        }
+
+        fn register_connect<F, FR, P, S>(&self, _rid: PluginRid, _provider: P, _source: S, _: F)
+        where
+            P: OBAppProvider<Output: 'static> + 'static,
+            S: MessageSource + 'static,
+            F: FnOnce() -> FR + Send + 'static,
+            FR: Future<Output = StdResult<()>> + Send + 'static,
+        {
+        }
+
+        fn get_config_dir(&self, _rid: Option<PluginRid>) -> StdResult<PathBuf> {
+            Ok(self.dir.clone())
+        }
+
+        fn get_data_dir(&self, _rid: Option<PluginRid>) -> StdResult<PathBuf> {
+            Ok(self.dir.clone())
+        }
+    }
+
+    fn write_script(path: &Path, source: &str, modified: SystemTime) {
+        std::fs::write(path, source).unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn reload_keeps_old_version_on_failure() {
+        let dir = std::env::temp_dir().join(format!(
+            "carolina-script-reload-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("echo.rhai");
+        // Whole seconds, which any file system keeps as written
+        let created = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        write_script(&path, "subscribe(\"message\");", created);
+
+        let global = TestGlobal { dir: dir.clone() };
+        let scripts = Scripts {
+            dir,
+            time_limit: Duration::from_secs(1),
+            context: PluginContext::new(PluginRid::new(1), global, None).into_shared(),
+            loaded: Default::default(),
+            failed: Default::default(),
+        };
+        let subscribed = |scripts: &Scripts| {
+            let loaded = scripts.loaded.read().unwrap();
+            let [script] = &loaded[..] else {
+                panic!("expected 1 script, got {}", loaded.len());
+            };
+            (script.modified, script.subscribes[0].event_type.clone())
+        };
+        scripts.reload();
+        assert_eq!(subscribed(&scripts), (created, "message".to_owned()));
+
+        let broken = created + Duration::from_secs(1);
+        write_script(&path, "subscribe(\"notice\"", broken);
+        scripts.reload();
+        assert_eq!(subscribed(&scripts), (created, "message".to_owned()));
+        assert_eq!(scripts.failed.lock().unwrap().get(&path), Some(&broken));
+
+        let fixed = created + Duration::from_secs(2);
+        write_script(&path, "subscribe(\"notice\");", fixed);
+        scripts.reload();
+        assert_eq!(subscribed(&scripts), (fixed, "notice".to_owned()));
+        assert!(scripts.failed.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{process::*, PluginRid};
+
+/// Operation of the host, which a sandboxed plugin must be granted to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Calling apis of other plugins.
+    CallPluginApi,
+    /// Reading and writing files in its config dir.
+    ConfigDir,
+    /// Reading and writing files in its data dir.
+    DataDir,
+    /// Sending actions to apps, including replies to events.
+    AppAction,
+    /// Waiting for events of sessions.
+    Session,
+    /// Switching other plugins, changing their scopes and getting their dirs.
+    ManagePlugins,
+}
+
+impl Capability {
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Capabilities granted to a sandboxed plugin.
+///
+/// Logging, looking up plugin ids and managing the plugin itself are always allowed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    pub const fn all() -> Self {
+        Self(u8::MAX)
+    }
+
+    pub const fn allow(self, capability: Capability) -> Self {
+        Self(self.0 | capability.bit())
+    }
+
+    pub const fn allows(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Checks the call of plugin `rid`, returns the missing capability if it is denied.
+    pub fn check(self, rid: PluginRid, call: &GuestCall) -> Result<(), Capability> {
+        match required_capability(rid, call) {
+            Some(capability) if !self.allows(capability) => Err(capability),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::none(), Self::allow)
+    }
+}
+
+/// Capability needed by the call of plugin `rid`, `None` if it is always allowed.
+pub fn required_capability(rid: PluginRid, call: &GuestCall) -> Option<Capability> {
+    let manage = |target: PluginRid| (target != rid).then_some(Capability::ManagePlugins);
+    match call {
+        GuestCall::Host(call) => match call {
+            HostCall::GetPluginRid { .. } | HostCall::GetPluginId { .. } => None,
+            HostCall::CallPluginApi { .. } => Some(Capability::CallPluginApi),
+            HostCall::IsPluginEnabled { rid }
+            | HostCall::SetPluginEnabled { rid, .. }
+            | HostCall::GetPluginScope { rid }
+            | HostCall::SetPluginScope { rid, .. } => manage(*rid),
+            HostCall::WaitSession { .. } => Some(Capability::Session),
+            HostCall::GetConfigDir { rid: Some(target) } if *target == rid => {
+                Some(Capability::ConfigDir)
+            }
+            HostCall::GetDataDir { rid: Some(target) } if *target == rid => {
+                Some(Capability::DataDir)
+            }
+            HostCall::GetConfigDir { .. } | HostCall::GetDataDir { .. } => {
+                Some(Capability::ManagePlugins)
+            }
+            HostCall::AppAction { .. } => Some(Capability::AppAction),
+        },
+        GuestCall::Sandbox(call) => match call {
+            SandboxCall::ReadFile { dir, .. } | SandboxCall::WriteFile { dir, .. } => match dir {
+                PluginDir::Config => Some(Capability::ConfigDir),
+                PluginDir::Data => Some(Capability::DataDir),
+            },
+            SandboxCall::Log { .. } => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{AppRid, Endpoint};
+
+    const OWN: PluginRid = PluginRid::new(1);
+    const OTHER: PluginRid = PluginRid::new(2);
+
+    fn required(call: HostCall) -> Option<Capability> {
+        required_capability(OWN, &GuestCall::Host(call))
+    }
+
+    #[test]
+    fn host_call_capabilities() {
+        let cases = [
+            (HostCall::GetPluginRid { id: "x".into() }, None),
+            (HostCall::GetPluginId { rid: OTHER }, None),
+            (
+                HostCall::CallPluginApi {
+                    target: OTHER,
+                    endpoint: Endpoint::new(1),
+                    payload: Value::Null,
+                },
+                Some(Capability::CallPluginApi),
+            ),
+            (HostCall::IsPluginEnabled { rid: OWN }, None),
+            (
+                HostCall::SetPluginEnabled {
+                    rid: OWN,
+                    enabled: false,
+                },
+                None,
+            ),
+            (
+                HostCall::IsPluginEnabled { rid: OTHER },
+                Some(Capability::ManagePlugins),
+            ),
+            (
+                HostCall::GetPluginScope { rid: OTHER },
+                Some(Capability::ManagePlugins),
+            ),
+            (
+                HostCall::GetConfigDir { rid: Some(OWN) },
+                Some(Capability::ConfigDir),
+            ),
+            (
+                HostCall::GetConfigDir { rid: Some(OTHER) },
+                Some(Capability::ManagePlugins),
+            ),
+            (
+                HostCall::GetConfigDir { rid: None },
+                Some(Capability::ManagePlugins),
+            ),
+            (
+                HostCall::GetDataDir { rid: Some(OWN) },
+                Some(Capability::DataDir),
+            ),
+            (
+                HostCall::GetDataDir { rid: Some(OTHER) },
+                Some(Capability::ManagePlugins),
+            ),
+            (
+                HostCall::AppAction {
+                    app: AppRid::new(1),
+                    action: "send_message".into(),
+                    params: Value::Null,
+                },
+                Some(Capability::AppAction),
+            ),
+        ];
+        for (call, expected) in cases {
+            let description = format!("{call:?}");
+            assert_eq!(required(call), expected, "{description}");
+        }
+    }
+
+    #[test]
+    fn sandbox_call_capabilities() {
+        let sandbox = |call| required_capability(OWN, &GuestCall::Sandbox(call));
+        let read = SandboxCall::ReadFile {
+            dir: PluginDir::Config,
+            path: "a.toml".into(),
+        };
+        assert_eq!(sandbox(read), Some(Capability::ConfigDir));
+        let write = SandboxCall::WriteFile {
+            dir: PluginDir::Data,
+            path: "a.json".into(),
+            content: String::new(),
+        };
+        assert_eq!(sandbox(write), Some(Capability::DataDir));
+        let log = SandboxCall::Log {
+            level: "info".into(),
+            message: String::new(),
+        };
+        assert_eq!(sandbox(log), None);
+    }
+
+    #[test]
+    fn check_reports_missing_capability() {
+        let config_dir = |rid| GuestCall::Host(HostCall::GetConfigDir { rid: Some(rid) });
+        let granted: Capabilities = [Capability::ConfigDir].into_iter().collect();
+        assert_eq!(granted.check(OWN, &config_dir(OWN)), Ok(()));
+        assert_eq!(
+            granted.check(OWN, &config_dir(OTHER)),
+            Err(Capability::ManagePlugins)
+        );
+        assert_eq!(Capabilities::all().check(OWN, &config_dir(OTHER)), Ok(()));
+        assert_eq!(
+            Capabilities::none().check(OWN, &config_dir(OWN)),
+            Err(Capability::ConfigDir)
+        );
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    future::Future,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use serde_json::Value;
+use tokio::sync::Mutex;
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc,
+};
+
+use super::*;
+use crate::{process::*, *};
+
+/// Fuel consumed between yields of a guest to the async runtime.
+const YIELD_INTERVAL: u64 = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WasmError {
+    #[error("wasm error: {0}")]
+    Wasm(wasmtime::Error),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid message: {0}")]
+    Codec(#[from] serde_json::Error),
+    #[error("guest exports no `{0}`")]
+    MissingExport(&'static str),
+    #[error("guest trapped in a previous call and can not be called again")]
+    Poisoned,
+    #[error("guest error: {0}")]
+    Remote(String),
+}
+
+impl From<wasmtime::Error> for WasmError {
+    fn from(e: wasmtime::Error) -> Self {
+        Self::Wasm(e)
+    }
+}
+
+impl From<WasmError> for APIError {
+    fn from(e: WasmError) -> Self {
+        match e {
+            WasmError::Remote(e) => Self::Error(e),
+            e => Self::other(e),
+        }
+    }
+}
+
+/// Compiles and links guest modules, shared by all wasm plugins of the host.
+#[derive(Clone)]
+pub struct WasmEngine {
+    engine: Engine,
+    linker: Arc<Linker<GuestState>>,
+}
+
+impl WasmEngine {
+    pub fn new() -> Result<Self, WasmError> {
+        let mut config = Config::new();
+        config.async_support(true).consume_fuel(true);
+        let engine = Engine::new(&config)?;
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap_async("carolina", "host_call", host_call)?;
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+        })
+    }
+}
+
+/// Limits and capabilities of a single wasm plugin.
+#[derive(Debug, Clone)]
+pub struct WasmOptions {
+    capabilities: Capabilities,
+    fuel_per_call: u64,
+    max_memory: usize,
+    max_message: usize,
+}
+
+impl WasmOptions {
+    /// Options granting `capabilities`, each call may consume 10^9 fuel, the guest may grow
+    /// its memory up to 64 MiB, and send messages up to 16 MiB.
+    pub fn new(capabilities: Capabilities) -> Self {
+        Self {
+            capabilities,
+            fuel_per_call: 1_000_000_000,
+            max_memory: 64 << 20,
+            max_message: 16 << 20,
+        }
+    }
+
+    /// Sets the fuel of each call, a call running out of it traps.
+    ///
+    /// Calls to the host made inside the call consume no fuel.
+    pub fn fuel_per_call(mut self, fuel: u64) -> Self {
+        self.fuel_per_call = fuel;
+        self
+    }
+
+    /// Sets the max size of the guest memory in bytes.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Sets the max size of a message from the guest in bytes, a larger one traps the guest.
+    pub fn max_message(mut self, bytes: usize) -> Self {
+        self.max_message = bytes;
+        self
+    }
+}
+
+/// State shared by the plugin and calls from its guest.
+struct Sandbox {
+    id: OnceLock<String>,
+    capabilities: Capabilities,
+    context: OnceLock<SharedPContext>,
+}
+
+struct GuestState {
+    sandbox: Arc<Sandbox>,
+    limits: StoreLimits,
+    max_message: usize,
+}
+
+/// Instance of the guest module, calls into it are serialized.
+struct Guest {
+    store: Store<GuestState>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    free: TypedFunc<(u32, u32), ()>,
+    call: TypedFunc<(u32, u32), u64>,
+    fuel_per_call: u64,
+    poisoned: bool,
+}
+
+fn pack(ptr: u32, len: u32) -> u64 {
+    (ptr as u64) << 32 | len as u64
+}
+
+fn unpack(packed: u64) -> (u32, u32) {
+    ((packed >> 32) as u32, packed as u32)
+}
+
+fn message_len(message: &[u8]) -> wasmtime::Result<u32> {
+    u32::try_from(message.len()).map_err(|_| wasmtime::Error::msg("message is too large"))
+}
+
+/// Copies a message out of the guest memory, checked before allocating as the guest may pass
+/// any `ptr` and `len`.
+fn read_message(memory: &[u8], ptr: u32, len: u32, max: usize) -> wasmtime::Result<Vec<u8>> {
+    let len = len as usize;
+    if len > max {
+        return Err(wasmtime::Error::msg(format!(
+            "message of {len} bytes exceeds the limit of {max} bytes"
+        )));
+    }
+    let start = ptr as usize;
+    start
+        .checked_add(len)
+        .and_then(|end| memory.get(start..end))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("message is out of the guest memory"))
+}
+
+impl Guest {
+    async fn call(&mut self, call: &PluginCall) -> Result<Value, WasmError> {
+        if self.poisoned {
+            return Err(WasmError::Poisoned);
+        }
+        let input = serde_json::to_vec(call)?;
+        // The instance may be left in any state by a trap
+        let output = self.call_raw(&input).await.inspect_err(|_| {
+            self.poisoned = true;
+        })?;
+        match serde_json::from_slice(&output)? {
+            Reply::Ok(value) => Ok(value),
+            Reply::Err(e) => Err(WasmError::Remote(e)),
+        }
+    }
+
+    async fn call_raw(&mut self, input: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        self.store.set_fuel(self.fuel_per_call)?;
+        let len = message_len(input)?;
+        let ptr = self.alloc.call_async(&mut self.store, len).await?;
+        self.memory.write(&mut self.store, ptr as usize, input)?;
+
+        // The guest frees the input, and the host frees the output
+        let packed = self.call.call_async(&mut self.store, (ptr, len)).await?;
+        let (ptr, len) = unpack(packed);
+        let max = self.store.data().max_message;
+        let output = read_message(self.memory.data(&self.store), ptr, len, max)?;
+        self.free.call_async(&mut self.store, (ptr, len)).await?;
+        Ok(output)
+    }
+}
+
+/// Plugin compiled to a wasm module, running sandboxed in the host process.
+///
+/// The guest reaches the host only through calls checked against its [`Capabilities`], and each
+/// call into it is limited by fuel and memory, see [`WasmOptions`]. A trapped guest fails all
+/// later calls with [`WasmError::Poisoned`].
+///
+/// Calls into the guest are serialized, so a guest calling its own api deadlocks.
+pub struct WasmPlugin {
+    info: PluginInfo,
+    guest: Mutex<Guest>,
+    sandbox: Arc<Sandbox>,
+}
+
+impl WasmPlugin {
+    /// Instantiates the module and fetches its info.
+    pub async fn load(
+        engine: &WasmEngine,
+        module: impl AsRef<[u8]>,
+        options: WasmOptions,
+    ) -> Result<Self, WasmError> {
+        let module = Module::new(&engine.engine, module)?;
+        let sandbox = Arc::new(Sandbox {
+            id: OnceLock::new(),
+            capabilities: options.capabilities,
+            context: OnceLock::new(),
+        });
+        let state = GuestState {
+            sandbox: sandbox.clone(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(options.max_memory)
+                .build(),
+            max_message: options.max_message,
+        };
+
+        let mut store = Store::new(&engine.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(options.fuel_per_call)?;
+        store.fuel_async_yield_interval(Some(YIELD_INTERVAL))?;
+        let instance = engine.linker.instantiate_async(&mut store, &module).await?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or(WasmError::MissingExport("memory"))?;
+        let alloc = instance.get_typed_func(&mut store, "carolina_alloc")?;
+        let free = instance.get_typed_func(&mut store, "carolina_free")?;
+        let call = instance.get_typed_func(&mut store, "carolina_call")?;
+        let mut guest = Guest {
+            store,
+            memory,
+            alloc,
+            free,
+            call,
+            fuel_per_call: options.fuel_per_call,
+            poisoned: false,
+        };
+
+        let info: PluginInfo = serde_json::from_value(
+            guest
+                .call(&PluginCall::Info {
+                    protocol: PROTOCOL_VERSION,
+                })
+                .await?,
+        )?;
+        let _ = sandbox.id.set(info.id.clone());
+        Ok(Self {
+            info,
+            guest: Mutex::new(guest),
+            sandbox,
+        })
+    }
+
+    /// Reads the module from file, and loads it.
+    pub async fn load_file(
+        engine: &WasmEngine,
+        path: impl AsRef<Path>,
+        options: WasmOptions,
+    ) -> Result<Self, WasmError> {
+        let module = tokio::fs::read(path).await?;
+        Self::load(engine, module, options).await
+    }
+
+    async fn call(&self, call: PluginCall) -> Result<Value, WasmError> {
+        self.guest.lock().await.call(&call).await
+    }
+
+    async fn call_unit(&self, call: PluginCall) -> StdResult<()> {
+        self.call(call).await?;
+        Ok(())
+    }
+}
+
+/// Import `carolina.host_call`, taking a [`GuestCall`] and returning the [`Reply`] in a buffer
+/// allocated by `carolina_alloc`, which the guest frees.
+fn host_call(
+    mut caller: Caller<'_, GuestState>,
+    (ptr, len): (u32, u32),
+) -> Box<dyn Future<Output = wasmtime::Result<u64>> + Send + '_> {
+    Box::new(async move {
+        let memory = caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| wasmtime::Error::msg("guest exports no memory"))?;
+        let max = caller.data().max_message;
+        let input = read_message(memory.data(&caller), ptr, len, max)?;
+
+        let sandbox = caller.data().sandbox.clone();
+        let reply = match serde_json::from_slice(&input) {
+            Ok(call) => Reply::from(sandbox.serve(call).await),
+            Err(e) => Reply::Err(format!("invalid call: {e}")),
+        };
+        let output = serde_json::to_vec(&reply)?;
+
+        let len = message_len(&output)?;
+        let alloc = caller
+            .get_export("carolina_alloc")
+            .and_then(Extern::into_func)
+            .ok_or_else(|| wasmtime::Error::msg("guest exports no carolina_alloc"))?
+            .typed::<u32, u32>(&caller)?;
+        let ptr = alloc.call_async(&mut caller, len).await?;
+        memory.write(&mut caller, ptr as usize, &output)?;
+        Ok(pack(ptr, len))
+    })
+}
+
+/// Joins the relative `path` to `root`, rejecting paths which may leave it.
+fn sandboxed_path(root: PathBuf, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    let escapes = relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)));
+    if path.is_empty() || escapes {
+        return Err(format!("path `{path}` is not relative to the plugin's dir"));
+    }
+    Ok(root.join(relative))
+}
+
+impl Sandbox {
+    async fn serve(&self, call: GuestCall) -> Result<Value, String> {
+        if let GuestCall::Sandbox(SandboxCall::Log { level, message }) = call {
+            return self.log(&level, &message);
+        }
+
+        let context = self.context.get().ok_or("plugin is not initialized")?;
+        if let Err(capability) = self.capabilities.check(context.rid(), &call) {
+            return Err(format!("capability {capability:?} is not granted"));
+        }
+        match call {
+            GuestCall::Host(call) => handle_host_call(context, call).await,
+            GuestCall::Sandbox(SandboxCall::ReadFile { dir, path }) => {
+                let path = sandboxed_path(plugin_dir(context, dir)?, &path)?;
+                match tokio::fs::read_to_string(path).await {
+                    Ok(content) => Ok(Value::String(content)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Value::Null),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            GuestCall::Sandbox(SandboxCall::WriteFile { dir, path, content }) => {
+                let path = sandboxed_path(plugin_dir(context, dir)?, &path)?;
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                tokio::fs::write(path, content)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(Value::Null)
+            }
+            GuestCall::Sandbox(SandboxCall::Log { .. }) => unreachable!(),
+        }
+    }
+
+    /// Logs with the plugin's logger, or the global one before the plugin is initialized.
+    fn log(&self, level: &str, message: &str) -> Result<Value, String> {
+        let level: log::Level = level
+            .parse()
+            .map_err(|_| format!("invalid log level `{level}`"))?;
+        match self.context.get() {
+            Some(context) => log::log!(logger: context.logger(), level, "{message}"),
+            None => {
+                let id = self.id.get().map_or("wasm plugin", String::as_str);
+                log::log!(level, "[{id}] {message}");
+            }
+        }
+        Ok(Value::Null)
+    }
+}
+
+fn plugin_dir(context: &SharedPContext, dir: PluginDir) -> Result<PathBuf, String> {
+    let dir = match dir {
+        PluginDir::Config => context.get_config_dir(),
+        PluginDir::Data => context.get_data_dir(),
+    };
+    dir.map_err(|e| e.to_string())
+}
+
+impl CarolinaPlugin for WasmPlugin {
+    fn info(&self) -> PluginInfo {
+        self.info.clone()
+    }
+
+    async fn init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        let rid = context.rid();
+        let _ = self.sandbox.context.set(context.into_shared());
+        self.call_unit(PluginCall::Init { rid }).await
+    }
+
+    #[allow(unused)]
+    async fn post_init<G: GlobalContext>(&mut self, context: PluginContext<G>) -> StdResult<()> {
+        self.call_unit(PluginCall::PostInit).await
+    }
+
+    async fn on_enable(&mut self) -> StdResult<()> {
+        self.call_unit(PluginCall::OnEnable).await
+    }
+
+    async fn on_disable(&mut self) -> StdResult<()> {
+        self.call_unit(PluginCall::OnDisable).await
+    }
+
+    async fn subscribe_events(&mut self) -> Vec<Subscribe> {
+        let subscribes = self
+            .call(PluginCall::SubscribeEvents)
+            .await
+            .and_then(|value| Ok(serde_json::from_value::<Vec<SubscribeWire>>(value)?));
+        match subscribes {
+            Ok(subscribes) => subscribes.into_iter().map(Subscribe::from).collect(),
+            Err(e) => {
+                log::error!("failed to get subscriptions of `{}`: {e}", self.info.id);
+                vec![]
+            }
+        }
+    }
+
+    async fn handle_event<EC>(&self, event: SharedEvent, context: EC) -> StdResult<EventState>
+    where
+        EC: EventContextTrait + Send + 'static,
+    {
+        let call = PluginCall::HandleEvent {
+            app: context.app_marker(),
+            event: event_json(&event),
+        };
+        Ok(serde_json::from_value(self.call(call).await?)?)
+    }
+
+    async fn handle_api_call(&self, src: PluginRid, call: APICall) -> APIResult {
+        let call = PluginCall::HandleApiCall {
+            src,
+            endpoint: call.endpoint,
+            payload: call.payload,
+        };
+        api_result(self.call(call).await)
+    }
+
+    async fn deinit(self) -> StdResult<()> {
+        self.call_unit(PluginCall::Deinit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_message_checks_bounds() {
+        let memory = [1, 2, 3, 4];
+        assert_eq!(read_message(&memory, 1, 2, 16).unwrap(), [2, 3]);
+        assert_eq!(read_message(&memory, 4, 0, 16).unwrap(), [0u8; 0]);
+        assert!(read_message(&memory, 3, 2, 16).is_err());
+        assert!(read_message(&memory, 5, 0, 16).is_err());
+        assert!(read_message(&memory, u32::MAX, u32::MAX, usize::MAX).is_err());
+        // Checked before the memory, so a huge length is not allocated
+        assert!(read_message(&memory, 0, 4, 3).is_err());
+        assert!(read_message(&memory, 0, u32::MAX, 16).is_err());
+    }
+
+    #[test]
+    fn sandboxed_path_stays_in_root() {
+        let root = PathBuf::from("plugins/echo");
+        assert_eq!(
+            sandboxed_path(root.clone(), "data/a.json").unwrap(),
+            root.join("data/a.json")
+        );
+        for path in ["", "..", "../a", "data/../../a", "./a", "/etc/passwd"] {
+            assert!(sandboxed_path(root.clone(), path).is_err(), "{path}");
+        }
+    }
+}
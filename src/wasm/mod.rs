@@ -0,0 +1,5 @@
+mod capability;
+mod host;
+
+pub use capability::*;
+pub use host::*;
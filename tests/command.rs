@@ -0,0 +1,8 @@
+#![cfg(feature = "plugin")]
+
+#[test]
+fn command() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/command/pass/*.rs");
+    t.compile_fail("tests/ui/command/fail/*.rs");
+}
@@ -0,0 +1,10 @@
+use carolina_api::plugin::{Command, Rest};
+
+#[derive(Command)]
+struct Echo {
+    #[arg(rest)]
+    text: Rest,
+    times: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use carolina_api::plugin::{Command, Rest};
+
+#[derive(Command)]
+struct Echo {
+    text: Rest,
+    times: u32,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use carolina_api::plugin::Command;
+
+#[derive(Command)]
+struct Move {
+    to: (i64, i64),
+}
+
+fn main() {}
@@ -0,0 +1,114 @@
+use carolina_api::plugin::{ArgKind, Command, CommandError, Mention, ParsedArgs, Rest, Token};
+
+type Switch = bool;
+
+/// Bans a user.
+#[derive(Command)]
+#[command(alias = "b")]
+struct Ban {
+    who: Mention,
+    minutes: Option<u32>,
+    #[arg(rest)]
+    reason: Option<Rest>,
+    silent: bool,
+    #[arg(flag)]
+    notify: Switch,
+    #[arg(name = "dry-run")]
+    dry_run: std::primitive::bool,
+}
+
+#[derive(Command)]
+enum Admin {
+    Ping,
+    #[command(name = "say")]
+    Echo {
+        #[arg(rest)]
+        text: String,
+    },
+}
+
+fn text(s: &str) -> Token {
+    Token::Text(s.to_owned())
+}
+
+fn space() -> Token {
+    Token::Space(" ".to_owned())
+}
+
+fn main() {
+    let specs = Ban::specs();
+    let spec = &specs[0];
+    assert_eq!(spec.name, "ban");
+    assert_eq!(spec.aliases, ["b"]);
+    assert_eq!(spec.description.as_deref(), Some("Bans a user."));
+    let args: Vec<_> = spec
+        .args
+        .iter()
+        .map(|arg| (arg.name.as_str(), arg.kind, arg.optional))
+        .collect();
+    assert_eq!(
+        args,
+        [
+            ("who", ArgKind::Mention, false),
+            ("minutes", ArgKind::Int, true),
+            ("reason", ArgKind::Rest, true),
+            ("silent", ArgKind::Flag, false),
+            ("notify", ArgKind::Flag, false),
+            ("dry-run", ArgKind::Flag, false),
+        ]
+    );
+
+    let tokens = vec![
+        Token::Mention("u".to_owned()),
+        space(),
+        text("--silent"),
+        space(),
+        text("5"),
+        space(),
+        text("--dry-run"),
+        space(),
+        text("spam"),
+        space(),
+        Token::Quoted {
+            text: "a b".to_owned(),
+            raw: "\"a b\"".to_owned(),
+        },
+    ];
+    let ban = Ban::from_args("ban", &spec.parse_args(tokens).unwrap()).unwrap();
+    assert_eq!(ban.who, Mention("u".to_owned()));
+    assert_eq!(ban.minutes, Some(5));
+    assert_eq!(ban.reason.as_ref().map(Rest::text), Some("spam \"a b\""));
+    assert!(ban.silent);
+    assert!(!ban.notify);
+    assert!(ban.dry_run);
+
+    let args = spec
+        .parse_args(vec![Token::Mention("u".to_owned())])
+        .unwrap();
+    let ban = Ban::from_args("ban", &args).unwrap();
+    assert_eq!(ban.minutes, None);
+    assert!(ban.reason.is_none());
+    assert!(matches!(
+        Ban::from_args("ban", &ParsedArgs::default()),
+        Err(CommandError::MissingArg(name)) if name == "who"
+    ));
+
+    let names: Vec<_> = Admin::specs().into_iter().map(|spec| spec.name).collect();
+    assert_eq!(names, ["ping", "say"]);
+    assert!(matches!(
+        Admin::from_args("ping", &ParsedArgs::default()),
+        Ok(Admin::Ping)
+    ));
+    let say = &Admin::specs()[1];
+    let args = say
+        .parse_args(vec![text("hi"), space(), text("there")])
+        .unwrap();
+    assert!(matches!(
+        Admin::from_args("say", &args),
+        Ok(Admin::Echo { text }) if text == "hi there"
+    ));
+    assert!(matches!(
+        Admin::from_args("kick", &args),
+        Err(CommandError::UnknownCommand(name)) if name == "kick"
+    ));
+}
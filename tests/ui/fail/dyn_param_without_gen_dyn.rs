@@ -0,0 +1,10 @@
+use carolina_api::plugin_api;
+
+#[plugin_api(dyn_param(C = String))]
+mod api {
+    pub trait Greeter {
+        fn greet<C: ToString>(&self, to: C) -> String;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+#![allow(unexpected_cfgs)]
+
+use carolina_api::plugin_api;
+
+#[plugin_api]
+mod api {
+    pub trait Greeter {
+        fn name(&self) -> String;
+    }
+}
+
+pub struct Plugin(String);
+
+impl Greeter for Plugin {
+    fn name(&self) -> String {
+        self.0.clone()
+    }
+}
+
+export_plugin!(Plugin);
+
+fn main() {}
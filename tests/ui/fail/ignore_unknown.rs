@@ -0,0 +1,10 @@
+use carolina_api::plugin_api;
+
+#[plugin_api(ignore(nope))]
+mod api {
+    pub trait Greeter {
+        fn name(&self) -> String;
+    }
+}
+
+fn main() {}
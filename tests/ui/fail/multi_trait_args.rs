@@ -0,0 +1,14 @@
+use carolina_api::plugin_api;
+
+#[plugin_api(dyn_t = CoreDyn)]
+mod api {
+    pub trait Core {
+        fn id(&self) -> u32;
+    }
+
+    pub trait Admin {
+        fn kick(&self, user: String) -> bool;
+    }
+}
+
+fn main() {}
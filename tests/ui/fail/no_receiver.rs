@@ -0,0 +1,10 @@
+use carolina_api::plugin_api;
+
+#[plugin_api]
+mod api {
+    pub trait Greeter {
+        fn create() -> Self;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use carolina_api::plugin_api;
+
+#[plugin_api]
+mod api {
+    use crate;
+
+    pub trait Greeter {
+        fn name(&self) -> String;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use carolina_api::plugin_api;
+
+pub struct Name;
+
+#[plugin_api]
+mod api {
+    use {super::Name, std::sync::Arc};
+
+    pub trait Greeter {
+        fn name(&self) -> Arc<Name>;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use carolina_api::plugin_api;
+
+pub struct Name;
+
+#[plugin_api]
+mod api {
+    use super::Name;
+
+    pub trait Greeter {
+        fn name(&self) -> Name;
+    }
+}
+
+fn main() {}
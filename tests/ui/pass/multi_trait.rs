@@ -0,0 +1,52 @@
+#![allow(unexpected_cfgs)]
+
+use carolina_api::plugin_api;
+
+extern crate self as multi_plugin;
+
+#[plugin_api]
+mod api {
+    #[plugin_api(gen_dyn, dyn_t = CoreDyn)]
+    pub trait Core: Send + Sync {
+        fn id(&self) -> u32;
+    }
+
+    #[plugin_api(gen_dyn, dyn_t = AdminDyn)]
+    pub trait Admin: Send + Sync {
+        fn kick(&self, user: String) -> bool;
+    }
+}
+
+#[derive(Default)]
+pub struct Plugin;
+
+impl Core for Plugin {
+    fn id(&self) -> u32 {
+        7
+    }
+}
+
+impl Admin for Plugin {
+    fn kick(&self, user: String) -> bool {
+        !user.is_empty()
+    }
+}
+
+export_core!(Plugin);
+export_admin!(Plugin);
+
+mod core_host {
+    define_dispatcher_core!(pub CoreDispatcher(multi_plugin));
+}
+
+mod admin_host {
+    define_dispatcher_admin!(pub AdminDispatcher(multi_plugin));
+}
+
+fn main() {
+    let core = core_host::CoreDispatcher::from(load_cmptime_core!(multi_plugin));
+    assert_eq!(Core::id(&core), 7);
+    let admin = admin_host::AdminDispatcher::from(load_cmptime_admin!(multi_plugin));
+    assert!(Admin::kick(&admin, "someone".into()));
+    assert_ne!(CORE_DYN_LOADER_FN_NAME, ADMIN_DYN_LOADER_FN_NAME);
+}
@@ -0,0 +1,66 @@
+#![allow(unexpected_cfgs)]
+
+use carolina_api::plugin_api;
+
+extern crate self as greeter_plugin;
+
+pub mod types {
+    pub struct Name(pub String);
+
+    pub mod nested {
+        pub struct Level(pub u8);
+    }
+}
+
+#[plugin_api(gen_dyn, dyn_t = GreeterDyn)]
+mod api {
+    use crate::types::{nested::*, Name as PluginName};
+    use std::{collections::HashMap, sync::Arc};
+    use {crate::types::nested::Level as Lv, ::std::string::String as Text};
+
+    pub trait Greeter: Send + Sync {
+        fn name(&self) -> PluginName;
+        fn level(&self) -> Level;
+        fn table(&self) -> HashMap<Text, Arc<Lv>>;
+    }
+}
+
+#[derive(Default)]
+pub struct Plugin;
+
+impl Greeter for Plugin {
+    fn name(&self) -> types::Name {
+        types::Name("greeter".into())
+    }
+
+    fn level(&self) -> types::nested::Level {
+        types::nested::Level(1)
+    }
+
+    fn table(&self) -> std::collections::HashMap<String, std::sync::Arc<types::nested::Level>> {
+        Default::default()
+    }
+}
+
+export_plugin!(Plugin);
+
+mod host {
+    define_dispatcher_greeter!(pub Dispatcher(greeter_plugin));
+}
+
+fn main() {
+    let plugin: Plugin = load_cmptime_greeter!(greeter_plugin);
+    let dispatcher = host::Dispatcher::from(plugin);
+    assert_eq!(Greeter::name(&dispatcher).0, "greeter");
+    assert_eq!(Greeter::level(&dispatcher).0, 1);
+
+    let boxed: Box<dyn GreeterDyn> = Box::new(Plugin);
+    let dispatcher = host::Dispatcher::from(boxed);
+    assert!(dispatcher.is_dynamic());
+
+    let linked: Box<dyn std::any::Any> = Box::new(Plugin);
+    let dispatcher = host::Dispatcher::try_from(linked).unwrap();
+    assert!(!dispatcher.is_dynamic());
+    let other: Box<dyn std::any::Any> = Box::new(1u8);
+    assert!(host::Dispatcher::try_from(other).is_err());
+}